@@ -1,48 +1,171 @@
 mod modules;
+use modules::buffer::{ask_swap, recover_swap, BufferList};
+use modules::cli::{self, Action};
 use modules::command;
-use modules::coordinate::Point;
+use modules::coordinate::ScreenPoint;
 use modules::file::FileBuffer;
-use modules::insert::proc_insert;
+use modules::hex::Hex;
 use modules::mode::{State, MODE};
 use modules::normal::Normal;
-use modules::search::Search;
+use modules::register::Registers;
 use modules::show::*;
 use modules::undo::Undo;
+use modules::watch::DiskChange;
 
 use crossterm::cursor::SetCursorStyle;
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
 use crossterm::terminal::size;
 
 use std::env;
+use std::ffi::OsStr;
+use std::io::{self, Read};
 use std::path::Path;
+use std::process;
+use std::time::Duration;
 
 fn main() {
-    let args = env::args();
-    let arg: Vec<String> = args.collect();
-    let path = Path::new(&arg[1]);
-    let mut buf = FileBuffer::new(path).expect("cannot open file");
+    let args = match cli::parse(env::args().skip(1)) {
+        Ok(Action::Edit(args)) => args,
+        Ok(Action::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("ve {}", env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\ntry 've --help' for more information", e);
+            process::exit(2);
+        }
+    };
+    // started as `view`, every buffer is read-only and paged through.
+    let view = env::args()
+        .next()
+        .map(|a| Path::new(&a).file_name() == Some(OsStr::new("view")))
+        .unwrap_or(false);
+    // files are read before the terminal is taken over, so errors go to stderr.
+    let mut files = vec![];
+    if args.stdin {
+        let mut bytes = vec![];
+        if let Err(e) = io::stdin().read_to_end(&mut bytes) {
+            eprintln!("ve: cannot read stdin: {}", e);
+            process::exit(1);
+        }
+        files.push(FileBuffer::scratch(&bytes));
+    }
+    for f in &args.files {
+        match FileBuffer::new(Path::new(f)) {
+            Ok(buf) => files.push(buf),
+            Err(e) => {
+                eprintln!("ve: {}: {}", f, e);
+                process::exit(1);
+            }
+        }
+    }
+    if files.is_empty() {
+        files.push(FileBuffer::scratch(&[]));
+    }
     let (col, row) = size().unwrap();
-    let mut display = Display::new(Point { col: col, row: row });
+    let mut display = Display::new(ScreenPoint { col, row });
     display.init_window();
     display.set_cursor_type(SetCursorStyle::SteadyBlock);
-    handle(&mut display, &mut buf);
+    let mut list: Option<BufferList> = None;
+    for mut buf in files {
+        let keep = if args.recover {
+            recover_swap(&mut display, &mut buf);
+            true
+        } else {
+            ask_swap(&mut display, &mut buf)
+        };
+        // [q]uit at the swap prompt leaves the file out.
+        if !keep {
+            continue;
+        }
+        if args.read_only {
+            buf.set_read_only(true);
+        }
+        buf.start_swap();
+        buf.start_watch();
+        match &mut list {
+            Some(l) => {
+                l.add(buf);
+            }
+            None => list = Some(BufferList::new(buf)),
+        }
+    }
+    if let Some(mut list) = list {
+        if view {
+            list.set_read_only();
+        }
+        handle(&mut display, &mut list, &args.commands, view);
+        list.close_all();
+    }
     display.close_terminal("".to_string());
 }
+/// the file was written by another program. read it again when the buffer has no
+/// edits and `autoread` is set, ask otherwise.
+fn check_disk(display: &mut Display, buf: &mut FileBuffer, undo: &mut Undo) {
+    match buf.check_disk() {
+        DiskChange::Unchanged => return,
+        DiskChange::Deleted => {
+            display.update_info_line(&format!(
+                "[W13] \"{}\" was deleted outside the editor",
+                buf.get_path()
+            ));
+            return;
+        }
+        DiskChange::Modified => (),
+    }
+    let load = if !buf.is_modified() && buf.get_options().autoread {
+        true
+    } else {
+        let msg = format!(
+            "[W11] \"{}\" changed outside the editor: [l]oad file [k]eep buffer",
+            buf.get_path()
+        );
+        display.ask(&msg, "lk") == 'l'
+    };
+    if !load {
+        // the next write goes over the new file without asking again.
+        buf.accept_disk();
+        return;
+    }
+    let result = command::reload(display, buf, undo, None);
+    display.update_all(buf).unwrap();
+    match result {
+        Ok(msg) => display.update_info_line(&format!("{} reloaded", msg)),
+        Err(e) => display.update_info_line(&e),
+    }
+}
 
-fn handle(display: &mut Display, buf: &mut FileBuffer) {
+fn handle(display: &mut Display, list: &mut BufferList, commands: &[String], view: bool) {
     let mut state = State::new();
+    state.set_read_only(view);
     let mut column_prev: u16 = 0;
     let mut row_prev: u16 = 0;
     let is_required_update = true;
     let mut command: command::Command = command::Command::new();
-    display.update_all(buf.get_contents()).unwrap();
-    let mut undo = Undo::new();
-    let mut sch = Search::new();
+    let mut hex = Hex::new();
+    let mut normal = Normal::new();
+    let mut registers = Registers::new();
+    display.update_all(&list.current().file).unwrap();
+    for c in commands {
+        let mode = command.run(display, list, &mut registers, c);
+        if write(display, &mut list.current_mut().file, mode) == MODE::Quit {
+            return;
+        }
+    }
+    display.draw_status_line(&list.current().file);
+    // a change on disk waits for normal mode, so that typed text does not answer the prompt.
+    let mut disk_touched = false;
+    // a large file was being indexed at the last tick.
+    let mut indexing = false;
 
     loop {
         let (size_column, size_row) = size().unwrap();
         if is_required_update || column_prev != size_column || row_prev != size_row {
-            display.update_wsize(Point {
+            display.update_wsize(ScreenPoint {
                 col: size_column,
                 row: size_row,
             });
@@ -50,43 +173,133 @@ fn handle(display: &mut Display, buf: &mut FileBuffer) {
             column_prev = size_column;
         }
 
-        let input = match event::read().unwrap() {
-            Event::Key(event) => event,
-            _ => KeyEvent::new(KeyCode::Null, KeyModifiers::empty()),
-        };
-        if input.kind == KeyEventKind::Release {
-            continue;
+        let cur = list.current_mut();
+        disk_touched |= cur.file.poll_watch();
+        if disk_touched && state.check_mode() == MODE::Normal {
+            disk_touched = false;
+            check_disk(display, &mut cur.file, &mut cur.undo);
+            display.draw_status_line(&cur.file);
         }
-        let code = input.code;
+        // keys of a macro being played come before the typed ones.
+        let input = match normal.next_macro_key() {
+            Some(key) => key,
+            None => {
+                if !event::poll(Duration::from_millis(200)).unwrap() {
+                    // show the lines and the progress of a large file being indexed.
+                    // once more when it is done, for the last lines.
+                    let buf = &list.current().file;
+                    let busy = buf.large_progress().is_some();
+                    if busy || indexing {
+                        display.update_all(buf).unwrap();
+                    }
+                    indexing = busy;
+                    continue;
+                }
+                let input = match event::read().unwrap() {
+                    Event::Key(event) => event,
+                    _ => KeyEvent::new(KeyCode::Null, KeyModifiers::empty()),
+                };
+                if input.kind == KeyEventKind::Release {
+                    continue;
+                }
+                normal.record_key(input);
+                input
+            }
+        };
+        display.take_error();
         let mode = state.check_mode();
+        // outside the command line Ctrl with a letter is its control char, `\x16` for Ctrl-V.
+        let code = match input.code {
+            KeyCode::Char(c)
+                if matches!(
+                    mode,
+                    MODE::Normal | MODE::Visual | MODE::Insert | MODE::Replace
+                ) && input.modifiers.contains(KeyModifiers::CONTROL)
+                    && c.is_ascii_alphabetic() =>
+            {
+                KeyCode::Char((c.to_ascii_lowercase() as u8 & 0x1f) as char)
+            }
+            code => code,
+        };
+        let shown = list.current().id;
 
+        let cur = list.current_mut();
         let new_mode = match mode {
-            MODE::Normal => Normal::proc_normal(code, display, buf, &mut undo),
-            MODE::Insert => {
-                let ret = proc_insert(code, display, buf, &mut undo);
-                display.update_all(buf.get_contents()).unwrap();
-                ret
+            // view mode quits like a pager, still keeping unsaved edits.
+            MODE::Normal if state.get_read_only() && code == KeyCode::Char('q') => {
+                command.run(display, list, &mut registers, "q")
             }
-            MODE::Command => command.proc_command(code, buf),
-            MODE::Visual => MODE::Normal,
-            MODE::Save => {
-                buf.save_file().unwrap();
-                MODE::Normal
+            MODE::Normal if cur.file.is_hex() => hex.proc_normal(code, display, &mut cur.file),
+            MODE::Normal if state.get_read_only() => {
+                normal.proc_view(code, display, list, &mut registers)
             }
-            MODE::SaveAndQuit => {
-                buf.save_file().unwrap();
-                MODE::Quit
+            MODE::Normal => normal.proc_normal(code, display, list, &mut registers),
+            MODE::Insert if cur.file.is_hex() => hex.proc_insert(code, display, &mut cur.file),
+            MODE::Insert | MODE::Replace => normal.proc_insert(code, display, cur, &mut registers),
+            MODE::Command => command.proc_command(code, display, list, &mut registers),
+            // `:` in visual mode works on the lines selected.
+            MODE::Visual => match normal.proc_visual(code, display, cur, &mut registers) {
+                MODE::Command => {
+                    command.start(display, "'<,'>");
+                    MODE::Command
+                }
+                m => m,
+            },
+            MODE::Search => {
+                let (id, from) = (cur.id, display.get_cursor_coordinate_in_file());
+                let mode = cur
+                    .search
+                    .proc_search(code, display, &mut cur.file, &mut registers);
+                // a search that finds a match is a jump.
+                if display.get_cursor_coordinate_in_file() != from {
+                    list.record_jump(id, from);
+                }
+                mode
             }
-            MODE::Search => sch.proc_search(code, buf),
             m => m,
         };
-        if new_mode == MODE::SaveAndQuit {
-            buf.save_file().unwrap();
-            break;
+        // a failed command stops the macros being played.
+        if display.take_error() {
+            normal.stop_macro();
+        }
+        // a buffer that was hidden may have changed on disk meanwhile.
+        if list.current().id != shown {
+            disk_touched = true;
         }
+        let buf = &mut list.current_mut().file;
+        let new_mode = write(display, buf, new_mode);
+        display.draw_status_line(buf);
         if new_mode == MODE::Quit {
             break;
         }
         state.change_mode(new_mode);
     }
 }
+/// carry out the writes asked for by a mode handler.
+fn write(display: &mut Display, buf: &mut FileBuffer, mode: MODE) -> MODE {
+    match mode {
+        MODE::Save => save(display, buf, MODE::Normal),
+        MODE::SaveAndQuit => save(display, buf, MODE::Quit),
+        m => m,
+    }
+}
+/// write the buffer and go on to `next`. stays in normal mode when the write failed.
+fn save(display: &mut Display, buf: &mut FileBuffer, next: MODE) -> MODE {
+    match buf.save_file() {
+        Ok(_) => {
+            // a buffer named by `:w file` is journaled and watched from its first write.
+            buf.start_swap();
+            buf.start_watch();
+            display.update_info_line(&format!(
+                "\"{}\" {} written",
+                buf.get_path(),
+                buf.get_size()
+            ));
+            next
+        }
+        Err(e) => {
+            display.update_info_line(&e);
+            MODE::Normal
+        }
+    }
+}
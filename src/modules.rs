@@ -1,10 +1,27 @@
+pub mod buffer;
+pub mod cli;
 pub mod command;
 pub mod coordinate;
+pub mod encoding;
 pub mod file;
+pub mod fileformat;
+pub mod hex;
 pub mod history;
 pub mod insert;
+pub mod large;
+pub mod macros;
+pub mod mark;
 pub mod mode;
+pub mod motion;
 pub mod normal;
+pub mod operator;
+pub mod option;
+pub mod register;
 pub mod search;
 pub mod show;
+pub mod swap;
+pub mod textobject;
 pub mod undo;
+pub mod unicode;
+pub mod visual;
+pub mod watch;
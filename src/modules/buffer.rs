@@ -0,0 +1,337 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::mark::{Jump, JumpList};
+use crate::modules::search::Search;
+use crate::modules::show::{Display, View};
+use crate::modules::swap::find_swap;
+use crate::modules::undo::Undo;
+use crate::modules::visual::Selection;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// a file open in the editor with the state that goes with it.
+pub struct Buffer {
+    /// number shown by `:ls`, kept while the buffer lives
+    pub id: usize,
+    pub file: FileBuffer,
+    pub undo: Undo,
+    pub search: Search,
+    /// cursor and window, while another buffer is shown
+    pub view: View,
+    /// the last selection of visual mode, for `gv`. its ends are the marks `<` and `>`
+    pub visual: Option<Selection>,
+}
+/// the open buffers. one of them is shown in the window.
+pub struct BufferList {
+    buffers: Vec<Buffer>,
+    current: usize,
+    next_id: usize,
+    /// every buffer added is read-only, in view mode
+    read_only: bool,
+    /// the places the cursor jumped from, in any buffer
+    jumps: JumpList,
+    /// file marks of the buffers closed, given back when their file is opened again
+    file_marks: HashMap<char, (String, Point)>,
+}
+
+impl BufferList {
+    pub fn new(file: FileBuffer) -> BufferList {
+        let mut list = BufferList {
+            buffers: vec![],
+            current: 0,
+            next_id: 1,
+            read_only: false,
+            jumps: JumpList::new(),
+            file_marks: HashMap::new(),
+        };
+        list.add(file);
+        list
+    }
+    /// add a buffer at the end of the list. returns its index.
+    pub fn add(&mut self, mut file: FileBuffer) -> usize {
+        if self.read_only {
+            file.set_read_only(true);
+        }
+        let names: Vec<char> = self
+            .file_marks
+            .iter()
+            .filter(|(_, (path, _))| same_file(path, file.get_path()))
+            .map(|(c, _)| *c)
+            .collect();
+        for c in names {
+            let (_, at) = self.file_marks.remove(&c).unwrap();
+            file.get_marks_mut().set(c, at);
+        }
+        self.buffers.push(Buffer {
+            id: self.next_id,
+            file,
+            undo: Undo::new(),
+            search: Search::new(),
+            view: View::new(),
+            visual: None,
+        });
+        self.next_id += 1;
+        self.buffers.len() - 1
+    }
+    /// make every buffer read-only, now and when added later.
+    pub fn set_read_only(&mut self) {
+        self.read_only = true;
+        for b in self.buffers.iter_mut() {
+            b.file.set_read_only(true);
+        }
+    }
+    pub fn get(&self, index: usize) -> &Buffer {
+        &self.buffers[index]
+    }
+    pub fn current(&self) -> &Buffer {
+        &self.buffers[self.current]
+    }
+    pub fn current_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.current]
+    }
+    pub fn current_index(&self) -> usize {
+        self.current
+    }
+    /// index of the buffer with number `id`.
+    pub fn find_id(&self, id: usize) -> Option<usize> {
+        self.buffers.iter().position(|b| b.id == id)
+    }
+    /// index of the buffer editing `path`, also when it was opened through another name.
+    pub fn find_path(&self, path: &str) -> Option<usize> {
+        self.buffers
+            .iter()
+            .position(|b| same_file(b.file.get_path(), path))
+    }
+    /// index of the buffer after (or before) the current one, wrapping around.
+    pub fn next_index(&self, forward: bool) -> usize {
+        let len = self.buffers.len();
+        if forward {
+            (self.current + 1) % len
+        } else {
+            (self.current + len - 1) % len
+        }
+    }
+    /// show the buffer at `index`, keeping the view of the one shown so far.
+    pub fn switch(&mut self, display: &mut Display, index: usize) {
+        self.buffers[self.current].view = display.get_view();
+        self.current = index;
+        let buf = &self.buffers[index];
+        display.set_view(&buf.file, buf.view);
+    }
+    /// take the buffer at `index` out of the list and close its swap file.
+    /// the last buffer is never removed.
+    pub fn remove(&mut self, display: &mut Display, index: usize) -> Result<(), String> {
+        if self.buffers.len() == 1 {
+            return Err(String::from("[E90] cannot unload last buffer"));
+        }
+        if index == self.current {
+            self.switch(display, self.next_index(true));
+        }
+        let mut removed = self.buffers.remove(index);
+        removed.file.close_swap();
+        self.jumps.forget(removed.id);
+        let path = removed.file.get_path();
+        if !path.is_empty() {
+            for (c, at) in removed.file.get_marks().iter() {
+                if c.is_ascii_uppercase() {
+                    self.file_marks.insert(c, (String::from(path), at));
+                }
+            }
+        }
+        if self.current > index {
+            self.current -= 1;
+        }
+        Ok(())
+    }
+    /// the first buffer with unsaved edits other than the current one.
+    pub fn other_modified(&self) -> Option<&Buffer> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .find(|(i, b)| *i != self.current && b.file.is_modified())
+            .map(|(_, b)| b)
+    }
+    /// apply a `:set` argument of a global option to every buffer.
+    pub fn set_global(&mut self, arg: &str) -> Result<(), String> {
+        for b in self.buffers.iter_mut() {
+            b.file.get_options_mut().set(arg)?;
+        }
+        Ok(())
+    }
+    /// the buffer list as shown by `:ls`, a line for each buffer.
+    /// `%` marks the current buffer, `h` the hidden ones and `+` unsaved edits.
+    pub fn list(&self, display: &Display) -> Vec<String> {
+        self.buffers
+            .iter()
+            .enumerate()
+            .map(|(i, b)| {
+                let cursor = if i == self.current {
+                    display.get_cursor_coordinate_in_file()
+                } else {
+                    b.view.get_cursor()
+                };
+                format!(
+                    "{} {}{} \"{}\" line {}",
+                    b.id,
+                    if i == self.current { "%a" } else { " h" },
+                    if b.file.is_modified() { "+" } else { " " },
+                    b.file.get_name(),
+                    cursor.row + 1
+                )
+            })
+            .collect()
+    }
+    /// the cursor jumps away from `at` in the buffer numbered `id`. the place is kept
+    /// in the jump list and as the mark `'` of the buffer.
+    pub fn record_jump(&mut self, id: usize, at: Point) {
+        self.jumps.push(Jump { id, at });
+        if let Some(i) = self.find_id(id) {
+            self.buffers[i].file.get_marks_mut().set('\'', at);
+        }
+    }
+    /// `Ctrl-O` from `at` in the current buffer.
+    pub fn jump_back(&mut self, at: Point, count: usize) -> Option<Jump> {
+        let id = self.current().id;
+        self.jumps.back(Jump { id, at }, count)
+    }
+    /// `Ctrl-I`.
+    pub fn jump_forward(&mut self, count: usize) -> Option<Jump> {
+        self.jumps.forward(count)
+    }
+    /// set the file mark `name` at `at` in the current buffer, taking it from the
+    /// buffer or file it was in before.
+    pub fn set_file_mark(&mut self, name: char, at: Point) {
+        self.file_marks.remove(&name);
+        for b in self.buffers.iter_mut() {
+            b.file.get_marks_mut().remove(name);
+        }
+        self.current_mut().file.get_marks_mut().set(name, at);
+    }
+    /// index of the buffer that has the file mark `name`.
+    pub fn find_mark(&self, name: char) -> Option<usize> {
+        self.buffers
+            .iter()
+            .position(|b| b.file.get_marks().get(name).is_some())
+    }
+    /// the file of a closed buffer that had the file mark `name`.
+    pub fn closed_mark(&self, name: char) -> Option<&str> {
+        self.file_marks.get(&name).map(|(path, _)| path.as_str())
+    }
+    /// the marks of the current buffer and the file marks, or only those in `names`,
+    /// a line for each under a header, for `:marks`. the line of a mark is shown, or the
+    /// file for a file mark elsewhere.
+    pub fn marks(&self, names: &str) -> Result<Vec<String>, String> {
+        let cur = &self.current().file;
+        let last = cur.get_row_length().saturating_sub(1);
+        let mut items = vec![];
+        for c in "'abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ\"[]^.<>".chars() {
+            if !names.is_empty() && !names.contains(c) {
+                continue;
+            }
+            let (at, text) = match cur.get_marks().get(c) {
+                Some(at) => (at, cur.get_line(at.row.min(last)).trim().to_string()),
+                None if c.is_ascii_uppercase() => match self.find_mark(c) {
+                    Some(i) => {
+                        let file = &self.buffers[i].file;
+                        (
+                            file.get_marks().get(c).unwrap(),
+                            file.get_name().to_string(),
+                        )
+                    }
+                    None => match self.file_marks.get(&c) {
+                        Some((path, at)) => (*at, path.clone()),
+                        None => continue,
+                    },
+                },
+                None => continue,
+            };
+            items.push(format!(" {} {:>6} {:>4} {}", c, at.row + 1, at.col, text));
+        }
+        if items.is_empty() {
+            return Err(format!("[E283] no marks matching \"{}\"", names));
+        }
+        items.insert(0, String::from("mark line  col file/text"));
+        Ok(items)
+    }
+    /// stop journaling every buffer, when the editor quits.
+    pub fn close_all(&mut self) {
+        for b in self.buffers.iter_mut() {
+            b.file.close_swap();
+        }
+    }
+}
+/// `a` and `b` name the same file, also when one is a link or a relative name.
+fn same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    let canonical = |p: &str| Path::new(p).canonicalize().ok();
+    canonical(a).is_some() && canonical(a) == canonical(b)
+}
+/// recover the swap file of `buf` and tell how it went.
+pub fn recover_swap(display: &mut Display, buf: &mut FileBuffer) {
+    match buf.recover() {
+        Ok(n) => display.update_info_line(&format!(
+            "recovered {} changes. write the buffer to keep them",
+            n
+        )),
+        Err(e) => display.update_info_line(&e),
+    }
+}
+/// ask what to do when the file has a swap file from another instance or a crash.
+/// returns false when the user gives up opening the file.
+pub fn ask_swap(display: &mut Display, buf: &mut FileBuffer) -> bool {
+    if buf.get_path().is_empty() {
+        return true;
+    }
+    let info = match find_swap(buf.get_path()) {
+        Some(info) => info,
+        None => return true,
+    };
+    display.update_all(buf).unwrap();
+    let msg = format!(
+        "swap file {} exists (pid {}{}): [r]ecover [d]elete [e]dit anyway [q]uit",
+        info.path.display(),
+        info.pid,
+        if info.running { ", still running" } else { "" }
+    );
+    match display.ask(&msg, "rdeq") {
+        'r' => recover_swap(display, buf),
+        'd' => {
+            let _ = std::fs::remove_file(&info.path);
+        }
+        'q' => return false,
+        _ => (),
+    }
+    true
+}
+#[cfg(test)]
+mod buffer_test {
+    use super::*;
+
+    fn test_file(name: &str) -> FileBuffer {
+        FileBuffer::from_text(name, "abc\n")
+    }
+    #[test]
+    fn test_list() {
+        let mut list = BufferList::new(test_file("a.txt"));
+        let b = list.add(test_file("b.txt"));
+        let c = list.add(test_file("c.txt"));
+        assert_eq!((list.get(b).id, list.get(c).id), (2, 3));
+        assert_eq!(list.find_id(3), Some(c));
+        let path = String::from(list.get(b).file.get_path());
+        assert_eq!(list.find_path(&path), Some(b));
+        assert_eq!(list.find_path("no_such_file"), None);
+        assert_eq!(list.next_index(true), b);
+        assert_eq!(list.next_index(false), c);
+
+        assert!(list.other_modified().is_none());
+        list.current_mut().file.insert_str(0, 0, "x");
+        assert!(list.other_modified().is_none());
+        list.buffers[c].file.insert_str(0, 0, "x");
+        assert_eq!(list.other_modified().map(|b| b.id), Some(3));
+
+        list.set_global("hidden").unwrap();
+        assert!(list.buffers.iter().all(|b| b.file.get_options().hidden));
+    }
+}
@@ -0,0 +1,120 @@
+// command line of ve:
+//   ve [options] [file ...]
+//   ve [options] -          read the text from stdin
+
+pub const USAGE: &str = "usage: ve [options] [file ...]
+       ve [options] -        read the text from stdin
+
+options:
+  +N            start at line N (the last line without N)
+  +/pattern     start at the first line containing pattern
+  +command      run the ex command after loading the first file
+  -c command    run the ex command after loading the first file
+  -R            read-only
+  -r            recover the files from their swap files
+  --            only file names follow
+  --version     print the version and exit
+  -h, --help    print this help and exit
+
+started as `view`, ve pages through the files read-only.
+";
+
+/// what the command line asks for.
+#[derive(Debug, PartialEq)]
+pub enum Action {
+    Edit(Args),
+    Help,
+    Version,
+}
+/// the files to edit and how to start.
+#[derive(Debug, PartialEq)]
+pub struct Args {
+    pub files: Vec<String>,
+    /// `-` was given, the text comes from stdin
+    pub stdin: bool,
+    pub read_only: bool,
+    pub recover: bool,
+    /// ex commands run after loading, from `+cmd` and `-c cmd` in order
+    pub commands: Vec<String>,
+}
+
+/// parse the arguments after the program name.
+pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Action, String> {
+    let mut ret = Args {
+        files: vec![],
+        stdin: false,
+        read_only: false,
+        recover: false,
+        commands: vec![],
+    };
+    let mut args = args.into_iter();
+    let mut only_files = false;
+    while let Some(arg) = args.next() {
+        if only_files {
+            ret.files.push(arg);
+            continue;
+        }
+        match arg.as_str() {
+            "--" => only_files = true,
+            "-" => ret.stdin = true,
+            "-h" | "--help" => return Ok(Action::Help),
+            "--version" => return Ok(Action::Version),
+            "-R" => ret.read_only = true,
+            "-r" => ret.recover = true,
+            "-c" => match args.next() {
+                Some(cmd) => ret.commands.push(cmd),
+                None => return Err(String::from("ve: -c needs a command")),
+            },
+            // `+` alone goes to the last line, `+N` and `+/pat` are ex commands as well.
+            "+" => ret.commands.push(String::from("$")),
+            a if a.starts_with('+') => ret.commands.push(String::from(&a[1..])),
+            a if a.starts_with('-') => return Err(format!("ve: unknown option: {}", a)),
+            _ => ret.files.push(arg),
+        }
+    }
+    if ret.stdin && !ret.files.is_empty() {
+        return Err(String::from("ve: cannot read stdin and files together"));
+    }
+    if ret.recover && ret.files.is_empty() {
+        return Err(String::from("ve: -r needs a file to recover"));
+    }
+    Ok(Action::Edit(ret))
+}
+#[cfg(test)]
+mod cli_test {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Action, String> {
+        parse(list.iter().map(|a| String::from(*a)))
+    }
+    #[test]
+    fn test_parse() {
+        let edit = match args(&["-R", "+42", "a.txt", "-c", "set ts=4", "b.txt", "+/fn main"]) {
+            Ok(Action::Edit(a)) => a,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(edit.files, ["a.txt", "b.txt"]);
+        assert!(edit.read_only && !edit.recover && !edit.stdin);
+        assert_eq!(edit.commands, ["42", "set ts=4", "/fn main"]);
+
+        let empty = match args(&[]) {
+            Ok(Action::Edit(a)) => a,
+            other => panic!("{:?}", other),
+        };
+        assert!(empty.files.is_empty() && empty.commands.is_empty());
+        match args(&["-", "+"]) {
+            Ok(Action::Edit(a)) => assert!(a.stdin && a.commands == ["$"]),
+            other => panic!("{:?}", other),
+        }
+        match args(&["--", "-R", "+3"]) {
+            Ok(Action::Edit(a)) => assert_eq!(a.files, ["-R", "+3"]),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(args(&["a", "--help"]), Ok(Action::Help));
+        assert_eq!(args(&["--version"]), Ok(Action::Version));
+        assert!(args(&["-x"]).is_err());
+        assert!(args(&["-c"]).is_err());
+        assert!(args(&["-r"]).is_err());
+        assert!(args(&["-", "a"]).is_err());
+    }
+}
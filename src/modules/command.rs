@@ -1,5 +1,19 @@
+use crate::modules::buffer::{ask_swap, BufferList};
+use crate::modules::coordinate::Point;
+use crate::modules::encoding::encoding_from_name;
+use crate::modules::hex::point_of;
+use crate::modules::mark::Marks;
 use crate::modules::mode::MODE;
+use crate::modules::operator::{self, Range};
+use crate::modules::option::Options;
+use crate::modules::register::{is_writable, Registers};
+use crate::modules::search::search_string;
+use crate::modules::show::Display;
+use crate::modules::undo::Undo;
+use crate::modules::watch::DiskChange;
 use crossterm::event::KeyCode;
+use encoding_rs::Encoding;
+use std::path::Path;
 
 use super::file::FileBuffer;
 
@@ -11,33 +25,608 @@ impl Command {
     pub fn new() -> Command {
         Command { inputs: vec![] }
     }
-    pub fn proc_command(&mut self, code: KeyCode, buf: &mut FileBuffer) -> MODE {
+    pub fn proc_command(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        list: &mut BufferList,
+        registers: &mut Registers,
+    ) -> MODE {
         match code {
             KeyCode::Char(c) => {
                 self.inputs.push(c);
+                display.update_info_line(&format!(":{}", self.get_input()));
                 MODE::Command
             }
-            KeyCode::Enter => self.exec_command(),
-            KeyCode::Esc => MODE::Normal,
+            KeyCode::Backspace => {
+                if self.inputs.pop().is_none() {
+                    display.update_info_line("");
+                    return MODE::Normal;
+                }
+                display.update_info_line(&format!(":{}", self.get_input()));
+                MODE::Command
+            }
+            KeyCode::Enter => {
+                let mode = self.exec_command(display, list, registers);
+                self.inputs.clear();
+                mode
+            }
+            KeyCode::Esc => {
+                self.inputs.clear();
+                display.update_info_line("");
+                MODE::Normal
+            }
             _ => MODE::Command,
         }
     }
-    pub fn exec_command(&mut self) -> MODE {
-        let mut flg: u16 = 0x00;
+    /// open the command line with `text` typed in it.
+    pub fn start(&mut self, display: &mut Display, text: &str) {
+        self.inputs = text.chars().collect();
+        display.update_info_line(&format!(":{}", text));
+    }
+    fn get_input(&self) -> String {
+        self.inputs.iter().collect()
+    }
+    /// run an ex command that was not typed, as given by `ve -c`.
+    pub fn run(
+        &mut self,
+        display: &mut Display,
+        list: &mut BufferList,
+        registers: &mut Registers,
+        line: &str,
+    ) -> MODE {
+        self.inputs = line.chars().collect();
+        let mode = self.exec_command(display, list, registers);
+        self.inputs.clear();
+        mode
+    }
+    pub fn exec_command(
+        &mut self,
+        display: &mut Display,
+        list: &mut BufferList,
+        registers: &mut Registers,
+    ) -> MODE {
+        let input = self.get_input();
+        if !input.trim().is_empty() {
+            registers.set_command(&input);
+        }
+        list.current_mut().file.get_marks_mut().start_change();
+        if let Some(result) = goto_line(display, list, input.trim()) {
+            match result {
+                Ok(msg) => display.update_info_line(&msg),
+                Err(msg) => display.update_info_line(&msg),
+            }
+            return MODE::Normal;
+        }
+        let cur = list.current();
+        let cursor = display.get_cursor_coordinate_in_file().row;
+        let last = cur.file.get_row_length().saturating_sub(1);
+        let (range, rest) = match parse_range(&input, cursor, last, cur.file.get_marks()) {
+            Ok(r) => r,
+            Err(e) => {
+                display.update_info_line(&e);
+                return MODE::Normal;
+            }
+        };
+        let (name, bang, arg) = parse_command(rest);
+        let takes_range = matches!(name, "" | "d" | "delete" | "y" | "yank" | "j" | "join")
+            || name.starts_with(['<', '>']);
+        if range.is_some() && !takes_range {
+            display.update_info_line("[E481] no range allowed");
+            return MODE::Normal;
+        }
+        let result = match name {
+            "q" | "quit" => match check_quit(list, bang, true) {
+                Ok(_) => return MODE::Quit,
+                Err(e) => Err(e),
+            },
+            "w" | "write" => match write_as(list, bang, arg) {
+                Ok(None) => return confirm_write(display, &list.current().file, bang, MODE::Save),
+                Ok(Some(msg)) => Ok(msg),
+                Err(e) => Err(e),
+            },
+            // `:x` only writes a modified buffer.
+            "x" | "xit" if !list.current().file.is_modified() => {
+                match check_quit(list, bang, false) {
+                    Ok(_) => return MODE::Quit,
+                    Err(e) => Err(e),
+                }
+            }
+            "wq" | "x" | "xit" => match check_quit(list, bang, false)
+                .and_then(|_| write_as(list, bang, arg))
+            {
+                Ok(None) => {
+                    return confirm_write(display, &list.current().file, bang, MODE::SaveAndQuit)
+                }
+                Ok(Some(_)) => return MODE::Quit,
+                Err(e) => Err(e),
+            },
+            "set" | "se" => set_options(list, arg),
+            "e" | "edit" => edit(display, list, bang, arg),
+            "ls" | "buffers" | "files" => {
+                let lines = list.list(display);
+                display.show_lines(&list.current().file, &lines);
+                Ok(String::new())
+            }
+            "bn" | "bnext" => switch(display, list, bang, list.next_index(true)),
+            "bp" | "bprevious" | "bN" | "bNext" => {
+                switch(display, list, bang, list.next_index(false))
+            }
+            "b" | "buffer" => match buffer_index(list, arg) {
+                Ok(i) => switch(display, list, bang, i),
+                Err(e) => Err(e),
+            },
+            "bd" | "bdelete" => delete_buffer(display, list, bang, arg),
+            "hex" => toggle_hex(display, list),
+            "marks" => list.marks(arg).map(|lines| {
+                display.show_lines(&list.current().file, &lines);
+                String::new()
+            }),
+            "reg" | "registers" | "di" | "display" => {
+                let lines = registers.list(arg, list.current().file.get_path());
+                display.show_lines(&list.current().file, &lines);
+                Ok(String::new())
+            }
+            "d" | "delete" | "y" | "yank" | "j" | "join" => {
+                edit_lines(display, list, registers, name, arg, range)
+            }
+            n if n.starts_with(['<', '>']) => edit_lines(display, list, registers, n, arg, range),
+            // a range alone goes to its last line.
+            "" => {
+                if let Some((_, row)) = range {
+                    let cur = list.current();
+                    list.record_jump(cur.id, display.get_cursor_coordinate_in_file());
+                    display.move_to_point(&list.current().file, Point { col: 0, row });
+                }
+                Ok(String::new())
+            }
+            _ => Err(format!("[E492] not an editor command: {}", input)),
+        };
+        match result {
+            Ok(msg) => display.update_info_line(&msg),
+            Err(msg) => display.update_info_line(&msg),
+        }
+        MODE::Normal
+    }
+}
+/// split a command line into the command name, whether `!` follows it, and the argument.
+/// `>>` and `<<` are names of their own.
+pub fn parse_command(input: &str) -> (&str, bool, &str) {
+    let input = input.trim_start();
+    let end = match input.chars().next() {
+        Some(c @ ('<' | '>')) => input.find(|d: char| d != c),
+        _ => input.find(|c: char| !c.is_ascii_alphabetic()),
+    }
+    .unwrap_or(input.len());
+    let (name, rest) = input.split_at(end);
+    match rest.strip_prefix('!') {
+        Some(rest) => (name, true, rest.trim()),
+        None => (name, false, rest.trim()),
+    }
+}
+/// the first and the last row of the lines a command works on.
+type LineRange = (usize, usize);
 
-        for cmd in &(self.inputs) {
-            match cmd {
-                'q' => flg |= 0x01,
-                'w' => flg |= 0x02,
-                _ => flg |= !0x03,
+/// split the lines a command works on off the front of a command line: `N`, `.`, `$`,
+/// `'x` (the line of a mark in `marks`, `'<` and `'>` for the lines selected last), two
+/// of them joined by `,`, or `%` for every line. the rows are from 0 to `last`.
+fn parse_range<'a>(
+    input: &'a str,
+    cursor: usize,
+    last: usize,
+    marks: &Marks,
+) -> Result<(Option<LineRange>, &'a str), String> {
+    let input = input.trim_start();
+    if let Some(rest) = input.strip_prefix('%') {
+        return Ok((Some((0, last)), rest));
+    }
+    let (first, rest) = match address(input, cursor, last, marks)? {
+        Some(a) => a,
+        None => return Ok((None, input)),
+    };
+    let (second, rest) = match rest.strip_prefix(',') {
+        Some(rest) => match address(rest, cursor, last, marks)? {
+            Some(a) => a,
+            None => return Err(String::from("[E14] invalid address")),
+        },
+        None => (first, rest),
+    };
+    if first.max(second) > last {
+        return Err(String::from("[E16] invalid range"));
+    }
+    Ok((Some((first.min(second), first.max(second))), rest))
+}
+/// the row of a line address at the front of `input`, and the rest of it.
+fn address<'a>(
+    input: &'a str,
+    cursor: usize,
+    last: usize,
+    marks: &Marks,
+) -> Result<Option<(usize, &'a str)>, String> {
+    let digits = input
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(input.len());
+    if digits > 0 {
+        let n = input[..digits].parse::<usize>().unwrap_or(usize::MAX);
+        return Ok(Some((n.saturating_sub(1), &input[digits..])));
+    }
+    let mut chars = input.chars();
+    let row = match (chars.next(), chars.next()) {
+        (Some('.'), _) => return Ok(Some((cursor, &input[1..]))),
+        (Some('$'), _) => return Ok(Some((last, &input[1..]))),
+        (Some('\''), Some(c)) => marks.get(c).map(|p| p.row),
+        (Some('\''), None) => None,
+        _ => return Ok(None),
+    };
+    match row {
+        Some(row) => Ok(Some((row, chars.as_str()))),
+        None => Err(String::from("[E20] mark not set")),
+    }
+}
+/// `:d`, `:y`, `:>`, `:<` and `:j` on the lines of `range`, the cursor line without one.
+/// `:d` and `:y` take a register name. `:j` on one line joins the line after it.
+fn edit_lines(
+    display: &mut Display,
+    list: &mut BufferList,
+    registers: &mut Registers,
+    name: &str,
+    arg: &str,
+    range: Option<LineRange>,
+) -> Result<String, String> {
+    let cur = list.current_mut();
+    let (buf, undo) = (&mut cur.file, &mut cur.undo);
+    let row = display.get_cursor_coordinate_in_file().row;
+    let (first, last) = range.unwrap_or((row, row));
+    let lines = last - first + 1;
+    let yank = matches!(name, "y" | "yank");
+    if !yank {
+        buf.check_edit()?;
+    }
+    let mut chars = arg.chars();
+    let register = match (chars.next(), chars.next()) {
+        (None, _) => None,
+        (Some(c), None) if matches!(name, "d" | "delete" | "y" | "yank") => Some(c),
+        _ => return Err(format!("[E488] trailing characters: {}", arg)),
+    };
+    if let Some(c) = register.filter(|c| !is_writable(*c)) {
+        return Err(format!("[E354] invalid register name: '{}'", c));
+    }
+    let range = Range::lines(first, last);
+    let mut msg = String::new();
+    if !yank {
+        undo.start_change();
+    }
+    let cursor = match name {
+        "y" | "yank" => {
+            registers.yank(register, operator::yank(buf, range));
+            if lines > 2 {
+                msg = format!("{} lines yanked", lines);
             }
+            return Ok(msg);
         }
-        self.inputs.clear();
-        match flg {
-            0x01 => MODE::Quit,
-            0x02 => MODE::Save,
-            0x03 => MODE::SaveAndQuit,
-            _ => MODE::Normal,
+        "d" | "delete" => {
+            registers.delete(register, operator::yank(buf, range));
+            if lines > 2 {
+                msg = format!("{} fewer lines", lines);
+            }
+            operator::delete(buf, undo, range, false)
+        }
+        "j" | "join" => {
+            let last = last.max(first + 1);
+            operator::join(buf, undo, first, last)
+        }
+        _ => {
+            let times = name.len();
+            let right = name.starts_with('>');
+            for _ in 1..times {
+                operator::shift(buf, undo, range, right);
+            }
+            if lines > 2 {
+                let sign = &name[..1];
+                let s = if times > 1 { "s" } else { "" };
+                msg = format!("{} lines {}ed {} time{}", lines, sign, times, s);
+            }
+            operator::shift(buf, undo, range, right)
+        }
+    };
+    display.update_all(buf)?;
+    display.jump_to(buf, cursor);
+    Ok(msg)
+}
+fn set_options(list: &mut BufferList, arg: &str) -> Result<String, String> {
+    let mut msgs = vec![];
+    for a in arg.split_whitespace() {
+        let buf = &mut list.current_mut().file;
+        msgs.push(buf.get_options_mut().set(a)?);
+        // choosing a fileformat converts every line of a mixed file to it.
+        if a.starts_with("ff=") || a.starts_with("fileformat=") {
+            buf.strip_stray_cr();
         }
+        if Options::is_global(a) && !a.ends_with('?') {
+            list.set_global(a)?;
+        }
+    }
+    Ok(msgs.join(" "))
+}
+/// `:N`, `:$` and `:/pattern` move the cursor to a line, a jump kept in the jump list.
+/// returns None when the input is not a line address.
+fn goto_line(
+    display: &mut Display,
+    list: &mut BufferList,
+    input: &str,
+) -> Option<Result<String, String>> {
+    let cursor = display.get_cursor_coordinate_in_file();
+    let buf = &list.current().file;
+    let last = buf.get_row_length().saturating_sub(1);
+    let row = if input == "$" {
+        last
+    } else if !input.is_empty() && input.chars().all(|c| c.is_ascii_digit()) {
+        let n = input.parse::<usize>().unwrap_or(usize::MAX);
+        n.saturating_sub(1).min(last)
+    } else if let Some(ptn) = input.strip_prefix('/') {
+        let found = search_string(buf, ptn);
+        // the next line with a match, wrapping around the end.
+        match found.iter().find(|p| p.row > cursor.row).or(found.first()) {
+            Some(p) => p.row,
+            None => return Some(Err(format!("[E486] pattern not found: {}", ptn))),
+        }
+    } else {
+        return None;
+    };
+    list.record_jump(list.current().id, cursor);
+    display.move_to_point(&list.current().file, Point { col: 0, row });
+    Some(Ok(String::new()))
+}
+/// `:w file` names a buffer without a file, or writes a copy of a named one.
+/// returns None when the buffer itself is to be written, the message of the copy otherwise.
+fn write_as(list: &mut BufferList, bang: bool, arg: &str) -> Result<Option<String>, String> {
+    let buf = &mut list.current_mut().file;
+    if arg.is_empty() || arg == buf.get_path() {
+        return Ok(None);
+    }
+    if !bang && Path::new(arg).exists() {
+        return Err(String::from("[E13] file exists (add ! to override)"));
+    }
+    if buf.get_path().is_empty() {
+        buf.set_path(arg);
+        return Ok(None);
+    }
+    buf.write_copy(Path::new(arg))?;
+    Ok(Some(format!("\"{}\" {} written", arg, buf.get_size())))
+}
+/// a read-only buffer is only written with `!`. before writing over a file that was
+/// changed outside, ask unless `!` forces it.
+fn confirm_write(display: &mut Display, buf: &FileBuffer, bang: bool, mode: MODE) -> MODE {
+    if bang {
+        return mode;
+    }
+    if buf.get_read_only() {
+        display.update_info_line("[E45] 'readonly' option is set (add ! to override)");
+        return MODE::Normal;
+    }
+    if buf.check_disk() != DiskChange::Modified {
+        return mode;
+    }
+    let msg = "[W10] the file changed on disk since it was read. write anyway? (y/n)";
+    match display.ask(msg, "yn") {
+        'y' => mode,
+        _ => MODE::Normal,
+    }
+}
+/// switch the current buffer between hex mode and text. undo starts over.
+/// hex edits are not journaled, so the info line says the swap file is off.
+fn toggle_hex(display: &mut Display, list: &mut BufferList) -> Result<String, String> {
+    let cur = list.current_mut();
+    let hex = !cur.file.is_hex();
+    cur.file.set_hex(hex)?;
+    cur.undo = Undo::new();
+    display.move_to_point(&cur.file, point_of(0, 0));
+    Ok(String::from(if hex {
+        "swap disabled in hex mode"
+    } else {
+        "text mode"
+    }))
+}
+/// `:q` leaves nothing unsaved behind unless `!` is given.
+/// `current` also checks the shown buffer, `:wq` writes that one.
+fn check_quit(list: &BufferList, bang: bool, current: bool) -> Result<(), String> {
+    if bang {
+        return Ok(());
+    }
+    if current && list.current().file.is_modified() {
+        return Err(String::from(
+            "[E37] no write since last change (add ! to override)",
+        ));
+    }
+    match list.other_modified() {
+        Some(b) => Err(format!(
+            "[E162] no write since last change for buffer \"{}\"",
+            b.file.get_name()
+        )),
+        None => Ok(()),
+    }
+}
+/// the shown buffer may be left when it has no unsaved edits, `hidden` is set or `!` is given.
+fn check_abandon(list: &BufferList, bang: bool) -> Result<(), String> {
+    let buf = &list.current().file;
+    if bang || buf.get_options().hidden || !buf.is_modified() {
+        Ok(())
+    } else {
+        Err(String::from(
+            "[E37] no write since last change (add ! to override)",
+        ))
+    }
+}
+/// show the buffer at `index`.
+pub fn switch(
+    display: &mut Display,
+    list: &mut BufferList,
+    bang: bool,
+    index: usize,
+) -> Result<String, String> {
+    if index != list.current_index() {
+        check_abandon(list, bang)?;
+        list.switch(display, index);
+    }
+    let cur = list.current();
+    Ok(format!(
+        "\"{}\" {}L{}",
+        cur.file.get_name(),
+        cur.file.get_row_length(),
+        if cur.file.is_modified() { " [+]" } else { "" }
+    ))
+}
+/// the buffer named by the number in `arg`, or the shown one without it.
+fn buffer_index(list: &BufferList, arg: &str) -> Result<usize, String> {
+    if arg.is_empty() {
+        return Ok(list.current_index());
+    }
+    match arg.parse::<usize>() {
+        Ok(id) => match list.find_id(id) {
+            Some(i) => Ok(i),
+            None => Err(format!("[E86] buffer {} does not exist", id)),
+        },
+        Err(_) => match list.find_path(arg) {
+            Some(i) => Ok(i),
+            None => Err(format!("[E94] no matching buffer for {}", arg)),
+        },
+    }
+}
+/// `:bd` removes a buffer from the list. unsaved edits need `!`.
+fn delete_buffer(
+    display: &mut Display,
+    list: &mut BufferList,
+    bang: bool,
+    arg: &str,
+) -> Result<String, String> {
+    let index = buffer_index(list, arg)?;
+    let b = list.get(index);
+    if !bang && b.file.is_modified() {
+        return Err(format!(
+            "[E89] no write since last change for buffer {} (add ! to override)",
+            b.id
+        ));
+    }
+    let id = b.id;
+    list.remove(display, index)?;
+    Ok(format!("buffer {} deleted", id))
+}
+/// `:e` reads the file again and `:e file` opens another file. `++enc=` forces the encoding.
+fn edit(
+    display: &mut Display,
+    list: &mut BufferList,
+    bang: bool,
+    arg: &str,
+) -> Result<String, String> {
+    let mut encoding = None;
+    let mut path = None;
+    for a in arg.split_whitespace() {
+        match a.strip_prefix("++enc=") {
+            Some(name) => match encoding_from_name(name) {
+                Some(e) => encoding = Some(e),
+                None => return Err(format!("[E474] invalid argument: {}", a)),
+            },
+            None if a.starts_with("++") || path.is_some() => {
+                return Err(format!("[E474] invalid argument: {}", a))
+            }
+            None => path = Some(a),
+        }
+    }
+    let index = match path {
+        Some(p) => list.find_path(p),
+        None => Some(list.current_index()),
+    };
+    match index {
+        // reading the shown file again drops its edits.
+        Some(i) if i == list.current_index() => {
+            if !bang && list.current().file.is_modified() {
+                return Err(String::from(
+                    "[E37] no write since last change (add ! to override)",
+                ));
+            }
+            let cur = list.current_mut();
+            reload(display, &mut cur.file, &mut cur.undo, encoding)
+        }
+        Some(i) => switch(display, list, bang, i),
+        None => open(display, list, bang, path.unwrap_or(""), encoding),
+    }
+}
+/// open `path` in a new buffer and show it.
+pub fn open(
+    display: &mut Display,
+    list: &mut BufferList,
+    bang: bool,
+    path: &str,
+    encoding: Option<&'static Encoding>,
+) -> Result<String, String> {
+    check_abandon(list, bang)?;
+    let mut buf = FileBuffer::new(Path::new(path))?;
+    buf.get_options_mut()
+        .inherit(list.current().file.get_options());
+    if encoding.is_some() {
+        buf.reload(encoding)?;
+    }
+    if !ask_swap(display, &mut buf) {
+        display.update_all(&list.current().file).unwrap();
+        return Ok(String::new());
+    }
+    buf.start_swap();
+    buf.start_watch();
+    let index = list.add(buf);
+    list.switch(display, index);
+    let cur = list.current();
+    Ok(format!(
+        "\"{}\" {}L",
+        cur.file.get_path(),
+        cur.file.get_row_length()
+    ))
+}
+/// read the file into the buffer again, dropping the undo history.
+pub fn reload(
+    display: &mut Display,
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    encoding: Option<&'static Encoding>,
+) -> Result<String, String> {
+    buf.reload(encoding)?;
+    *undo = Undo::new();
+    display.move_to_point(buf, display.get_cursor_coordinate_in_file());
+    Ok(format!("\"{}\" {}L", buf.get_path(), buf.get_row_length()))
+}
+#[cfg(test)]
+mod command_test {
+    use super::{parse_command, parse_range};
+    use crate::modules::coordinate::Point;
+    use crate::modules::mark::Marks;
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(parse_command("w"), ("w", false, ""));
+        assert_eq!(parse_command("w!"), ("w", true, ""));
+        assert_eq!(parse_command("e ++enc=sjis"), ("e", false, "++enc=sjis"));
+        assert_eq!(parse_command(" set ts=4 "), ("set", false, "ts=4"));
+        assert_eq!(parse_command("bd! 2"), ("bd", true, "2"));
+        assert_eq!(parse_command(""), ("", false, ""));
+        assert_eq!(parse_command(">> "), (">>", false, ""));
+    }
+    #[test]
+    fn test_parse_range() {
+        let mut marks = Marks::new();
+        marks.set('<', Point { col: 3, row: 2 });
+        marks.set('>', Point { col: 0, row: 6 });
+        marks.set('a', Point { col: 1, row: 8 });
+        let range = |input| parse_range(input, 4, 9, &marks);
+        assert_eq!(range("'<,'>d"), Ok((Some((2, 6)), "d")));
+        assert_eq!(range("%y a"), Ok((Some((0, 9)), "y a")));
+        assert_eq!(range(".,$>"), Ok((Some((4, 9)), ">")));
+        assert_eq!(range("8,3"), Ok((Some((2, 7)), "")));
+        assert_eq!(range(".,'aj"), Ok((Some((4, 8)), "j")));
+        assert_eq!(range("w"), Ok((None, "w")));
+        assert_eq!(range("3,"), Err(String::from("[E14] invalid address")));
+        assert_eq!(range("1,11"), Err(String::from("[E16] invalid range")));
+        assert_eq!(
+            parse_range("'b", 0, 9, &marks),
+            Err(String::from("[E20] mark not set"))
+        );
     }
 }
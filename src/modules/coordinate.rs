@@ -1,5 +1,12 @@
-#[derive(Debug, Clone, Copy)]
+/// a position in the file. col is a char index in the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Point {
+    pub col: usize,
+    pub row: usize,
+}
+/// a cell of the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ScreenPoint {
     pub col: u16,
     pub row: u16,
 }
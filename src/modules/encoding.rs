@@ -0,0 +1,159 @@
+use chardetng::EncodingDetector;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+// file encodings. the text is kept as utf-8 in the buffer and converted
+// back to the encoding of the file on save.
+
+/// decode `bytes` as the encoding of a file.
+/// returns the text, the encoding and whether the file started with a BOM.
+pub fn decode(bytes: &[u8]) -> (String, &'static Encoding, bool) {
+    let (encoding, bom) = detect(bytes);
+    match decode_with(bytes, encoding) {
+        Some((text, bom)) => (text, encoding, bom),
+        // latin1 maps every byte, so nothing is lost when the guess was wrong.
+        None => (
+            decode_with(bytes, WINDOWS_1252).unwrap().0,
+            WINDOWS_1252,
+            bom,
+        ),
+    }
+}
+/// decode `bytes` as `encoding`. a BOM of that encoding is skipped.
+/// returns None if the bytes are not valid in the encoding.
+pub fn decode_with(bytes: &[u8], encoding: &'static Encoding) -> Option<(String, bool)> {
+    let bom = match Encoding::for_bom(bytes) {
+        Some((e, len)) if e == encoding => len,
+        _ => 0,
+    };
+    encoding
+        .decode_without_bom_handling_and_without_replacement(&bytes[bom..])
+        .map(|text| (text.into_owned(), bom > 0))
+}
+/// encode `text` as `encoding`, with a BOM if `bom` is set.
+pub fn encode(text: &str, encoding: &'static Encoding, bom: bool) -> Result<Vec<u8>, String> {
+    let mut ret = vec![];
+    // encoding_rs only decodes utf-16, so it is encoded here.
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        let le = encoding == UTF_16LE;
+        if bom {
+            ret.extend(if le { [0xff, 0xfe] } else { [0xfe, 0xff] });
+        }
+        for unit in text.encode_utf16() {
+            ret.extend(if le {
+                unit.to_le_bytes()
+            } else {
+                unit.to_be_bytes()
+            });
+        }
+        return Ok(ret);
+    }
+    if bom && encoding == UTF_8 {
+        ret.extend([0xef, 0xbb, 0xbf]);
+    }
+    let (bytes, _, unmappable) = encoding.encode(text);
+    if unmappable {
+        return Err(format!(
+            "[E301] cannot convert the text to {}",
+            encoding_name(encoding)
+        ));
+    }
+    ret.extend(bytes.iter());
+    Ok(ret)
+}
+/// guess the encoding of `bytes` from the BOM, then utf-16 and utf-8 validity, then the contents.
+pub fn detect(bytes: &[u8]) -> (&'static Encoding, bool) {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return (encoding, true);
+    }
+    // checked before utf-8 since ascii in utf-16 is also valid utf-8.
+    if let Some(encoding) = detect_utf16(bytes) {
+        return (encoding, false);
+    }
+    if std::str::from_utf8(bytes).is_ok() {
+        return (UTF_8, false);
+    }
+    let mut detector = EncodingDetector::new();
+    detector.feed(bytes, true);
+    (detector.guess(None, false), false)
+}
+/// utf-16 without BOM: mostly ascii text has a zero byte in every other byte.
+fn detect_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    if bytes.len() < 2 || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = bytes.len() / 2;
+    let even = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd * 10 >= pairs * 9 && even == 0 {
+        Some(UTF_16LE)
+    } else if even * 10 >= pairs * 9 && odd == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+/// find an encoding by the names used in `fileencoding` and `++enc=`.
+pub fn encoding_from_name(name: &str) -> Option<&'static Encoding> {
+    match name.to_ascii_lowercase().as_str() {
+        "utf8" => Some(UTF_8),
+        "sjis" | "cp932" => Some(encoding_rs::SHIFT_JIS),
+        "euc-jp" | "eucjp" => Some(encoding_rs::EUC_JP),
+        "latin1" | "iso-8859-1" => Some(WINDOWS_1252),
+        "utf-16" => Some(UTF_16BE),
+        name => Encoding::for_label_no_replacement(name.as_bytes()),
+    }
+}
+/// the name shown for `fileencoding`.
+pub fn encoding_name(encoding: &'static Encoding) -> String {
+    if encoding == WINDOWS_1252 {
+        String::from("latin1")
+    } else {
+        encoding.name().to_ascii_lowercase()
+    }
+}
+#[cfg(test)]
+mod encoding_test {
+    use super::*;
+
+    #[test]
+    fn test_detect_and_round_trip() {
+        let text = "日本語のテキスト、これはテストです。\n";
+        let ascii = "fn main() {\n    println!(\"hello\");\n}\n";
+        for (text, encoding) in [
+            (text, encoding_rs::SHIFT_JIS),
+            (text, encoding_rs::EUC_JP),
+            (text, UTF_8),
+            (ascii, UTF_16LE),
+            (ascii, UTF_16BE),
+        ] {
+            let bytes = encode(text, encoding, false).unwrap();
+            let (decoded, detected, bom) = decode(&bytes);
+            assert_eq!(detected, encoding);
+            assert!(!bom);
+            assert_eq!(decoded, text);
+            assert_eq!(encode(&decoded, detected, bom).unwrap(), bytes);
+        }
+    }
+    #[test]
+    fn test_bom() {
+        let bytes = [0xfe, 0xff, 0x00, b'a', 0x00, b'\n'];
+        let (decoded, detected, bom) = decode(&bytes);
+        assert_eq!((decoded.as_str(), detected, bom), ("a\n", UTF_16BE, true));
+        assert_eq!(encode(&decoded, detected, bom).unwrap(), bytes);
+        let bytes = [0xef, 0xbb, 0xbf, b'a'];
+        assert_eq!(decode(&bytes), (String::from("a"), UTF_8, true));
+        assert_eq!(encode("a", UTF_8, true).unwrap(), bytes);
+    }
+    #[test]
+    fn test_latin1_is_lossless() {
+        let bytes: Vec<u8> = (0x20..=0xff).collect();
+        let text = decode_with(&bytes, WINDOWS_1252).unwrap().0;
+        assert_eq!(encode(&text, WINDOWS_1252, false).unwrap(), bytes);
+    }
+    #[test]
+    fn test_unmappable() {
+        assert!(encode("日本", WINDOWS_1252, false).is_err());
+        assert_eq!(encoding_from_name("sjis"), Some(encoding_rs::SHIFT_JIS));
+        assert_eq!(encoding_name(WINDOWS_1252), "latin1");
+    }
+}
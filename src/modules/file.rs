@@ -1,116 +1,706 @@
 use crate::modules::coordinate::Point;
-use std::fs::File;
+use crate::modules::encoding::{decode, decode_with, encode, encoding_name};
+use crate::modules::fileformat::{detect, from_lf, to_lf, FileFormat};
+use crate::modules::hex::{looks_binary, HexBuffer};
+use crate::modules::large::{LargeFile, LARGE_FILE_SIZE};
+use crate::modules::mark::Marks;
+use crate::modules::option::Options;
+use crate::modules::swap::{find_swap, read_records, Record, SwapFile};
+use crate::modules::watch::{DiskChange, DiskState, Watcher};
+use encoding_rs::{Encoding, UTF_8};
+use ropey::{Rope, RopeSlice};
+use std::borrow::Cow;
+use std::fs::{self, File, OpenOptions};
 use std::io::prelude::*;
 use std::io::{BufReader, BufWriter};
 
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+/// the edits refused in large-file mode, which never reads the whole file.
+const LARGE_NO_EDIT: &str = "[E509] large-file mode cannot make changes";
 #[derive(Debug)]
 pub struct FileBuffer {
-    contents: String,
-    is_read_only: bool,
+    contents: Rope,
     path: String,
     search_result: Vec<Point>,
-    search_result_index: u16,
+    search_result_index: usize,
+    /// the last search was `?`, so `n` goes backward.
+    search_backward: bool,
+    options: Options,
+    /// the file had several kinds of line endings. stray CRs are kept in the text.
+    mixed_line_endings: bool,
+    /// journal of unsaved edits, once started
+    swap: Option<SwapFile>,
+    /// the file as last read or written. None if it could not be read back.
+    disk: Option<DiskState>,
+    /// edited since the file was last read or written
+    modified: bool,
+    /// reports writes to the file by other programs, once started
+    watcher: Option<Watcher>,
+    /// the file read in blocks in large-file mode, `contents` stays empty then
+    large: Option<LargeFile>,
+    /// the bytes of a binary file in hex mode, `contents` stays empty then
+    hex: Option<HexBuffer>,
+    /// places in the text, moved along by the edits
+    marks: Marks,
 }
 
 impl FileBuffer {
     pub fn new(path: &Path) -> Result<Self, String> {
-        let mut f = match File::open(path) {
-            Ok(some) => some,
-            Err(_) => {
-                let mut f = match File::create(path) {
-                    Ok(t) => t,
-                    Err(e) => return Err(e.to_string()),
-                };
-                let _result = match f.write_all("".as_bytes()) {
-                    Ok(_) => "Ok",
-                    Err(e) => return Err(e.to_string()),
-                };
-                let f = match File::open(path) {
-                    Ok(f) => f,
-                    Err(e) => return Err(e.to_string()),
-                };
-                f
+        if fs::metadata(path).is_ok_and(|m| m.len() > LARGE_FILE_SIZE) {
+            return FileBuffer::open_large(path);
+        }
+        let bytes = read_or_create(path)?;
+        let mut buf = FileBuffer::scratch(&bytes);
+        buf.path = String::from(path.to_str().unwrap_or(""));
+        buf.disk = DiskState::new(path, &bytes);
+        buf.set_read_only(!is_writable(path));
+        Ok(buf)
+    }
+    /// open `path` in large-file mode: read as drawn, indexed in the background and
+    /// not editable.
+    /// there is no swap file or encoding detection, and search stops early.
+    pub fn open_large(path: &Path) -> Result<Self, String> {
+        let mut buf = FileBuffer::scratch(&[]);
+        buf.path = String::from(path.to_str().unwrap_or(""));
+        buf.disk = DiskState::stat(path);
+        buf.large = Some(LargeFile::open(path)?);
+        Ok(buf)
+    }
+    /// a buffer without a file, holding `bytes` read from elsewhere (stdin) or nothing.
+    pub fn scratch(bytes: &[u8]) -> FileBuffer {
+        let mut buf = FileBuffer {
+            contents: Rope::new(),
+            path: String::new(),
+            search_result: vec![],
+            search_result_index: 0,
+            search_backward: false,
+            options: Options::new(),
+            mixed_line_endings: false,
+            swap: None,
+            disk: None,
+            modified: false,
+            watcher: None,
+            large: None,
+            hex: None,
+            marks: Marks::new(),
+        };
+        if looks_binary(bytes) {
+            buf.hex = Some(HexBuffer::new(bytes.to_vec()));
+            return buf;
+        }
+        let (text, encoding, bom) = decode(bytes);
+        buf.set_text(&text, encoding, bom);
+        buf
+    }
+    /// a buffer holding `text` for tests. `path` names it, nothing is read or written there.
+    #[cfg(test)]
+    pub fn from_text(path: &str, text: &str) -> FileBuffer {
+        let mut buf = FileBuffer::scratch(text.as_bytes());
+        buf.path = String::from(path);
+        buf
+    }
+    /// replace the contents with decoded file text, normalizing its line endings.
+    fn set_text(&mut self, text: &str, encoding: &'static Encoding, bom: bool) {
+        let (format, mixed) = detect(text);
+        self.contents = Rope::from_str(&to_lf(text, format));
+        self.options.fileencoding = encoding;
+        self.options.bomb = bom;
+        self.options.fileformat = format;
+        self.mixed_line_endings = mixed;
+    }
+    /// read the file again, as `encoding` if given or detecting it otherwise.
+    pub fn reload(&mut self, encoding: Option<&'static Encoding>) -> Result<(), String> {
+        if self.path.is_empty() {
+            return Err(String::from("[E32] no file name"));
+        }
+        let path = Path::new(self.path.as_str());
+        if self.large.is_some() {
+            if encoding.is_some() {
+                return Err(String::from("[E507] large-file mode reads utf-8 only"));
             }
+            self.large = Some(LargeFile::open(path)?);
+            self.disk = DiskState::stat(path);
+            self.search_result_register(vec![]);
+            return Ok(());
+        }
+        let bytes = read_or_create(path)?;
+        // a binary file stays in hex mode unless it is read as a given encoding.
+        let hex = encoding.is_none() && (self.hex.is_some() || looks_binary(&bytes));
+        let decoded = match encoding {
+            _ if hex => None,
+            Some(e) => match decode_with(&bytes, e) {
+                Some((text, bom)) => Some((text, e, bom)),
+                None => return Err(format!("[E302] the file is not valid {}", encoding_name(e))),
+            },
+            None => Some(decode(&bytes)),
         };
-        let mut buf = Vec::new();
-        match f.read_to_end(&mut buf) {
-            Ok(_) => Ok(FileBuffer {
-                contents: String::from_utf8(buf).unwrap_or(String::from("")),
-                is_read_only: false,
-                path: String::from(path.to_str().unwrap_or("")),
-                search_result: vec![],
-                search_result_index: 0,
-            }),
-            Err(e) => Err(e.to_string()),
+        self.disk = DiskState::new(path, &bytes);
+        // `-R`, view mode and `:set ro` outlast the reload.
+        if !is_writable(path) {
+            self.set_read_only(true);
+        }
+        match decoded {
+            Some((text, encoding, bom)) => {
+                self.hex = None;
+                self.set_text(&text, encoding, bom);
+            }
+            None => {
+                self.contents = Rope::new();
+                self.hex = Some(HexBuffer::new(bytes));
+            }
+        }
+        self.search_result_register(vec![]);
+        self.modified = false;
+        if let Some(swap) = &mut self.swap {
+            swap.remove();
         }
+        Ok(())
     }
-    pub fn get_contents(&self) -> String {
-        String::from(self.contents.as_str())
+    pub fn is_hex(&self) -> bool {
+        self.hex.is_some()
     }
-    pub fn update_contents(&mut self, new_contents: String) {
-        self.contents = String::from(new_contents);
+    pub fn get_hex(&self) -> Option<&HexBuffer> {
+        self.hex.as_ref()
     }
-    pub fn save_file(&mut self) -> Result<(), String> {
-        let file = match File::create(Path::new(self.path.as_str())) {
-            Ok(some) => some,
-            Err(e) => return Err(e.to_string()),
+    /// edit the bytes in hex mode. `edit` gives None when it changed nothing,
+    /// otherwise the buffer counts as modified.
+    pub fn edit_hex<T>(&mut self, edit: impl FnOnce(&mut HexBuffer) -> Option<T>) -> Option<T> {
+        let ret = edit(self.hex.as_mut()?);
+        self.modified |= ret.is_some();
+        ret
+    }
+    /// switch between hex mode and text. the text goes to hex as the bytes it would be
+    /// saved as, and the bytes come back decoded. the swap journal only follows the text.
+    pub fn set_hex(&mut self, dst: bool) -> Result<(), String> {
+        if self.large.is_some() {
+            return Err(String::from("[E508] large-file mode has no hex mode"));
+        }
+        match (dst, self.hex.take()) {
+            (true, None) => {
+                self.hex = Some(HexBuffer::new(self.encode()?));
+                self.contents = Rope::new();
+            }
+            (false, Some(hex)) => {
+                let (text, encoding, bom) = decode(hex.get_bytes());
+                self.set_text(&text, encoding, bom);
+                self.journal(Record::Replace(self.contents.to_string()));
+            }
+            (_, hex) => self.hex = hex,
+        }
+        self.search_result_register(vec![]);
+        Ok(())
+    }
+    pub fn is_modified(&self) -> bool {
+        self.modified
+    }
+    /// how the file on disk differs from the one last read or written.
+    pub fn check_disk(&self) -> DiskChange {
+        match &self.disk {
+            Some(state) => state.compare(Path::new(self.path.as_str())).0,
+            None if Path::new(self.path.as_str()).exists() => DiskChange::Modified,
+            None => DiskChange::Unchanged,
+        }
+    }
+    /// take the file now on disk as the one the buffer is based on,
+    /// when the user keeps the buffer over a change made outside.
+    pub fn accept_disk(&mut self) {
+        let path = Path::new(self.path.as_str());
+        self.disk = if self.large.is_some() {
+            DiskState::stat(path)
+        } else {
+            DiskState::read(path)
         };
-
-        let mut writer = BufWriter::new(file);
-        let _result = match writer.write_all(self.contents.as_bytes()) {
-            Ok(_) => "Ok",
-            Err(e) => return Err(e.to_string()),
+    }
+    pub fn is_large(&self) -> bool {
+        self.large.is_some()
+    }
+    /// percent of a large file indexed so far, None when done or not in large-file mode.
+    pub fn large_progress(&self) -> Option<usize> {
+        self.large.as_ref().and_then(|l| l.progress())
+    }
+    /// the first `limit` matches of `ptn` in a large file, as (row, byte offset in the line).
+    pub fn find_large(&self, ptn: &str, limit: usize) -> Vec<(usize, usize)> {
+        match &self.large {
+            Some(l) => l.find(ptn, limit),
+            None => vec![],
+        }
+    }
+    /// watch the file for writes by other programs from now on.
+    pub fn start_watch(&mut self) {
+        if self.watcher.is_none() {
+            self.watcher = Watcher::new(Path::new(self.path.as_str()));
+        }
+    }
+    /// whether the file was touched since the last poll. our own saves are reported too,
+    /// `check_disk` tells them apart.
+    pub fn poll_watch(&mut self) -> bool {
+        match &mut self.watcher {
+            Some(w) => w.poll(),
+            None => false,
+        }
+    }
+    /// journal the edits of this buffer to a swap file from now on.
+    pub fn start_swap(&mut self) {
+        if self.swap.is_none() && !self.path.is_empty() && self.large.is_none() {
+            self.swap = Some(SwapFile::new(&self.path));
+        }
+    }
+    /// stop journaling and remove the swap file, when the buffer is closed.
+    pub fn close_swap(&mut self) {
+        if let Some(mut swap) = self.swap.take() {
+            swap.remove();
+        }
+    }
+    fn journal(&mut self, record: Record) {
+        if let Some(swap) = &mut self.swap {
+            swap.record(&record);
+        }
+    }
+    /// replay the swap file left by a crash on top of the file.
+    /// returns the number of edits recovered.
+    pub fn recover(&mut self) -> Result<usize, String> {
+        if self.large.is_some() {
+            return Err(String::from(LARGE_NO_EDIT));
+        }
+        let info = match find_swap(&self.path) {
+            Some(info) => info,
+            None => return Err(format!("[E305] no swap file found for {}", self.path)),
         };
-        let _result = match writer.flush() {
-            Ok(_) => "Ok",
-            Err(e) => return Err(e.to_string()),
+        let records = read_records(&info.path)?;
+        let swap = self.swap.take();
+        for r in &records {
+            match r {
+                Record::Insert(row, col, text) => self.insert_str(*col, *row, text),
+                Record::Remove(row, col, count) => {
+                    self.remove_chars(*col, *row, *count);
+                }
+                Record::Replace(text) => self.update_contents(text.clone()),
+            }
+        }
+        self.swap = match SwapFile::take_over(&info, &self.path) {
+            Ok(s) => Some(s),
+            Err(_) => swap,
         };
+        Ok(records.len())
+    }
+    pub fn has_mixed_line_endings(&self) -> bool {
+        self.mixed_line_endings
+    }
+    /// drop the CRs left at the end of lines of a mixed file,
+    /// so that every line gets the ending of `fileformat` on save.
+    pub fn strip_stray_cr(&mut self) {
+        if !self.mixed_line_endings {
+            return;
+        }
+        for row in (0..self.contents.len_lines()).rev() {
+            let line = self.line_slice(row);
+            let len = line.len_chars();
+            if len > 0 && line.char(len - 1) == '\r' {
+                self.remove_chars(len - 1, row, 1);
+            }
+        }
+        self.mixed_line_endings = false;
+    }
+    pub fn get_path(&self) -> &str {
+        self.path.as_str()
+    }
+    /// the path, or a placeholder for a buffer without a file.
+    pub fn get_name(&self) -> &str {
+        if self.path.is_empty() {
+            "[No Name]"
+        } else {
+            self.path.as_str()
+        }
+    }
+    /// give a buffer without a file its name, before it is written the first time.
+    pub fn set_path(&mut self, path: &str) {
+        self.path = String::from(path);
+        self.disk = None;
+    }
+    /// copy the whole contents out of the buffer.
+    /// only for tests, the editor reads with `get_line` / `get_lines`.
+    #[cfg(test)]
+    pub fn get_contents(&self) -> String {
+        if let Some(h) = &self.hex {
+            return String::from_utf8_lossy(h.get_bytes()).into_owned();
+        }
+        if let Some(l) = &self.large {
+            let mut bytes = vec![];
+            l.copy_to(&mut bytes).unwrap();
+            return String::from_utf8_lossy(&bytes).into_owned();
+        }
+        self.contents.to_string()
+    }
+    pub fn update_contents(&mut self, new_contents: String) {
+        if self.large.is_some() {
+            return;
+        }
+        self.contents = Rope::from_str(&new_contents);
+        self.modified = true;
+        self.journal(Record::Replace(new_contents));
+    }
+    pub fn save_file(&mut self) -> Result<(), String> {
+        if self.path.is_empty() {
+            return Err(String::from("[E32] no file name"));
+        }
+        // a large file takes no edits, it is on disk as it is.
+        if self.large.is_some() {
+            return Ok(());
+        }
+        self.write_file(Path::new(self.path.as_str()), self.options.backup)?;
+        // the edits are in the file now.
+        self.disk = DiskState::read(Path::new(self.path.as_str()));
+        self.modified = false;
+        if let Some(swap) = &mut self.swap {
+            swap.remove();
+        }
         Ok(())
     }
+    /// write the buffer to another file, leaving the buffer as it is.
+    pub fn write_copy(&self, path: &Path) -> Result<(), String> {
+        self.write_file(path, false)
+    }
+    /// the text as it is written to the file.
+    fn encode(&self) -> Result<Vec<u8>, String> {
+        let format = self.options.fileformat;
+        encode(
+            &from_lf(&self.contents.to_string(), format),
+            self.options.fileencoding,
+            self.options.bomb,
+        )
+    }
+    fn write_file(&self, path: &Path, backup: bool) -> Result<(), String> {
+        if let Some(l) = &self.large {
+            return write_atomic(path, backup, |writer| l.copy_to(writer));
+        }
+        if let Some(hex) = &self.hex {
+            return write_atomic(path, backup, |writer| writer.write_all(hex.get_bytes()));
+        }
+        // convert before touching the file so that an unmappable char keeps it intact.
+        let format = self.options.fileformat;
+        let encoded = if self.options.fileencoding == UTF_8 && format == FileFormat::Unix {
+            None
+        } else {
+            Some(self.encode()?)
+        };
+        let contents = &self.contents;
+        let bomb = self.options.bomb;
+        write_atomic(path, backup, |writer| match encoded {
+            Some(bytes) => writer.write_all(&bytes),
+            None if bomb => writer
+                .write_all(&[0xef, 0xbb, 0xbf])
+                .and_then(|_| contents.write_to(&mut *writer)),
+            None => contents.write_to(writer),
+        })
+    }
+    /// the buffer refuses edits and `:w` without `!`. the `readonly` option.
     pub fn get_read_only(&self) -> bool {
-        self.is_read_only
+        self.options.readonly
+    }
+    /// whether the buffer takes edits. the error is the message to show when not.
+    pub fn check_edit(&self) -> Result<(), String> {
+        if self.large.is_some() {
+            return Err(String::from(LARGE_NO_EDIT));
+        }
+        if self.options.readonly {
+            return Err(String::from(
+                "[E21] cannot make changes, the buffer is read-only (:set noro to allow)",
+            ));
+        }
+        Ok(())
     }
     pub fn set_read_only(&mut self, dst: bool) {
-        self.is_read_only = dst;
+        self.options.readonly = dst;
     }
-    pub fn get_col_length(&self, row: u16) -> u16 {
-        let mut row_count = 0;
-        if self.contents.len() < 1 {
-            return 0;
+    /// a line without its line break. empty if `row` is out of range.
+    fn line_slice(&self, row: usize) -> RopeSlice<'_> {
+        if row >= self.contents.len_lines() {
+            return self.contents.slice(0..0);
+        }
+        let line = self.contents.line(row);
+        let len = line.len_chars();
+        if len > 0 && line.char(len - 1) == '\n' {
+            line.slice(..len - 1)
         } else {
-            for col in self.contents.split('\n') {
-                if row_count == row {
-                    return col.len() as u16;
+            line
+        }
+    }
+    /// borrow a line without its line break.
+    /// the line is only copied when it spans several rope chunks.
+    pub fn get_line(&self, row: usize) -> Cow<'_, str> {
+        if let Some(h) = &self.hex {
+            return h.format_row(row).into();
+        }
+        match &self.large {
+            Some(l) => l.line(row),
+            None => self.line_slice(row).into(),
+        }
+    }
+    /// iterate lines (without line breaks) starting at `row`.
+    pub fn get_lines(&self, row: usize) -> impl Iterator<Item = Cow<'_, str>> {
+        let len = self.get_row_length();
+        (row.min(len)..len).map(|r| self.get_line(r))
+    }
+    pub fn get_row_length(&self) -> usize {
+        if let Some(h) = &self.hex {
+            return h.len_rows();
+        }
+        match &self.large {
+            Some(l) => l.len_lines(),
+            None => self.contents.len_lines(),
+        }
+    }
+    pub fn get_marks(&self) -> &Marks {
+        &self.marks
+    }
+    pub fn get_marks_mut(&mut self) -> &mut Marks {
+        &mut self.marks
+    }
+    pub fn get_options(&self) -> &Options {
+        &self.options
+    }
+    pub fn get_options_mut(&mut self) -> &mut Options {
+        &mut self.options
+    }
+    /// length of a line in chars, the unit of columns in file positions.
+    /// use `unicode::display_width` for the drawn width.
+    pub fn get_col_length(&self, row: usize) -> usize {
+        if let Some(h) = &self.hex {
+            return h.format_row(row).len();
+        }
+        match &self.large {
+            Some(l) => l.line(row).chars().count(),
+            None => self.line_slice(row).len_chars(),
+        }
+    }
+    /// the size told when the buffer is written: lines, or bytes in hex mode.
+    pub fn get_size(&self) -> String {
+        match &self.hex {
+            Some(h) => format!("{}B", h.len()),
+            None => format!("{}L", self.get_row_length()),
+        }
+    }
+    /// char index in the whole buffer of a (col, row) position.
+    /// clamps to the end of the line / buffer.
+    fn char_index(&self, col: usize, row: usize) -> usize {
+        if row >= self.contents.len_lines() {
+            return self.contents.len_chars();
+        }
+        let col = col.min(self.line_slice(row).len_chars());
+        self.contents.line_to_char(row) + col
+    }
+    /// the (col, row) position of a char index in the whole buffer.
+    fn point_of(&self, idx: usize) -> Point {
+        let row = self.contents.char_to_line(idx);
+        Point {
+            col: idx - self.contents.line_to_char(row),
+            row,
+        }
+    }
+    /// the text from `start` up to `end`, line breaks included.
+    pub fn get_text(&self, start: Point, end: Point) -> String {
+        if let Some(l) = &self.large {
+            let last = l.len_lines().saturating_sub(1);
+            let mut text = String::new();
+            for row in start.row..=end.row.min(last) {
+                let from = if row == start.row { start.col } else { 0 };
+                let to = if row == end.row { end.col } else { usize::MAX };
+                text.extend(l.line(row).chars().take(to).skip(from));
+                if row < end.row && row < last {
+                    text.push('\n');
                 }
-                row_count = row_count + 1;
             }
-            return 0;
-        };
+            return text;
+        }
+        let a = self.char_index(start.col, start.row);
+        let b = self.char_index(end.col, end.row);
+        self.contents.slice(a..b.max(a)).to_string()
+    }
+    pub fn insert_str(&mut self, col: usize, row: usize, text: &str) {
+        // large-file mode takes no edits, `check_edit` tells.
+        if self.large.is_some() {
+            return;
+        }
+        let idx = self.char_index(col, row);
+        let at = self.point_of(idx);
+        self.contents.insert(idx, text);
+        self.modified = true;
+        self.marks.inserted(at, text);
+        self.journal(Record::Insert(row, col, String::from(text)));
     }
-    pub fn get_row_length(&self) -> u16 {
-        let v: Vec<&str> = self.contents.split('\n').collect();
-        v.len() as u16
+    /// remove `count` chars starting at (col, row). line breaks count as one char.
+    /// returns the removed chars.
+    pub fn remove_chars(&mut self, col: usize, row: usize, count: usize) -> Vec<char> {
+        if self.large.is_some() {
+            return vec![];
+        }
+        let start = self.char_index(col, row);
+        let end = (start + count).min(self.contents.len_chars());
+        let removed: Vec<char> = self.contents.slice(start..end).chars().collect();
+        self.contents.remove(start..end);
+        if !removed.is_empty() {
+            self.modified = true;
+            self.marks.removed(self.point_of(start), &removed);
+            self.journal(Record::Remove(row, col, removed.len()));
+        }
+        removed
     }
     pub fn search_result_register(&mut self, result: Vec<Point>) {
         self.search_result_index = 0;
         self.search_result = result;
     }
+    /// the first match after `from`, wrapping around to the first one.
+    pub fn get_searchresult_after(&mut self, from: Point) -> Option<Point> {
+        if self.search_result.is_empty() {
+            return None;
+        }
+        self.search_result_index = self
+            .search_result
+            .iter()
+            .position(|p| (p.row, p.col) > (from.row, from.col))
+            .unwrap_or(0);
+        Some(self.search_result[self.search_result_index])
+    }
+    /// the last match before `from`, wrapping around to the last one.
+    pub fn get_searchresult_before(&mut self, from: Point) -> Option<Point> {
+        if self.search_result.is_empty() {
+            return None;
+        }
+        self.search_result_index = self
+            .search_result
+            .iter()
+            .rposition(|p| (p.row, p.col) < (from.row, from.col))
+            .unwrap_or(self.search_result.len() - 1);
+        Some(self.search_result[self.search_result_index])
+    }
+    pub fn set_search_backward(&mut self, backward: bool) {
+        self.search_backward = backward;
+    }
+    pub fn is_search_backward(&self) -> bool {
+        self.search_backward
+    }
+    /// the match `n` goes to, in the direction of the last search. `N` is `reverse`.
+    pub fn repeat_search(&mut self, reverse: bool) -> Option<Point> {
+        if self.search_backward != reverse {
+            self.get_prev_searchresult()
+        } else {
+            self.get_next_searchresult()
+        }
+    }
+    pub fn get_prev_searchresult(&mut self) -> Option<Point> {
+        if !self.search_result.is_empty() {
+            // wrap around before the first match.
+            let len = self.search_result.len();
+            self.search_result_index = (self.search_result_index + len - 1) % len;
+            Some(self.search_result[self.search_result_index])
+        } else {
+            None
+        }
+    }
     pub fn get_next_searchresult(&mut self) -> Option<Point> {
-        if self.search_result.len() > 0 {
-            self.search_result_index += 1;
-            Some(Point {
-                col: self.search_result[self.search_result_index as usize].col,
-                row: self.search_result[self.search_result_index as usize].row,
-            })
+        if !self.search_result.is_empty() {
+            // wrap around after the last match.
+            self.search_result_index = (self.search_result_index + 1) % self.search_result.len();
+            Some(self.search_result[self.search_result_index])
         } else {
             None
         }
     }
 }
+/// replace the file at `path` without leaving it truncated on a failure.
+/// the contents go to a temp file in the same directory, which is synced and renamed
+/// over the file. a symlink is kept and its target is replaced. the mode and, where
+/// allowed, the owner of the old file are kept. with `backup` the old file is copied
+/// to `path~` first.
+fn write_atomic<F>(path: &Path, backup: bool, write: F) -> Result<(), String>
+where
+    F: FnOnce(&mut BufWriter<File>) -> std::io::Result<()>,
+{
+    let target = match fs::canonicalize(path) {
+        Ok(p) => p,
+        Err(_) => path.to_path_buf(),
+    };
+    let dir = match target.parent() {
+        Some(d) if !d.as_os_str().is_empty() => d.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let name = match target.file_name() {
+        Some(n) => n.to_string_lossy().into_owned(),
+        None => return Err(format!("[E502] not a file: {}", path.display())),
+    };
+    let old = fs::metadata(&target).ok();
+    let tmp = dir.join(format!(".{}.{}.tmp", name, std::process::id()));
+    let result = (|| -> std::io::Result<()> {
+        let file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        let mut writer = BufWriter::new(file);
+        write(&mut writer)?;
+        let file = writer.into_inner().map_err(|e| e.into_error())?;
+        if let Some(meta) = &old {
+            file.set_permissions(meta.permissions())?;
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                // only root can give a file away, keep at least the group then.
+                if std::os::unix::fs::fchown(&file, Some(meta.uid()), Some(meta.gid())).is_err() {
+                    let _ = std::os::unix::fs::fchown(&file, None, Some(meta.gid()));
+                }
+            }
+        }
+        file.sync_all()?;
+        if backup && old.is_some() {
+            let mut bak = target.clone().into_os_string();
+            bak.push("~");
+            fs::copy(&target, bak)?;
+        }
+        fs::rename(&tmp, &target)?;
+        // make the rename itself durable.
+        if let Ok(d) = File::open(&dir) {
+            let _ = d.sync_all();
+        }
+        Ok(())
+    })();
+    match result {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            Err(format!("[E212] cannot write {}: {}", path.display(), e))
+        }
+    }
+}
+/// whether this process may write to the file. opened for append, so nothing changes.
+fn is_writable(path: &Path) -> bool {
+    OpenOptions::new().append(true).open(path).is_ok()
+}
+/// read the whole file, creating it when it does not exist yet.
+fn read_or_create(path: &Path) -> Result<Vec<u8>, String> {
+    let f = match File::open(path) {
+        Ok(some) => some,
+        Err(_) => match File::create(path) {
+            Ok(_) => return Ok(vec![]),
+            Err(e) => return Err(e.to_string()),
+        },
+    };
+    let mut buf = Vec::new();
+    match BufReader::new(f).read_to_end(&mut buf) {
+        Ok(_) => Ok(buf),
+        Err(e) => Err(e.to_string()),
+    }
+}
 #[cfg(test)]
+// the first tests are kept as they were written.
+#[allow(
+    non_snake_case,
+    clippy::bool_assert_comparison,
+    clippy::needless_borrow
+)]
 mod FileTest {
     use super::FileBuffer;
+    use crate::modules::fileformat::FileFormat;
+
+    fn test_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("ve_file_test_{}", name))
+    }
 
     #[test]
     fn test_read_write_contents() {
@@ -131,6 +721,18 @@ mod FileTest {
         assert!(!buf.get_read_only());
     }
     #[test]
+    #[cfg(unix)]
+    fn test_read_only_file() {
+        use std::os::unix::fs::PermissionsExt;
+        let p = test_path("read_only_file.txt");
+        std::fs::write(&p, "abc\n").unwrap();
+        std::fs::set_permissions(&p, std::fs::Permissions::from_mode(0o444)).unwrap();
+        // root writes anyway, so the check follows what the file allows.
+        let writable = std::fs::OpenOptions::new().append(true).open(&p).is_ok();
+        assert_eq!(FileBuffer::new(&p).unwrap().get_read_only(), !writable);
+        std::fs::remove_file(&p).unwrap();
+    }
+    #[test]
     fn test_save_file() {
         let p = std::path::Path::new("test.txt");
         let mut buf = FileBuffer::new(&p).unwrap();
@@ -152,4 +754,241 @@ mod FileTest {
         assert_eq!(buf.get_col_length(1), 10);
         assert_eq!(buf.get_col_length(2), 10);
     }
+    #[test]
+    fn test_get_line() {
+        let p = test_path("get_line.txt");
+        let mut buf = FileBuffer::new(&p).unwrap();
+        buf.update_contents(String::from("abc\r\ndef\n\nghi"));
+        assert_eq!(buf.get_line(0), "abc\r");
+        assert_eq!(buf.get_line(1), "def");
+        assert_eq!(buf.get_line(2), "");
+        assert_eq!(buf.get_line(3), "ghi");
+        assert_eq!(buf.get_line(4), "");
+        let lines: Vec<String> = buf.get_lines(1).map(|l| l.into_owned()).collect();
+        assert_eq!(lines, vec!["def", "", "ghi"]);
+    }
+    #[test]
+    fn test_many_lines() {
+        use crate::modules::coordinate::Point;
+        use crate::modules::history::Operation;
+        use crate::modules::undo::Undo;
+        let p = test_path("many_lines.txt");
+        let text: String = (0..70000).map(|i| format!("{}\n", i)).collect();
+        std::fs::write(&p, &text).unwrap();
+        let mut buf = FileBuffer::new(&p).unwrap();
+        // past what a u16 holds.
+        assert_eq!(buf.get_row_length(), 70001);
+        assert_eq!(buf.get_line(69999), "69999");
+        let found = crate::modules::search::search_string(&buf, "69999");
+        assert_eq!(found, [Point { col: 0, row: 69999 }]);
+        let pos = Point { col: 5, row: 66000 };
+        buf.insert_str(pos.col, pos.row, "!");
+        assert_eq!(buf.get_line(66000), "66000!");
+        let mut undo = Undo::new();
+        undo.add_do_history(Operation::ADD, vec!['!'], pos);
+        assert_eq!(undo.undo(&mut buf), pos);
+        assert_eq!(buf.get_line(66000), "66000");
+    }
+    #[test]
+    fn test_insert_remove() {
+        let p = test_path("insert_remove.txt");
+        let mut buf = FileBuffer::new(&p).unwrap();
+        buf.update_contents(String::from("abc\ndef"));
+        buf.insert_str(1, 1, "XY");
+        assert_eq!(buf.get_contents(), "abc\ndXYef");
+        assert_eq!(buf.remove_chars(3, 0, 1), vec!['\n']);
+        assert_eq!(buf.get_contents(), "abcdXYef");
+        assert_eq!(buf.remove_chars(4, 0, 2), vec!['X', 'Y']);
+        assert_eq!(buf.get_contents(), "abcdef");
+        assert_eq!(buf.remove_chars(6, 0, 1), vec![]);
+    }
+    #[test]
+    fn test_encoding_round_trip() {
+        let p = test_path("encoding_round_trip.txt");
+        // "テスト\n" in shift_jis
+        let bytes = [0x83, 0x65, 0x83, 0x58, 0x83, 0x67, 0x0a];
+        std::fs::write(&p, bytes).unwrap();
+        let mut buf = FileBuffer::new(&p).unwrap();
+        assert_eq!(buf.get_contents(), "テスト\n");
+        assert_eq!(buf.get_options().fileencoding, encoding_rs::SHIFT_JIS);
+        buf.insert_str(3, 0, "!");
+        assert_eq!(buf.save_file(), Ok(()));
+        assert_eq!(
+            std::fs::read(&p).unwrap(),
+            [0x83, 0x65, 0x83, 0x58, 0x83, 0x67, b'!', 0x0a]
+        );
+        buf.reload(Some(encoding_rs::WINDOWS_1252)).unwrap();
+        assert_eq!(buf.get_col_length(0), 7);
+        assert!(buf.reload(Some(encoding_rs::UTF_8)).is_err());
+    }
+    #[test]
+    fn test_line_endings() {
+        let p = test_path("line_endings.txt");
+        for (text, format, mixed) in [
+            ("a\r\nb\r\n", FileFormat::Dos, false),
+            ("a\rb\r", FileFormat::Mac, false),
+            ("a\r\nb\n", FileFormat::Unix, true),
+        ] {
+            std::fs::write(&p, text).unwrap();
+            let mut buf = FileBuffer::new(&p).unwrap();
+            assert_eq!(buf.get_options().fileformat, format);
+            assert_eq!(buf.has_mixed_line_endings(), mixed);
+            assert_eq!(buf.get_line(1), "b");
+            assert_eq!(buf.save_file(), Ok(()));
+            assert_eq!(std::fs::read_to_string(&p).unwrap(), text);
+        }
+        let mut buf = FileBuffer::new(&p).unwrap();
+        buf.get_options_mut().fileformat = FileFormat::Dos;
+        buf.strip_stray_cr();
+        assert_eq!(buf.save_file(), Ok(()));
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "a\r\nb\r\n");
+    }
+    #[test]
+    #[cfg(unix)]
+    fn test_atomic_save() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_path("atomic_save");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir(&dir).unwrap();
+        let real = dir.join("real.txt");
+        let link = dir.join("link.txt");
+        std::fs::write(&real, "old\n").unwrap();
+        std::fs::set_permissions(&real, std::fs::Permissions::from_mode(0o640)).unwrap();
+        std::os::unix::fs::symlink(&real, &link).unwrap();
+
+        let mut buf = FileBuffer::new(&link).unwrap();
+        buf.get_options_mut().backup = true;
+        buf.insert_str(0, 0, "new ");
+        assert_eq!(buf.save_file(), Ok(()));
+        assert!(std::fs::symlink_metadata(&link)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(std::fs::read_to_string(&real).unwrap(), "new old\n");
+        assert_eq!(
+            std::fs::metadata(&real).unwrap().permissions().mode() & 0o777,
+            0o640
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("real.txt~")).unwrap(),
+            "old\n"
+        );
+        // nothing but the file, its link and the backup is left behind.
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+    }
+    #[test]
+    fn test_recover() {
+        let p = test_path("recover.txt");
+        let _ = std::fs::remove_file(std::env::temp_dir().join(".ve_file_test_recover.txt.swp"));
+        std::fs::write(&p, "abc\ndef\n").unwrap();
+        {
+            let mut buf = FileBuffer::new(&p).unwrap();
+            buf.start_swap();
+            buf.insert_str(1, 1, "X\nY");
+            buf.remove_chars(0, 0, 2);
+            // dropped without saving, as in a crash.
+        }
+        let mut buf = FileBuffer::new(&p).unwrap();
+        assert_eq!(buf.get_contents(), "abc\ndef\n");
+        assert_eq!(buf.recover(), Ok(2));
+        assert_eq!(buf.get_contents(), "c\ndX\nYef\n");
+        // the journal goes on in the recovered swap file and is gone after a save.
+        buf.insert_str(0, 0, "!");
+        assert_eq!(buf.save_file(), Ok(()));
+        assert!(crate::modules::swap::find_swap(p.to_str().unwrap()).is_none());
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "!c\ndX\nYef\n");
+    }
+    #[test]
+    fn test_large_file() {
+        use crate::modules::coordinate::Point;
+        let p = test_path("large_file.txt");
+        std::fs::write(&p, "abc\ndef\n").unwrap();
+        let mut buf = FileBuffer::open_large(&p).unwrap();
+        buf.start_swap();
+        assert!(buf.is_large() && !buf.get_read_only());
+        while buf.large_progress().is_some() {
+            std::thread::yield_now();
+        }
+        assert_eq!(buf.get_row_length(), 3);
+        assert_eq!(buf.get_line(1), "def");
+        assert_eq!(buf.get_col_length(0), 3);
+        assert_eq!(
+            buf.get_text(Point { col: 1, row: 0 }, Point { col: 2, row: 1 }),
+            "bc\nde"
+        );
+        assert!(buf.swap.is_none());
+        assert_eq!(buf.save_file(), Ok(()));
+        assert_eq!(std::fs::read_to_string(&p).unwrap(), "abc\ndef\n");
+        let copy = test_path("large_file_copy.txt");
+        assert_eq!(buf.write_copy(&copy), Ok(()));
+        assert_eq!(std::fs::read_to_string(&copy).unwrap(), "abc\ndef\n");
+        assert!(buf.check_edit().is_err());
+        buf.insert_str(1, 1, "X");
+        assert_eq!(buf.get_line(1), "def");
+        assert!(!buf.is_modified());
+    }
+    #[test]
+    fn test_hex_mode() {
+        let p = test_path("hex_mode.bin");
+        let bytes = [0x7f, b'E', b'L', b'F', 0x00, 0xff, b'\r', b'\n', 0x80];
+        std::fs::write(&p, bytes).unwrap();
+        let mut buf = FileBuffer::new(&p).unwrap();
+        assert!(buf.is_hex());
+        assert_eq!(buf.get_row_length(), 1);
+        assert!(buf.get_line(0).ends_with("|.ELF.....|"));
+        let found = crate::modules::search::search_string(&buf, "ff 0d");
+        assert_eq!(found.len(), 1);
+        assert_eq!(crate::modules::hex::offset_at(found[0]), (5, 0));
+        assert_eq!(buf.edit_hex(|h| h.undo()), None);
+        assert!(!buf.is_modified());
+        buf.edit_hex(|h| {
+            h.insert(0, 0x01);
+            Some(())
+        });
+        assert!(buf.is_modified());
+        assert_eq!(buf.save_file(), Ok(()));
+        let mut saved = vec![0x01];
+        saved.extend_from_slice(&bytes);
+        assert_eq!(std::fs::read(&p).unwrap(), saved);
+
+        // a text file goes to hex as the bytes it is saved as.
+        std::fs::write(&p, "a\r\nb\r\n").unwrap();
+        buf.reload(None).unwrap();
+        assert!(buf.is_hex());
+        buf.reload(Some(encoding_rs::UTF_8)).unwrap();
+        assert!(!buf.is_hex());
+        buf.set_hex(true).unwrap();
+        assert_eq!(buf.get_hex().unwrap().get_bytes(), b"a\r\nb\r\n");
+        buf.set_hex(false).unwrap();
+        assert_eq!(buf.get_line(1), "b");
+        assert_eq!(buf.get_options().fileformat, FileFormat::Dos);
+    }
+    #[test]
+    fn test_external_change() {
+        use crate::modules::watch::DiskChange;
+        let p = test_path("external_change.txt");
+        std::fs::write(&p, "abc\n").unwrap();
+        let mut buf = FileBuffer::new(&p).unwrap();
+        assert!(!buf.is_modified());
+        assert_eq!(buf.check_disk(), DiskChange::Unchanged);
+        buf.insert_str(0, 0, "x");
+        assert!(buf.is_modified());
+        assert_eq!(buf.save_file(), Ok(()));
+        assert!(!buf.is_modified());
+        // our own write is no change.
+        assert_eq!(buf.check_disk(), DiskChange::Unchanged);
+
+        std::fs::write(&p, "changed outside\n").unwrap();
+        assert_eq!(buf.check_disk(), DiskChange::Modified);
+        buf.accept_disk();
+        assert_eq!(buf.check_disk(), DiskChange::Unchanged);
+        std::fs::write(&p, "changed again\n").unwrap();
+        buf.set_read_only(true);
+        buf.reload(None).unwrap();
+        assert_eq!(buf.get_line(0), "changed again");
+        assert!(buf.get_read_only());
+        assert_eq!(buf.check_disk(), DiskChange::Unchanged);
+        std::fs::remove_file(&p).unwrap();
+        assert_eq!(buf.check_disk(), DiskChange::Deleted);
+    }
 }
@@ -0,0 +1,130 @@
+use loe::{Transform, TransformMode};
+
+const LF: u8 = b'\n';
+const CR: u8 = b'\r';
+
+/// line ending of a file. the buffer always uses LF.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FileFormat {
+    Unix,
+    Dos,
+    Mac,
+}
+
+impl FileFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<FileFormat> {
+        match name {
+            "unix" => Some(FileFormat::Unix),
+            "dos" => Some(FileFormat::Dos),
+            "mac" => Some(FileFormat::Mac),
+            _ => None,
+        }
+    }
+}
+/// find the line ending of `text` and whether several kinds are mixed.
+/// a mixed file is read as unix so that the stray CRs stay in the text
+/// and the file is written back unchanged.
+pub fn detect(text: &str) -> (FileFormat, bool) {
+    let bytes = text.as_bytes();
+    let (mut crlf, mut lf, mut cr) = (0, 0, 0);
+    for (i, b) in bytes.iter().enumerate() {
+        match *b {
+            LF if i > 0 && bytes[i - 1] == CR => crlf += 1,
+            LF => lf += 1,
+            CR if bytes.get(i + 1) != Some(&LF) => cr += 1,
+            _ => (),
+        }
+    }
+    let kinds = [crlf, lf, cr].iter().filter(|n| **n > 0).count();
+    let format = if crlf > 0 && kinds == 1 {
+        FileFormat::Dos
+    } else if cr > 0 && kinds == 1 {
+        FileFormat::Mac
+    } else {
+        FileFormat::Unix
+    };
+    (format, kinds > 1)
+}
+/// CR to LF, for reading mac files.
+struct CrToLf;
+impl Transform for CrToLf {
+    fn transform_buffer(&mut self, i: usize, o: usize, input: &[u8], output: &mut [u8]) -> usize {
+        output[o] = if input[i] == CR { LF } else { input[i] };
+        o + 1
+    }
+}
+/// LF to CR, for writing mac files.
+struct LfToCr;
+impl Transform for LfToCr {
+    fn transform_buffer(&mut self, i: usize, o: usize, input: &[u8], output: &mut [u8]) -> usize {
+        output[o] = if input[i] == LF { CR } else { input[i] };
+        o + 1
+    }
+}
+/// run a loe transform over the whole text.
+/// `loe::process` only takes its own transform modes, so the buffer is driven here.
+fn run(text: &str, mut transform: Box<dyn Transform>) -> String {
+    let input = text.as_bytes();
+    // CRLF output is at most twice as long as the input.
+    let mut output = vec![0; input.len() * 2];
+    let mut o = 0;
+    for i in 0..input.len() {
+        o = transform.transform_buffer(i, o, input, &mut output);
+    }
+    output.truncate(o);
+    // only ascii CR and LF bytes are changed, so the text stays utf-8.
+    String::from_utf8(output).unwrap()
+}
+/// convert the line endings of `text` from `format` to LF.
+pub fn to_lf(text: &str, format: FileFormat) -> String {
+    match format {
+        FileFormat::Unix => String::from(text),
+        FileFormat::Dos => run(text, TransformMode::Lf.into()),
+        FileFormat::Mac => run(text, Box::new(CrToLf)),
+    }
+}
+/// convert the LF line endings of `text` to `format`.
+pub fn from_lf(text: &str, format: FileFormat) -> String {
+    match format {
+        FileFormat::Unix => String::from(text),
+        FileFormat::Dos => run(text, TransformMode::Crlf.into()),
+        FileFormat::Mac => run(text, Box::new(LfToCr)),
+    }
+}
+#[cfg(test)]
+mod fileformat_test {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(detect("a\nb\n"), (FileFormat::Unix, false));
+        assert_eq!(detect("a\r\nb\r\n"), (FileFormat::Dos, false));
+        assert_eq!(detect("a\rb\r"), (FileFormat::Mac, false));
+        assert_eq!(detect("a\r\nb\n"), (FileFormat::Unix, true));
+        assert_eq!(detect("a\r\nb\r"), (FileFormat::Unix, true));
+        assert_eq!(detect("abc"), (FileFormat::Unix, false));
+    }
+    #[test]
+    fn test_round_trip() {
+        for (text, format) in [
+            ("a\r\nあ\r\n", FileFormat::Dos),
+            ("a\rあ\r", FileFormat::Mac),
+            ("a\r\nあ\n", FileFormat::Unix),
+        ] {
+            let (detected, _) = detect(text);
+            assert_eq!(detected, format);
+            let normalized = to_lf(text, detected);
+            assert_eq!(from_lf(&normalized, detected), text);
+        }
+        assert_eq!(to_lf("a\r\nb\r\n", FileFormat::Dos), "a\nb\n");
+        assert_eq!(to_lf("a\rb\r", FileFormat::Mac), "a\nb\n");
+        assert_eq!(from_lf("a\nb", FileFormat::Dos), "a\r\nb");
+    }
+}
@@ -0,0 +1,382 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::mode::MODE;
+use crate::modules::show::Display;
+use crossterm::cursor::SetCursorStyle;
+use crossterm::event::KeyCode;
+
+// hex mode. the buffer holds the bytes of the file and shows 16 of them a row:
+//   00000010  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|
+// the cursor is on a hex digit. the bytes are written back as they are.
+
+pub const ROW_BYTES: usize = 16;
+/// column of the first hex digit, after the offset.
+const HEX_COL: usize = 10;
+/// column of the ascii part.
+const ASCII_COL: usize = HEX_COL + ROW_BYTES * 3 + 3;
+/// bytes looked at to tell a binary file.
+const SAMPLE: usize = 8000;
+
+/// an edit of the bytes, kept to undo it.
+#[derive(Debug)]
+struct HexEdit {
+    offset: usize,
+    /// bytes that were at `offset` before
+    removed: Vec<u8>,
+    /// bytes put at `offset`
+    inserted: usize,
+}
+#[derive(Debug)]
+pub struct HexBuffer {
+    bytes: Vec<u8>,
+    undo: Vec<HexEdit>,
+}
+
+impl HexBuffer {
+    pub fn new(bytes: Vec<u8>) -> HexBuffer {
+        HexBuffer {
+            bytes,
+            undo: vec![],
+        }
+    }
+    pub fn get_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+    pub fn len(&self) -> usize {
+        self.bytes.len()
+    }
+    /// rows shown. the last one has room for a byte after the end, to append.
+    pub fn len_rows(&self) -> usize {
+        self.bytes.len() / ROW_BYTES + 1
+    }
+    /// the text of a row: offset, hex bytes and the bytes as ascii.
+    pub fn format_row(&self, row: usize) -> String {
+        let start = row * ROW_BYTES;
+        if start > self.bytes.len() {
+            return String::new();
+        }
+        let bytes = &self.bytes[start..(start + ROW_BYTES).min(self.bytes.len())];
+        let mut ret = format!("{:08x}  ", start);
+        for i in 0..ROW_BYTES {
+            if i == ROW_BYTES / 2 {
+                ret.push(' ');
+            }
+            match bytes.get(i) {
+                Some(b) => ret.push_str(&format!("{:02x} ", b)),
+                None => ret.push_str("   "),
+            }
+        }
+        ret.push_str(" |");
+        for b in bytes {
+            ret.push(if b.is_ascii_graphic() || *b == b' ' {
+                *b as char
+            } else {
+                '.'
+            });
+        }
+        ret.push('|');
+        ret
+    }
+    /// replace a hex digit of the byte at `offset`. at the end a byte is appended.
+    pub fn set_nibble(&mut self, offset: usize, nibble: usize, value: u8) {
+        let old = self.bytes.get(offset).copied();
+        let byte = old.unwrap_or(0);
+        let byte = if nibble == 0 {
+            (byte & 0x0f) | (value << 4)
+        } else {
+            (byte & 0xf0) | value
+        };
+        match old {
+            Some(_) => self.bytes[offset] = byte,
+            None => self.bytes.push(byte),
+        }
+        self.undo.push(HexEdit {
+            offset,
+            removed: old.into_iter().collect(),
+            inserted: 1,
+        });
+    }
+    pub fn insert(&mut self, offset: usize, byte: u8) {
+        let offset = offset.min(self.bytes.len());
+        self.bytes.insert(offset, byte);
+        self.undo.push(HexEdit {
+            offset,
+            removed: vec![],
+            inserted: 1,
+        });
+    }
+    pub fn remove(&mut self, offset: usize) -> Option<u8> {
+        if offset >= self.bytes.len() {
+            return None;
+        }
+        let byte = self.bytes.remove(offset);
+        self.undo.push(HexEdit {
+            offset,
+            removed: vec![byte],
+            inserted: 0,
+        });
+        Some(byte)
+    }
+    /// undo the last edit. returns where it was.
+    pub fn undo(&mut self) -> Option<usize> {
+        let edit = self.undo.pop()?;
+        self.bytes
+            .splice(edit.offset..edit.offset + edit.inserted, edit.removed);
+        Some(edit.offset)
+    }
+    /// offsets of every occurrence of `ptn`.
+    pub fn find(&self, ptn: &[u8]) -> Vec<usize> {
+        if ptn.is_empty() {
+            return vec![];
+        }
+        memchr::memmem::find_iter(&self.bytes, ptn).collect()
+    }
+}
+/// a file with NUL bytes that is not utf-16 is taken as binary, as git and grep do.
+pub fn looks_binary(bytes: &[u8]) -> bool {
+    let sample = &bytes[..bytes.len().min(SAMPLE)];
+    if !sample.contains(&0) {
+        return false;
+    }
+    let (encoding, _) = crate::modules::encoding::detect(sample);
+    encoding != encoding_rs::UTF_16LE && encoding != encoding_rs::UTF_16BE
+}
+/// a search pattern as bytes: hex digits like `de ad be ef`, or the text itself.
+pub fn parse_pattern(ptn: &str) -> Vec<u8> {
+    let digits: Vec<u8> = ptn
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .map_while(|c| c.to_digit(16).map(|d| d as u8))
+        .collect();
+    let len = ptn.chars().filter(|c| !c.is_whitespace()).count();
+    if digits.is_empty() || digits.len() != len || len % 2 != 0 {
+        return ptn.as_bytes().to_vec();
+    }
+    digits.chunks(2).map(|d| (d[0] << 4) | d[1]).collect()
+}
+/// the screen position of a hex digit.
+pub fn point_of(offset: usize, nibble: usize) -> Point {
+    let i = offset % ROW_BYTES;
+    let gap = if i >= ROW_BYTES / 2 { 1 } else { 0 };
+    Point {
+        col: HEX_COL + i * 3 + gap + nibble,
+        row: offset / ROW_BYTES,
+    }
+}
+/// the byte and hex digit at a position. the offset and ascii parts map to the nearest byte.
+pub fn offset_at(point: Point) -> (usize, usize) {
+    let col = point.col;
+    let row = point.row * ROW_BYTES;
+    if col < HEX_COL {
+        return (row, 0);
+    }
+    if col >= ASCII_COL {
+        return (row + (col - ASCII_COL).min(ROW_BYTES - 1), 0);
+    }
+    let mut col = col - HEX_COL;
+    if col >= ROW_BYTES / 2 * 3 {
+        col = col.saturating_sub(1).max(ROW_BYTES / 2 * 3);
+    }
+    let i = (col / 3).min(ROW_BYTES - 1);
+    (row + i, (col % 3).min(1))
+}
+
+/// keys of a buffer in hex mode.
+pub struct Hex {
+    /// typed hex digits replace the ones under the cursor instead of inserting bytes
+    overwrite: bool,
+    /// the first hex digit of a byte being inserted
+    pending: Option<u8>,
+}
+
+impl Hex {
+    pub fn new() -> Hex {
+        Hex {
+            overwrite: false,
+            pending: None,
+        }
+    }
+    pub fn proc_normal(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        buf: &mut FileBuffer,
+    ) -> MODE {
+        let (offset, nibble) = offset_at(display.get_cursor_coordinate_in_file());
+        let len = buf.get_hex().map_or(0, |h| h.len());
+        let edit = matches!(
+            code,
+            KeyCode::Char('i') | KeyCode::Char('R') | KeyCode::Char('x') | KeyCode::Char('u')
+        );
+        if let (true, Err(e)) = (edit, buf.check_edit()) {
+            display.update_info_line(&e);
+            return MODE::Normal;
+        }
+        let (offset, nibble) = match code {
+            KeyCode::Char(':') => return MODE::Command,
+            KeyCode::Char(c @ ('/' | '?')) => {
+                buf.set_search_backward(c == '?');
+                return MODE::Search;
+            }
+            KeyCode::Char(c @ ('n' | 'N')) => {
+                if let Some(point) = buf.repeat_search(c == 'N') {
+                    display.move_to_point(buf, point);
+                }
+                return MODE::Normal;
+            }
+            KeyCode::Char('i') | KeyCode::Char('R') => {
+                self.overwrite = code == KeyCode::Char('R');
+                self.pending = None;
+                display.set_cursor_type(SetCursorStyle::BlinkingBar);
+                return MODE::Insert;
+            }
+            KeyCode::Char('h') | KeyCode::Left => match nibble {
+                1 => (offset, 0),
+                _ if offset > 0 => (offset - 1, 1),
+                _ => (0, 0),
+            },
+            KeyCode::Char('l') | KeyCode::Right if offset < len => match nibble {
+                0 => (offset, 1),
+                _ => (offset + 1, 0),
+            },
+            KeyCode::Char('j') | KeyCode::Down => ((offset + ROW_BYTES).min(len), nibble),
+            KeyCode::Char('k') | KeyCode::Up => (offset.saturating_sub(ROW_BYTES), nibble),
+            KeyCode::Char('0') | KeyCode::Home => (offset - offset % ROW_BYTES, 0),
+            KeyCode::Char('$') | KeyCode::End => {
+                ((offset - offset % ROW_BYTES + ROW_BYTES - 1).min(len), 0)
+            }
+            KeyCode::Char('x') => {
+                buf.edit_hex(|h| h.remove(offset));
+                (offset.min(len.saturating_sub(1)), 0)
+            }
+            KeyCode::Char('u') => match buf.edit_hex(|h| h.undo()) {
+                Some(at) => (at, 0),
+                None => (offset, nibble),
+            },
+            _ => (offset, nibble),
+        };
+        if edit {
+            display.update_all(buf).unwrap();
+        }
+        display.jump_to(buf, point_of(offset, nibble));
+        MODE::Normal
+    }
+    pub fn proc_insert(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        buf: &mut FileBuffer,
+    ) -> MODE {
+        let (offset, nibble) = offset_at(display.get_cursor_coordinate_in_file());
+        let (offset, nibble) = match code {
+            KeyCode::Esc => {
+                self.pending = None;
+                display.set_cursor_type(SetCursorStyle::SteadyBlock);
+                return MODE::Normal;
+            }
+            KeyCode::Char(c) if c.is_ascii_hexdigit() => {
+                let value = c.to_digit(16).unwrap() as u8;
+                if self.overwrite {
+                    buf.edit_hex(|h| {
+                        h.set_nibble(offset, nibble, value);
+                        Some(())
+                    });
+                    if nibble == 0 {
+                        (offset, 1)
+                    } else {
+                        (offset + 1, 0)
+                    }
+                } else {
+                    match self.pending.take() {
+                        // the first digit waits for the second one.
+                        None => {
+                            self.pending = Some(value);
+                            display.update_info_line(&format!("-- INSERT -- {:x}_", value));
+                            return MODE::Insert;
+                        }
+                        Some(high) => {
+                            buf.edit_hex(|h| {
+                                h.insert(offset, (high << 4) | value);
+                                Some(())
+                            });
+                            (offset + 1, 0)
+                        }
+                    }
+                }
+            }
+            KeyCode::Backspace if self.pending.is_some() => {
+                self.pending = None;
+                (offset, nibble)
+            }
+            KeyCode::Backspace if self.overwrite => match nibble {
+                1 => (offset, 0),
+                _ => (offset.saturating_sub(1), 1.min(offset)),
+            },
+            KeyCode::Backspace if offset > 0 => {
+                buf.edit_hex(|h| h.remove(offset - 1));
+                (offset - 1, 0)
+            }
+            _ => return MODE::Insert,
+        };
+        display.update_info_line(if self.overwrite {
+            "-- REPLACE --"
+        } else {
+            "-- INSERT --"
+        });
+        display.update_all(buf).unwrap();
+        display.jump_to(buf, point_of(offset, nibble));
+        MODE::Insert
+    }
+}
+#[cfg(test)]
+mod hex_test {
+    use super::*;
+
+    #[test]
+    fn test_format_and_positions() {
+        let hex = HexBuffer::new(b"Hello, world!\n\x00\xff\x01".to_vec());
+        assert_eq!(hex.len_rows(), 2);
+        assert_eq!(
+            hex.format_row(0),
+            "00000000  48 65 6c 6c 6f 2c 20 77  6f 72 6c 64 21 0a 00 ff  |Hello, world!...|"
+        );
+        assert!(hex.format_row(1).starts_with("00000010  01    "));
+        for offset in [0, 7, 8, 15, 16, 17] {
+            for nibble in [0, 1] {
+                let p = point_of(offset, nibble);
+                assert_eq!(offset_at(p), (offset, nibble));
+                let line = hex.format_row(p.row);
+                if offset < 16 {
+                    let byte = &line[p.col - nibble..p.col - nibble + 2];
+                    assert_eq!(byte, format!("{:02x}", hex.get_bytes()[offset]));
+                }
+            }
+        }
+        assert_eq!(offset_at(Point { col: 62, row: 0 }), (1, 0));
+    }
+    #[test]
+    fn test_edit_and_undo() {
+        let mut hex = HexBuffer::new(vec![0x12, 0x34]);
+        hex.set_nibble(0, 1, 0xf);
+        hex.insert(1, 0xab);
+        hex.set_nibble(3, 0, 0x5);
+        assert_eq!(hex.get_bytes(), [0x1f, 0xab, 0x34, 0x50]);
+        assert_eq!(hex.remove(2), Some(0x34));
+        assert_eq!(hex.find(&[0xab, 0x50]), [1]);
+        for _ in 0..4 {
+            hex.undo();
+        }
+        assert_eq!(hex.get_bytes(), [0x12, 0x34]);
+        assert_eq!(hex.undo(), None);
+    }
+    #[test]
+    fn test_binary_and_pattern() {
+        assert!(looks_binary(b"\x7fELF\x02\x01\x01\x00\x00"));
+        assert!(!looks_binary(b"text\n"));
+        // ascii in utf-16 has NUL bytes too.
+        assert!(!looks_binary(&[b'a', 0, b'b', 0, b'\n', 0]));
+        assert_eq!(parse_pattern("de ad BE ef"), [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(parse_pattern("cafe!"), b"cafe!");
+        assert_eq!(parse_pattern("abc"), b"abc");
+    }
+}
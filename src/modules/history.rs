@@ -1,59 +1,87 @@
+use crate::modules::coordinate::Point;
 use std::collections::VecDeque;
+
+/// records kept for undo. older changes are dropped whole past this.
+const HISTORY_SIZE: usize = 999;
+
 #[derive(Clone)]
 pub struct HistoryRecord {
     ope: Operation,
     target: Vec<char>,
-    position: [u32; 2],
+    position: Point,
+    /// records of one change share this number and are undone together
+    change: usize,
 }
 #[derive(Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum Operation {
     HEAD,
     ADD,
     DELETE,
+    #[allow(dead_code)]
     COMMAND,
 }
 pub struct History {
     history: VecDeque<HistoryRecord>,
-    index: u32,
 }
 
 impl HistoryRecord {
-    fn new(operation: Operation, input: Vec<char>, pos: [u32; 2]) -> HistoryRecord {
+    fn new(operation: Operation, input: Vec<char>, pos: Point, change: usize) -> HistoryRecord {
         HistoryRecord {
             ope: operation,
             target: input,
             position: pos,
+            change,
         }
     }
     pub fn get_operation(&self) -> Operation {
         self.ope
     }
-    pub fn get_pos(&self) -> [u32; 2] {
+    pub fn get_pos(&self) -> Point {
         self.position
     }
     pub fn get_target(&self) -> &Vec<char> {
         &self.target
     }
+    pub fn get_change(&self) -> usize {
+        self.change
+    }
 }
 impl History {
     pub fn new() -> History {
         History {
-            history: VecDeque::with_capacity(1000),
-            index: 0,
+            history: VecDeque::with_capacity(HISTORY_SIZE + 1),
         }
     }
-    pub fn add(&mut self, ope: Operation, target: Vec<char>, pos: [u32; 2]) {
-        if self.history.len() >= 999{
-            self.history.pop_front();
-            self.index -= 1;
+    /// add a record of `change`. when there are too many, the oldest changes are dropped
+    /// whole, never the one being made, so that each is undone entirely.
+    pub fn add(&mut self, ope: Operation, target: Vec<char>, pos: Point, change: usize) {
+        self.history
+            .push_back(HistoryRecord::new(ope, target, pos, change));
+        while self.history.len() > HISTORY_SIZE {
+            let oldest = self.history.front().unwrap().change;
+            if oldest == change {
+                break;
+            }
+            while self.history.front().map(|r| r.change) == Some(oldest) {
+                self.history.pop_front();
+            }
         }
-        self.history.push_back(HistoryRecord::new(ope, target, pos));
-        self.index += 1;
     }
     pub fn undo(&mut self) -> HistoryRecord {
         match self.history.pop_back() {
             Some(t) => t,
-            None => HistoryRecord::new(Operation::HEAD, Vec::new(), [0, 0]),
+            None => HistoryRecord::new(Operation::HEAD, Vec::new(), Point { col: 0, row: 0 }, 0),
         }
     }
+    pub fn clear(&mut self) {
+        self.history.clear();
+    }
+    pub fn is_empty(&self) -> bool {
+        self.history.is_empty()
+    }
+    /// the change of the last record.
+    pub fn last_change(&self) -> Option<usize> {
+        self.history.back().map(|r| r.change)
+    }
 }
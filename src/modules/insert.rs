@@ -1,146 +1,385 @@
+use crate::modules::coordinate::Point;
 use crate::modules::history::Operation;
 use crate::modules::mode::MODE;
+use crate::modules::motion::{class, first_non_blank};
+use crate::modules::operator::{indent_below, indent_width};
+use crate::modules::option::Options;
+use crate::modules::register::{is_register, Registers};
 use crate::modules::show::Display;
 use crate::modules::show::MoveDirection;
 use crate::modules::undo::Undo;
+use crate::modules::unicode::{char_to_display_col, next_grapheme};
 use crossterm::cursor::SetCursorStyle;
 use crossterm::event::KeyCode;
 
 use super::file::FileBuffer;
 
 /// insert a charactor on a point.
-pub fn insert(col: u16, row: u16, base_string: String, charactor: char) -> String {
-    // create copy string
-    let tmp = String::from(base_string);
-    let mut count = 0;
-    let mut result = String::new();
-    let mut first = true;
-
-    // move to target row
-    for content in tmp.split('\n') {
-        if first {
-            first = false;
-        } else {
-            result.push_str("\n");
-        }
-        let mut tmpstring = format!("{}", content);
-        if count == row {
-            tmpstring.insert(col as usize, charactor);
-        }
-        result.push_str(&tmpstring);
-        count += 1;
+pub fn insert(col: usize, row: usize, buf: &mut FileBuffer, charactor: char) {
+    let mut tmp = [0u8; 4];
+    buf.insert_str(col, row, charactor.encode_utf8(&mut tmp));
+}
+/// delete the grapheme cluster on a point. at the end of a line the line break is deleted.
+pub fn delback(col: usize, row: usize, buf: &mut FileBuffer) -> Vec<char> {
+    let len = match next_grapheme(&buf.get_line(row), col) - col {
+        0 => 1,
+        len => len,
+    };
+    buf.remove_chars(col, row, len)
+}
+/// put `text` at the cursor and move the cursor past it.
+fn insert_text(display: &mut Display, buf: &mut FileBuffer, undo: &mut Undo, text: &str) {
+    let at = display.get_cursor_coordinate_in_file();
+    buf.insert_str(at.col, at.row, text);
+    undo.add_do_history(Operation::ADD, text.chars().collect(), at);
+    let end = text.chars().fold(at, |p, c| match c {
+        '\n' => Point {
+            col: 0,
+            row: p.row + 1,
+        },
+        _ => Point {
+            col: p.col + 1,
+            row: p.row,
+        },
+    });
+    display.jump_to(buf, end);
+}
+/// put `text` in place of the chars from `col` to the cursor. returns the chars taken.
+fn replace_before(
+    display: &mut Display,
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    col: usize,
+    text: &str,
+) -> usize {
+    let at = display.get_cursor_coordinate_in_file();
+    let removed = buf.remove_chars(col, at.row, at.col - col);
+    let len = removed.len();
+    let start = Point { col, row: at.row };
+    undo.add_do_history(Operation::DELETE, removed, start);
+    display.jump_to(buf, start);
+    insert_text(display, buf, undo, text);
+    len
+}
+/// the indent of a new line that starts with `rest` below the text `above`, as
+/// `autoindent` and `smartindent` make it.
+fn new_indent(opt: &Options, above: &str, rest: &str) -> String {
+    if !opt.autoindent && !opt.smartindent {
+        return String::new();
     }
-    String::from(result)
+    opt.indent_string(indent_below(opt, above, rest, opt.smartindent))
 }
-pub fn delback(col: u16, row: u16, base_string: String) -> (String, Vec<char>) {
-    let tmp = String::from(base_string);
-    let mut count = 0;
-    let mut after = String::new();
-    let mut cr = true;
-    let mut del_char: char = 0x00 as char;
-    // move to target row
-    for content in tmp.split('\n') {
-        if cr {
-            cr = false;
-        } else {
-            after.push_str("\n");
-        }
-        let mut tmpstring = format!("{}", content);
-        if count == row {
-            if col < tmpstring.len() as u16 {
-                let mut colcount = 0;
-                for charactor in tmpstring.chars() {
-                    if colcount == col {
-                        del_char = charactor;
-                    }
-                    colcount += 1;
-                }
-                tmpstring.remove((col) as usize);
-            } else {
-                del_char = '\n' as char;
-                cr = true;
-            }
-        }
-        after.push_str(&tmpstring);
-        count += 1;
+/// `o` (`O` when not `below`): open a line below (above) the line of the cursor and put
+/// the cursor on it, indented as `autoindent` and `smartindent` say.
+pub fn open_line(display: &mut Display, buf: &mut FileBuffer, undo: &mut Undo, below: bool) {
+    let row = display.get_cursor_coordinate_in_file().row;
+    let line = buf.get_line(row).into_owned();
+    let opt = buf.get_options().clone();
+    if below {
+        let indent = new_indent(&opt, &line, "");
+        display.jump_to(
+            buf,
+            Point {
+                col: line.chars().count(),
+                row,
+            },
+        );
+        insert_text(display, buf, undo, &format!("\n{}", indent));
+        return;
     }
-    (String::from(after), vec![del_char])
+    // above a `}` the line goes a step further in.
+    let indent = match (opt.autoindent, opt.smartindent) {
+        (_, true) if line.trim_start().starts_with('}') => {
+            opt.indent_string(indent_width(&line, opt.tabstop) + opt.get_shiftwidth())
+        }
+        (false, false) => String::new(),
+        _ => opt.indent_string(indent_width(&line, opt.tabstop)),
+    };
+    display.jump_to(buf, Point { col: 0, row });
+    insert_text(display, buf, undo, &format!("{}\n", indent));
+    display.jump_to(
+        buf,
+        Point {
+            col: indent.chars().count(),
+            row,
+        },
+    );
+}
+/// the start of the blanks right before `col`.
+fn blanks_start(line: &[char], col: usize) -> usize {
+    let blanks = line[..col]
+        .iter()
+        .rev()
+        .take_while(|c| **c == ' ' || **c == '\t')
+        .count();
+    col - blanks
 }
+/// keys of insert mode. `pending` is a `Ctrl-R` or `Ctrl-V` waiting for the key after it.
+/// `Ctrl-O` leaves the insert like Esc, normal mode comes back to it after a command.
 pub fn proc_insert(
     code: KeyCode,
     display: &mut Display,
     buf: &mut FileBuffer,
     undo: &mut Undo,
+    registers: &mut Registers,
+    pending: &mut Option<char>,
 ) -> MODE {
+    let at = display.get_cursor_coordinate_in_file();
+    if let Some(key) = pending.take() {
+        display.set_pending_keys("");
+        let text = match (key, code) {
+            ('\x12', KeyCode::Char(name)) if is_register(name) => {
+                match registers.get(Some(name), buf.get_path()) {
+                    Some(reg) => reg.text,
+                    None => return MODE::Insert,
+                }
+            }
+            ('\x12', KeyCode::Esc) => return MODE::Insert,
+            ('\x12', _) => {
+                display.bell();
+                return MODE::Insert;
+            }
+            (_, KeyCode::Char(c)) => String::from(c),
+            (_, KeyCode::Tab) => String::from('\t'),
+            (_, KeyCode::Enter) => String::from('\r'),
+            (_, KeyCode::Esc) => String::from('\x1b'),
+            _ => return MODE::Insert,
+        };
+        insert_text(display, buf, undo, &text);
+        text.chars().for_each(|c| registers.insert_typed(c));
+        return MODE::Insert;
+    }
     match code {
-        KeyCode::Esc => {
+        KeyCode::Esc | KeyCode::Char('\x0f') => {
             display.set_cursor_type(SetCursorStyle::SteadyBlock);
             MODE::Normal
         }
         KeyCode::Enter => {
-            buf.update_contents(insert(
-                display.get_cursor_coordinate_in_file().col,
-                display.get_cursor_coordinate_in_file().row,
-                buf.get_contents(),
-                '\n',
-            ));
-            undo.add_do_history(
-                Operation::ADD,
-                vec!['\n' as char],
-                [
-                    display.get_cursor_coordinate_in_file().col as u32,
-                    display.get_cursor_coordinate_in_file().row as u32,
-                ],
-            );
-            display.move_cursor_nextpos(MoveDirection::Down, &buf);
-            display.move_cursor_nextpos(MoveDirection::Head, &buf);
-            display.update_all(buf.get_contents()).unwrap();
+            let line = buf.get_line(at.row).into_owned();
+            let split = line
+                .char_indices()
+                .nth(at.col)
+                .map_or(line.len(), |(i, _)| i);
+            let indent = new_indent(buf.get_options(), &line[..split], &line[split..]);
+            insert_text(display, buf, undo, &format!("\n{}", indent));
+            registers.insert_typed('\n');
+            MODE::Insert
+        }
+        KeyCode::Char(c @ ('\x12' | '\x16')) => {
+            *pending = Some(c);
+            display.set_pending_keys(if c == '\x12' { "^R" } else { "^V" });
+            MODE::Insert
+        }
+        // `Ctrl-W` takes the word before the cursor, `Ctrl-U` the text before it on the
+        // line, down to the indent first. at the start of a line they join it to the one above.
+        KeyCode::Char('\x17' | '\x15') if at.col == 0 => {
+            proc_insert(KeyCode::Backspace, display, buf, undo, registers, pending)
+        }
+        KeyCode::Char(c @ ('\x17' | '\x15')) => {
+            let line: Vec<char> = buf.get_line(at.row).chars().collect();
+            let col = if c == '\x17' {
+                let mut col = blanks_start(&line, at.col);
+                let kind = class(col.checked_sub(1).map(|i| line[i]));
+                while col > 0 && kind != 0 && class(Some(line[col - 1])) == kind {
+                    col -= 1;
+                }
+                col
+            } else {
+                match first_non_blank(buf, at.row).col {
+                    indent if indent < at.col => indent,
+                    _ => 0,
+                }
+            };
+            for _ in 0..replace_before(display, buf, undo, col, "") {
+                registers.insert_erased();
+            }
+            MODE::Insert
+        }
+        // with `smartindent` a `}` typed first on a line takes a step off its indent.
+        KeyCode::Char('}')
+            if buf.get_options().smartindent
+                && at.col > 0
+                && buf
+                    .get_line(at.row)
+                    .chars()
+                    .take(at.col)
+                    .all(|c| c == ' ' || c == '\t') =>
+        {
+            let opt = buf.get_options().clone();
+            let width = indent_width(&buf.get_line(at.row), opt.tabstop);
+            let indent = opt.indent_string(width.saturating_sub(opt.get_shiftwidth()));
+            replace_before(display, buf, undo, 0, &indent);
+            insert_text(display, buf, undo, "}");
+            registers.insert_typed('}');
             MODE::Insert
         }
+        // other control chars do nothing, `Ctrl-V` puts them in.
+        KeyCode::Char(c) if c.is_control() => MODE::Insert,
         KeyCode::Char(c) => {
-            buf.update_contents(insert(
+            insert(
                 display.get_cursor_coordinate_in_file().col,
                 display.get_cursor_coordinate_in_file().row,
-                buf.get_contents(),
+                buf,
                 c,
-            ));
+            );
             undo.add_do_history(
                 Operation::ADD,
-                vec![c as char],
-                [
-                    display.get_cursor_coordinate_in_file().col as u32,
-                    display.get_cursor_coordinate_in_file().row as u32,
-                ],
+                vec![c],
+                display.get_cursor_coordinate_in_file(),
             );
-            display.move_cursor_nextpos(MoveDirection::Right, &buf);
-            display.update_all(buf.get_contents()).unwrap();
+            registers.insert_typed(c);
+            display.move_cursor_nextpos(MoveDirection::Right, buf);
             MODE::Insert
         }
-        KeyCode::Backspace => {
-            let tmp_pos = display.get_cursor_coordinate_in_file();
-            if display.get_cursor_coordinate_in_file().col <= 0 {
-                if display.get_cursor_coordinate().row > 0 {
-                    display.move_cursor_nextpos(MoveDirection::Up, &buf);
-                    display.move_cursor_nextpos(MoveDirection::Tail, &buf);
-                } else {
-                };
+        // with `softtabstop` or `expandtab` a Tab goes on to the next stop with blanks,
+        // turning the blanks before it into tabs where they fit unless `expandtab`.
+        KeyCode::Tab => {
+            let opt = buf.get_options().clone();
+            registers.insert_typed('\t');
+            if opt.softtabstop == 0 && !opt.expandtab {
+                insert_text(display, buf, undo, "\t");
+                return MODE::Insert;
+            }
+            let line = buf.get_line(at.row).into_owned();
+            let vcol = char_to_display_col(&line, at.col, opt.tabstop);
+            let step = match opt.softtabstop {
+                0 => opt.tabstop,
+                n => n,
+            };
+            let to = (vcol / step + 1) * step;
+            if opt.expandtab {
+                insert_text(display, buf, undo, &" ".repeat(to - vcol));
             } else {
-                display.move_cursor_nextpos(MoveDirection::Left, &buf);
+                let chars: Vec<char> = line.chars().collect();
+                let start = blanks_start(&chars, at.col);
+                let from = char_to_display_col(&line, start, opt.tabstop);
+                replace_before(display, buf, undo, start, &opt.blanks(from, to));
+            }
+            MODE::Insert
+        }
+        // with `softtabstop` a Backspace after blanks goes back to the stop before.
+        KeyCode::Backspace if buf.get_options().softtabstop > 0 && at.col > 0 => {
+            let opt = buf.get_options().clone();
+            let line = buf.get_line(at.row).into_owned();
+            let chars: Vec<char> = line.chars().collect();
+            let start = blanks_start(&chars, at.col);
+            if start == at.col || chars[at.col - 1] == '\t' {
+                return proc_delete_back(display, buf, undo, registers);
+            }
+            let vcol = char_to_display_col(&line, at.col, opt.tabstop);
+            let from = char_to_display_col(&line, start, opt.tabstop);
+            let to = ((vcol - 1) / opt.softtabstop * opt.softtabstop).max(from);
+            replace_before(display, buf, undo, start, &opt.blanks(from, to));
+            registers.insert_erased();
+            MODE::Insert
+        }
+        KeyCode::Backspace => proc_delete_back(display, buf, undo, registers),
+        KeyCode::Delete => {
+            let last = at.col >= buf.get_col_length(at.row);
+            if !(last && at.row + 1 >= buf.get_row_length()) {
+                let removed = delback(at.col, at.row, buf);
+                undo.add_do_history(Operation::DELETE, removed, at);
+            }
+            MODE::Insert
+        }
+        // moving the cursor starts a new insert where it goes.
+        KeyCode::Left
+        | KeyCode::Right
+        | KeyCode::Up
+        | KeyCode::Down
+        | KeyCode::Home
+        | KeyCode::End => {
+            let direction = match code {
+                KeyCode::Left => MoveDirection::Left,
+                KeyCode::Right => MoveDirection::Right,
+                KeyCode::Up => MoveDirection::Up,
+                KeyCode::Down => MoveDirection::Down,
+                KeyCode::Home => MoveDirection::Head,
+                _ => MoveDirection::Tail,
             };
-            let (result, delchar) = delback(
-                display.get_cursor_coordinate_in_file().col,
-                display.get_cursor_coordinate_in_file().row,
-                buf.get_contents(),
-            );
-            buf.update_contents(result);
-            undo.add_do_history(
-                Operation::DELETE,
-                delchar,
-                [tmp_pos.col as u32, tmp_pos.row as u32],
-            );
-            display.update_all(buf.get_contents()).unwrap();
+            display.move_cursor_nextpos(direction, buf);
+            undo.start_change();
+            registers.start_insert();
             MODE::Insert
         }
         _ => MODE::Insert,
     }
 }
+/// Backspace: delete the grapheme before the cursor, or the line break before the line.
+fn proc_delete_back(
+    display: &mut Display,
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    registers: &mut Registers,
+) -> MODE {
+    let before = display.get_cursor_coordinate_in_file();
+    if before.col == 0 {
+        if before.row == 0 {
+            return MODE::Insert;
+        }
+        display.move_cursor_nextpos(MoveDirection::Up, buf);
+        display.move_cursor_nextpos(MoveDirection::Tail, buf);
+    } else {
+        display.move_cursor_nextpos(MoveDirection::Left, buf);
+    };
+    let pos = display.get_cursor_coordinate_in_file();
+    let delchar = delback(pos.col, pos.row, buf);
+    undo.add_do_history(Operation::DELETE, delchar, pos);
+    registers.insert_erased();
+    MODE::Insert
+}
+/// keys of replace mode. a typed char writes over the char at the cursor, Backspace
+/// puts back what the last typed char wrote over. `replaced` keeps those chars, none
+/// for a char added at the end of a line or a line break.
+pub fn proc_replace(
+    code: KeyCode,
+    display: &mut Display,
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    registers: &mut Registers,
+    replaced: &mut Vec<Vec<char>>,
+) -> MODE {
+    let mut pending = None;
+    match code {
+        KeyCode::Esc | KeyCode::Char('\x0f') => {
+            return proc_insert(code, display, buf, undo, registers, &mut pending)
+        }
+        KeyCode::Enter => {
+            proc_insert(code, display, buf, undo, registers, &mut pending);
+            replaced.push(vec![]);
+        }
+        // the cursor keys and Delete work as in insert mode, other control keys do nothing.
+        KeyCode::Left
+        | KeyCode::Right
+        | KeyCode::Up
+        | KeyCode::Down
+        | KeyCode::Home
+        | KeyCode::End
+        | KeyCode::Delete => {
+            proc_insert(code, display, buf, undo, registers, &mut pending);
+            replaced.clear();
+        }
+        KeyCode::Char(c) if c.is_control() => (),
+        KeyCode::Char(_) => {
+            let at = display.get_cursor_coordinate_in_file();
+            let end = next_grapheme(&buf.get_line(at.row), at.col);
+            let removed = buf.remove_chars(at.col, at.row, end - at.col);
+            undo.add_do_history(Operation::DELETE, removed.clone(), at);
+            replaced.push(removed);
+            proc_insert(code, display, buf, undo, registers, &mut pending);
+        }
+        KeyCode::Backspace => match replaced.pop() {
+            // before the start of the replace the cursor only moves.
+            None => display.move_cursor_nextpos(MoveDirection::Left, buf),
+            Some(old) => {
+                proc_delete_back(display, buf, undo, registers);
+                let at = display.get_cursor_coordinate_in_file();
+                let text: String = old.iter().collect();
+                buf.insert_str(at.col, at.row, &text);
+                undo.add_do_history(Operation::ADD, old, at);
+            }
+        },
+        _ => (),
+    }
+    MODE::Replace
+}
@@ -0,0 +1,307 @@
+use crate::modules::fileformat::{detect, FileFormat};
+use memchr::memmem;
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Write};
+#[cfg(unix)]
+use std::os::unix::fs::FileExt;
+#[cfg(windows)]
+use std::os::windows::fs::FileExt;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+// large-file mode. the file is read in blocks as its lines are drawn, and the line
+// starts are found by a background thread so that the first screen shows at once.
+// the text is read as utf-8 and the buffer takes no edits.
+
+/// files above this size are opened in large-file mode.
+pub const LARGE_FILE_SIZE: u64 = 64 * 1024 * 1024;
+/// line starts are handed to the index in batches of this many.
+const BATCH: usize = 16 * 1024;
+/// bytes looked at to guess the line endings.
+const SAMPLE: usize = 64 * 1024;
+/// bytes read at once by the indexing thread and by a search.
+const CHUNK: usize = 1024 * 1024;
+/// bytes searched at most, from the start of the file.
+const SEARCH_SIZE: usize = 64 * 1024 * 1024;
+/// size of the blocks the lines are read from.
+const BLOCK: usize = 64 * 1024;
+/// blocks kept for drawing the lines again.
+const CACHED_BLOCKS: usize = 64;
+
+/// byte offsets of the line starts, filled in by the indexing thread.
+#[derive(Debug)]
+struct LineIndex {
+    starts: Mutex<Vec<usize>>,
+    /// bytes scanned so far, the size of the file once done
+    scanned: AtomicUsize,
+    done: AtomicBool,
+}
+/// an open file with its line index.
+/// a file truncated by another program reads short, `:e` opens it again.
+#[derive(Debug)]
+pub struct LargeFile {
+    file: Arc<File>,
+    /// size when opened, for the progress
+    size: usize,
+    index: Arc<LineIndex>,
+    /// tells the indexing thread to give up, when the file is closed or opened again
+    stop: Arc<AtomicBool>,
+    /// blocks read lately as (block number, bytes), the latest last
+    cache: Mutex<Vec<(usize, Arc<Vec<u8>>)>>,
+    /// CRLF file, the CRs are left out of the lines
+    strip_cr: bool,
+}
+
+impl LargeFile {
+    pub fn open(path: &Path) -> Result<LargeFile, String> {
+        let file = match File::open(path) {
+            Ok(f) => Arc::new(f),
+            Err(e) => return Err(format!("[E506] cannot open {}: {}", path.display(), e)),
+        };
+        let size = file.metadata().map_or(0, |m| m.len() as usize);
+        let (format, _) = detect(&String::from_utf8_lossy(
+            &read_at(&file, 0, SAMPLE).unwrap_or_default(),
+        ));
+        let index = Arc::new(LineIndex {
+            starts: Mutex::new(vec![0]),
+            scanned: AtomicUsize::new(0),
+            done: AtomicBool::new(false),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let (f, idx, halt) = (file.clone(), index.clone(), stop.clone());
+        thread::spawn(move || {
+            let mut batch = Vec::with_capacity(BATCH);
+            let mut pos = 0;
+            loop {
+                if halt.load(Ordering::Relaxed) {
+                    return;
+                }
+                let chunk = read_at(&f, pos, CHUNK).unwrap_or_default();
+                if chunk.is_empty() {
+                    break;
+                }
+                for i in memchr::memchr_iter(b'\n', &chunk) {
+                    batch.push(pos + i + 1);
+                    if batch.len() == BATCH {
+                        idx.starts.lock().unwrap().append(&mut batch);
+                    }
+                }
+                pos += chunk.len();
+                idx.scanned.store(pos, Ordering::Relaxed);
+            }
+            idx.starts.lock().unwrap().append(&mut batch);
+            idx.scanned.store(pos, Ordering::Relaxed);
+            idx.done.store(true, Ordering::Release);
+        });
+        Ok(LargeFile {
+            file,
+            size,
+            index,
+            stop,
+            cache: Mutex::new(vec![]),
+            strip_cr: format == FileFormat::Dos,
+        })
+    }
+    /// the lines known so far. the last line only counts once the whole file is indexed.
+    pub fn len_lines(&self) -> usize {
+        let known = self.index.starts.lock().unwrap().len();
+        if self.index.done.load(Ordering::Acquire) {
+            known
+        } else {
+            known - 1
+        }
+    }
+    /// percent of the file indexed, None when done.
+    pub fn progress(&self) -> Option<usize> {
+        if self.index.done.load(Ordering::Acquire) {
+            return None;
+        }
+        let scanned = self.index.scanned.load(Ordering::Relaxed);
+        Some((scanned * 100 / self.size.max(1)).min(99))
+    }
+    /// byte range of a line with its CR but without the LF.
+    fn range(&self, row: usize) -> Option<(usize, usize)> {
+        let starts = self.index.starts.lock().unwrap();
+        let start = *starts.get(row)?;
+        let end = match starts.get(row + 1) {
+            Some(next) => next - 1,
+            None if self.index.done.load(Ordering::Acquire) => {
+                self.index.scanned.load(Ordering::Relaxed)
+            }
+            None => return None,
+        };
+        Some((start, end))
+    }
+    /// a block of the file, short or empty past its end.
+    fn block(&self, n: usize) -> Arc<Vec<u8>> {
+        let mut cache = self.cache.lock().unwrap();
+        if let Some(i) = cache.iter().position(|(b, _)| *b == n) {
+            let hit = cache.remove(i);
+            cache.push(hit.clone());
+            return hit.1;
+        }
+        let bytes = Arc::new(read_at(&self.file, n * BLOCK, BLOCK).unwrap_or_default());
+        if cache.len() == CACHED_BLOCKS {
+            cache.remove(0);
+        }
+        cache.push((n, bytes.clone()));
+        bytes
+    }
+    /// the bytes from `start` up to `end`, fewer if the file got shorter.
+    fn read(&self, start: usize, end: usize) -> Vec<u8> {
+        let mut ret = Vec::with_capacity(end - start);
+        let mut pos = start;
+        while pos < end {
+            let block = self.block(pos / BLOCK);
+            let from = pos % BLOCK;
+            if from >= block.len() {
+                break;
+            }
+            let to = block.len().min(from + end - pos);
+            ret.extend_from_slice(&block[from..to]);
+            pos += to - from;
+        }
+        ret
+    }
+    /// a line without its line break. empty if it is not indexed yet.
+    pub fn line(&self, row: usize) -> Cow<'_, str> {
+        let Some((start, end)) = self.range(row) else {
+            return Cow::Borrowed("");
+        };
+        let mut bytes = self.read(start, end);
+        if self.strip_cr && bytes.last() == Some(&b'\r') {
+            bytes.pop();
+        }
+        Cow::Owned(String::from_utf8_lossy(&bytes).into_owned())
+    }
+    /// the first `limit` matches of `ptn` in the indexed lines as (row, byte offset in the line).
+    /// only the first `SEARCH_SIZE` bytes are searched.
+    pub fn find(&self, ptn: &str, limit: usize) -> Vec<(usize, usize)> {
+        // a match in the line being indexed is not found.
+        let end = if self.index.done.load(Ordering::Acquire) {
+            self.index.scanned.load(Ordering::Relaxed)
+        } else {
+            *self.index.starts.lock().unwrap().last().unwrap()
+        };
+        let end = end.min(SEARCH_SIZE);
+        let finder = memmem::Finder::new(ptn.as_bytes());
+        // chunks overlap so that a match across two of them is found once.
+        let overlap = ptn.len().saturating_sub(1);
+        // the index is left to its thread while the file is read.
+        let mut found = vec![];
+        let mut pos = 0;
+        'scan: while pos < end {
+            let want = (CHUNK + overlap).min(end - pos);
+            let chunk = read_at(&self.file, pos, want).unwrap_or_default();
+            for i in finder.find_iter(&chunk) {
+                if i >= CHUNK {
+                    break;
+                }
+                found.push(pos + i);
+                if found.len() == limit {
+                    break 'scan;
+                }
+            }
+            if chunk.len() < want {
+                break;
+            }
+            pos += CHUNK;
+        }
+        let starts = self.index.starts.lock().unwrap();
+        found
+            .into_iter()
+            .map(|at| {
+                // the row whose start is the last one at or before the match.
+                let row = starts.partition_point(|s| *s <= at) - 1;
+                (row, at - starts[row])
+            })
+            .collect()
+    }
+    /// write the file as it is on disk now to `out`, a chunk at a time.
+    pub fn copy_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut pos = 0;
+        loop {
+            let chunk = read_at(&self.file, pos, CHUNK)?;
+            if chunk.is_empty() {
+                return Ok(());
+            }
+            out.write_all(&chunk)?;
+            pos += chunk.len();
+        }
+    }
+    /// wait until the indexing thread is done.
+    #[cfg(test)]
+    pub fn wait(&self) {
+        while !self.index.done.load(Ordering::Acquire) {
+            thread::yield_now();
+        }
+    }
+}
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+/// read up to `len` bytes at `offset`, fewer at the end of the file.
+fn read_at(file: &File, offset: usize, len: usize) -> io::Result<Vec<u8>> {
+    let mut buf = vec![0; len];
+    let mut got = 0;
+    while got < len {
+        let at = (offset + got) as u64;
+        #[cfg(unix)]
+        let read = file.read_at(&mut buf[got..], at);
+        // moves the file position, which no reader here relies on.
+        #[cfg(windows)]
+        let read = file.seek_read(&mut buf[got..], at);
+        match read {
+            Ok(0) => break,
+            Ok(n) => got += n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+    buf.truncate(got);
+    Ok(buf)
+}
+#[cfg(test)]
+mod large_test {
+    use super::*;
+
+    #[test]
+    fn test_index() {
+        let p = std::env::temp_dir().join("ve_large_test_index.txt");
+        let text: String = (0..40000).map(|i| format!("line {}\r\n", i)).collect();
+        std::fs::write(&p, &text).unwrap();
+        let large = LargeFile::open(&p).unwrap();
+        large.wait();
+        assert_eq!(large.progress(), None);
+        // the text ends with a line break, so the last line is empty.
+        assert_eq!(large.len_lines(), 40001);
+        assert_eq!(large.line(0), "line 0");
+        assert_eq!(large.line(39999), "line 39999");
+        assert_eq!(large.line(40000), "");
+        assert_eq!(large.line(50000), "");
+        assert_eq!(
+            large.find("line 1234", 3),
+            [(1234, 0), (12340, 0), (12341, 0)]
+        );
+        assert_eq!(large.find("e 7\r", 10), [(7, 3)]);
+        // a truncated file reads short instead of failing.
+        std::fs::File::options()
+            .write(true)
+            .open(&p)
+            .unwrap()
+            .set_len(18)
+            .unwrap();
+        large.cache.lock().unwrap().clear();
+        assert_eq!(large.line(1), "line 1");
+        assert_eq!(large.line(2), "li");
+        assert_eq!(large.line(39999), "");
+        let stop = large.stop.clone();
+        drop(large);
+        assert!(stop.load(Ordering::Relaxed));
+    }
+}
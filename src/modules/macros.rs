@@ -0,0 +1,193 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use std::collections::VecDeque;
+
+// macros are keys kept in a register. they are written as text, so that a register can
+// be put, edited and yanked back: chars as they are, other keys like `<Esc>` or `<C-o>`.
+
+/// names of the keys written as `<name>`.
+const NAMES: [(&str, KeyCode); 15] = [
+    ("CR", KeyCode::Enter),
+    ("Esc", KeyCode::Esc),
+    ("BS", KeyCode::Backspace),
+    ("Tab", KeyCode::Tab),
+    ("S-Tab", KeyCode::BackTab),
+    ("Del", KeyCode::Delete),
+    ("Insert", KeyCode::Insert),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("PageUp", KeyCode::PageUp),
+    ("PageDown", KeyCode::PageDown),
+];
+
+/// recording and playing of macros.
+#[derive(Debug)]
+pub struct Macro {
+    /// the register being recorded to, and the keys so far
+    recording: Option<(char, Vec<KeyEvent>)>,
+    /// the register last played, for `@@`
+    last: Option<char>,
+    /// keys of macros still to be played
+    queue: VecDeque<KeyEvent>,
+}
+
+/// the text of `key` in a macro.
+pub fn key_to_text(key: KeyEvent) -> String {
+    match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => format!("<C-{}>", c),
+        KeyCode::Char('<') => String::from("<lt>"),
+        KeyCode::Char(c) => String::from(c),
+        KeyCode::F(n) => format!("<F{}>", n),
+        code => match NAMES.iter().find(|(_, k)| *k == code) {
+            Some((name, _)) => format!("<{}>", name),
+            None => String::new(),
+        },
+    }
+}
+/// the keys written in `text`. a `<` that starts no key name is the char itself.
+/// line breaks, tabs and other control chars are the keys that type them.
+pub fn text_to_keys(text: &str) -> Vec<KeyEvent> {
+    let key = |code| KeyEvent::new(code, KeyModifiers::empty());
+    let chars: Vec<char> = text.chars().collect();
+    let mut keys = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        if c == '<' {
+            let name = chars[i..]
+                .iter()
+                .position(|c| *c == '>')
+                .map(|n| chars[i..i + n].iter().collect::<String>());
+            if let Some(k) = name.as_deref().and_then(key_of_name) {
+                i += name.unwrap().chars().count() + 1;
+                keys.push(k);
+                continue;
+            }
+        }
+        keys.push(match c {
+            '\n' | '\r' => key(KeyCode::Enter),
+            '\x1b' => key(KeyCode::Esc),
+            '\t' => key(KeyCode::Tab),
+            '\x08' | '\x7f' => key(KeyCode::Backspace),
+            '\x01'..='\x1a' => KeyEvent::new(
+                KeyCode::Char((c as u8 - 1 + b'a') as char),
+                KeyModifiers::CONTROL,
+            ),
+            c => key(KeyCode::Char(c)),
+        });
+    }
+    keys
+}
+/// the key written as `<name>`.
+fn key_of_name(name: &str) -> Option<KeyEvent> {
+    let key = |code| Some(KeyEvent::new(code, KeyModifiers::empty()));
+    if name.eq_ignore_ascii_case("lt") {
+        return key(KeyCode::Char('<'));
+    }
+    if let Some(c) = name.strip_prefix("C-").or_else(|| name.strip_prefix("c-")) {
+        let mut chars = c.chars();
+        return match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(KeyEvent::new(
+                KeyCode::Char(c.to_ascii_lowercase()),
+                KeyModifiers::CONTROL,
+            )),
+            _ => None,
+        };
+    }
+    if let Some(n) = name.strip_prefix('F').and_then(|n| n.parse().ok()) {
+        return key(KeyCode::F(n));
+    }
+    match NAMES.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)) {
+        Some((_, code)) => key(*code),
+        None => None,
+    }
+}
+
+impl Macro {
+    pub fn new() -> Macro {
+        Macro {
+            recording: None,
+            last: None,
+            queue: VecDeque::new(),
+        }
+    }
+    pub fn get_recording(&self) -> Option<char> {
+        self.recording.as_ref().map(|r| r.0)
+    }
+    pub fn start_recording(&mut self, name: char) {
+        self.recording = Some((name, vec![]));
+    }
+    /// stop recording. returns the register and the text of the keys recorded.
+    pub fn stop_recording(&mut self) -> Option<(char, String)> {
+        let (name, keys) = self.recording.take()?;
+        Some((name, keys.into_iter().map(key_to_text).collect()))
+    }
+    /// keep a typed key while recording.
+    pub fn record(&mut self, key: KeyEvent) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+    }
+    /// drop the last key recorded, the `q` that stops the recording.
+    pub fn unrecord(&mut self) {
+        if let Some((_, keys)) = &mut self.recording {
+            keys.pop();
+        }
+    }
+    pub fn get_last(&self) -> Option<char> {
+        self.last
+    }
+    /// play `keys` of register `name` before the keys still queued, so that a macro
+    /// run from a macro is played where it is called.
+    pub fn play(&mut self, name: char, keys: Vec<KeyEvent>) {
+        self.last = Some(name);
+        for key in keys.into_iter().rev() {
+            self.queue.push_front(key);
+        }
+    }
+    /// the next key of a macro being played.
+    pub fn next_key(&mut self) -> Option<KeyEvent> {
+        self.queue.pop_front()
+    }
+    /// stop playing, after a command failed.
+    pub fn stop(&mut self) {
+        self.queue.clear();
+    }
+}
+#[cfg(test)]
+mod macros_test {
+    use super::*;
+
+    #[test]
+    fn test_notation() {
+        let keys = text_to_keys("d<lt>a<Esc>:wq<CR><C-o><Up><F3><x>\n");
+        let text: String = keys.iter().map(|k| key_to_text(*k)).collect();
+        assert_eq!(text, "d<lt>a<Esc>:wq<CR><C-o><Up><F3><lt>x><CR>");
+        assert_eq!(
+            keys[0],
+            KeyEvent::new(KeyCode::Char('d'), KeyModifiers::empty())
+        );
+        assert_eq!(
+            text_to_keys("\x0f"),
+            vec![KeyEvent::new(KeyCode::Char('o'), KeyModifiers::CONTROL)]
+        );
+        let mut m = Macro::new();
+        m.start_recording('a');
+        for k in text_to_keys("dwq") {
+            m.record(k);
+        }
+        m.unrecord();
+        assert_eq!(m.stop_recording(), Some(('a', String::from("dw"))));
+        m.play('a', text_to_keys("ab"));
+        assert_eq!(m.next_key().map(key_to_text).as_deref(), Some("a"));
+        m.play('b', text_to_keys("c"));
+        let rest: String = std::iter::from_fn(|| m.next_key())
+            .map(key_to_text)
+            .collect();
+        assert_eq!(rest, "cb");
+    }
+}
@@ -0,0 +1,240 @@
+use crate::modules::coordinate::Point;
+use std::collections::HashMap;
+
+// marks keep places in a buffer: `a-z` and the file marks `A-Z` set with `m`, and the
+// ones set as the cursor jumps and the text is edited. they move with the text.
+
+/// jumps kept in the jump list.
+const JUMPLIST_SIZE: usize = 100;
+
+/// a name that can follow `'` or `` ` ``.
+pub fn is_mark(name: char) -> bool {
+    name.is_ascii_alphabetic() || "'`.^[]<>".contains(name)
+}
+/// a name that can follow `m`.
+pub fn is_settable(name: char) -> bool {
+    name.is_ascii_alphabetic() || "'`[]<>".contains(name)
+}
+/// where the cursor is after `text` is put at `at`.
+fn after(at: Point, text: impl Iterator<Item = char>) -> Point {
+    text.fold(at, |p, c| match c {
+        '\n' => Point {
+            col: 0,
+            row: p.row + 1,
+        },
+        _ => Point {
+            col: p.col + 1,
+            row: p.row,
+        },
+    })
+}
+fn before(a: Point, b: Point) -> bool {
+    (a.row, a.col) < (b.row, b.col)
+}
+
+/// the marks of a buffer.
+#[derive(Debug)]
+pub struct Marks {
+    marks: HashMap<char, Point>,
+    /// the next edit starts a new change, `[` and `]` are set around it alone
+    new_change: bool,
+}
+/// a place the cursor jumped from, in the buffer numbered `id`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jump {
+    pub id: usize,
+    pub at: Point,
+}
+/// the places jumped from, gone back to with `Ctrl-O` and forward again with `Ctrl-I`.
+#[derive(Debug)]
+pub struct JumpList {
+    jumps: Vec<Jump>,
+    /// the entry gone back to last, the end of the list when none
+    index: usize,
+}
+
+impl Marks {
+    pub fn new() -> Marks {
+        Marks {
+            marks: HashMap::new(),
+            new_change: true,
+        }
+    }
+    /// `` ` `` is another name of `'`, the place of the last jump.
+    pub fn get(&self, name: char) -> Option<Point> {
+        let name = if name == '`' { '\'' } else { name };
+        self.marks.get(&name).copied()
+    }
+    pub fn set(&mut self, name: char, at: Point) {
+        let name = if name == '`' { '\'' } else { name };
+        self.marks.insert(name, at);
+    }
+    pub fn remove(&mut self, name: char) -> Option<Point> {
+        self.marks.remove(&name)
+    }
+    /// the marks set, in no order.
+    pub fn iter(&self) -> impl Iterator<Item = (char, Point)> + '_ {
+        self.marks.iter().map(|(c, p)| (*c, *p))
+    }
+    /// a command starts. its edits make one change for `[` and `]`.
+    pub fn start_change(&mut self) {
+        self.new_change = true;
+    }
+    /// `text` was put at `at`. marks after it move along.
+    pub fn inserted(&mut self, at: Point, text: &str) {
+        let end = after(at, text.chars());
+        for p in self.marks.values_mut() {
+            if p.row == at.row && p.col >= at.col {
+                *p = Point {
+                    col: end.col + p.col - at.col,
+                    row: end.row,
+                };
+            } else if p.row > at.row {
+                p.row += end.row - at.row;
+            }
+        }
+        let last = match end.col {
+            0 => end,
+            col => Point {
+                col: col - 1,
+                ..end
+            },
+        };
+        self.changed(at, last);
+    }
+    /// `text` was taken out at `at`. marks after it move back, the ones on lines that
+    /// are gone are dropped and others in the text go to its start.
+    pub fn removed(&mut self, at: Point, text: &[char]) {
+        let end = after(at, text.iter().copied());
+        self.marks.retain(|_, p| {
+            let inside = !before(*p, at) && before(*p, end);
+            !(inside && p.row < end.row && (p.row > at.row || at.col == 0))
+        });
+        for p in self.marks.values_mut() {
+            if before(*p, at) {
+                continue;
+            }
+            *p = if before(*p, end) {
+                at
+            } else if p.row == end.row {
+                Point {
+                    col: at.col + p.col - end.col,
+                    row: at.row,
+                }
+            } else {
+                Point {
+                    col: p.col,
+                    row: p.row - (end.row - at.row),
+                }
+            };
+        }
+        self.changed(at, at);
+    }
+    /// `.` goes to the last edit, `[` and `]` around the edits of the change.
+    fn changed(&mut self, start: Point, end: Point) {
+        self.marks.insert('.', start);
+        let (mut first, mut last) = (start, end);
+        if !self.new_change {
+            if let Some(p) = self.get('[').filter(|p| before(*p, first)) {
+                first = p;
+            }
+            if let Some(p) = self.get(']').filter(|p| before(last, *p)) {
+                last = p;
+            }
+        }
+        self.new_change = false;
+        self.marks.insert('[', first);
+        self.marks.insert(']', last);
+    }
+}
+impl JumpList {
+    pub fn new() -> JumpList {
+        JumpList {
+            jumps: vec![],
+            index: 0,
+        }
+    }
+    /// remember a jump. an older one from the same line is dropped.
+    pub fn push(&mut self, jump: Jump) {
+        self.jumps
+            .retain(|j| j.id != jump.id || j.at.row != jump.at.row);
+        self.jumps.push(jump);
+        if self.jumps.len() > JUMPLIST_SIZE {
+            self.jumps.remove(0);
+        }
+        self.index = self.jumps.len();
+    }
+    /// `Ctrl-O`: the jump `count` back. going back from the newest one keeps `at`,
+    /// the place of the cursor, for `Ctrl-I` to come back to.
+    pub fn back(&mut self, at: Jump, count: usize) -> Option<Jump> {
+        if self.index >= self.jumps.len() {
+            self.push(at);
+            self.index = self.jumps.len() - 1;
+        }
+        self.index = self.index.checked_sub(count)?;
+        Some(self.jumps[self.index])
+    }
+    /// `Ctrl-I`: the jump `count` forward after going back.
+    pub fn forward(&mut self, count: usize) -> Option<Jump> {
+        let index = self.index.saturating_add(count);
+        if index >= self.jumps.len() {
+            return None;
+        }
+        self.index = index;
+        Some(self.jumps[index])
+    }
+    /// drop the jumps to a buffer that was closed.
+    pub fn forget(&mut self, id: usize) {
+        self.jumps.retain(|j| j.id != id);
+        self.index = self.jumps.len();
+    }
+}
+#[cfg(test)]
+mod mark_test {
+    use super::*;
+
+    fn p(col: usize, row: usize) -> Point {
+        Point { col, row }
+    }
+    #[test]
+    fn test_marks() {
+        let mut m = Marks::new();
+        m.set('a', p(2, 1));
+        m.set('b', p(4, 3));
+        m.set('`', p(0, 5));
+        assert_eq!(m.get('\''), Some(p(0, 5)));
+        // a line put above, and text before the mark on its line.
+        m.inserted(p(0, 0), "new\n");
+        m.start_change();
+        m.inserted(p(0, 2), "xy");
+        assert_eq!((m.get('a'), m.get('b')), (Some(p(4, 2)), Some(p(4, 4))));
+        assert_eq!((m.get('['), m.get(']')), (Some(p(0, 2)), Some(p(1, 2))));
+        assert_eq!(m.get('.'), Some(p(0, 2)));
+        // the line of `a` is deleted, the others move up.
+        m.start_change();
+        m.removed(p(0, 2), &"xyab..\n".chars().collect::<Vec<char>>());
+        assert_eq!(m.get('a'), None);
+        assert_eq!((m.get('b'), m.get('\'')), (Some(p(4, 3)), Some(p(0, 5))));
+        // joining lines brings a mark to the line above.
+        m.removed(p(3, 2), &['\n', ' ']);
+        assert_eq!(m.get('b'), Some(p(6, 2)));
+        assert_eq!(m.get('.'), Some(p(3, 2)));
+        assert_eq!((m.get('['), m.get(']')), (Some(p(0, 2)), Some(p(3, 2))));
+    }
+    #[test]
+    fn test_jumplist() {
+        let jump = |id, row| Jump { id, at: p(0, row) };
+        let mut j = JumpList::new();
+        j.push(jump(1, 3));
+        j.push(jump(1, 8));
+        j.push(jump(2, 3));
+        j.push(jump(1, 3));
+        assert_eq!(j.back(jump(1, 9), 1), Some(jump(1, 3)));
+        assert_eq!(j.back(jump(1, 3), 2), Some(jump(1, 8)));
+        assert_eq!(j.back(jump(1, 8), 1), None);
+        assert_eq!(j.forward(3), Some(jump(1, 9)));
+        assert_eq!(j.forward(1), None);
+        j.forget(2);
+        assert_eq!(j.back(jump(1, 0), 1), Some(jump(1, 9)));
+    }
+}
@@ -1,4 +1,5 @@
 #[derive(Debug, Eq, PartialEq, Clone, Copy)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum MODE {
     Normal,
     Insert,
@@ -8,6 +9,7 @@ pub enum MODE {
     Save,
     SaveAndQuit,
     Search,
+    Replace,
 }
 
 #[derive(Debug)]
@@ -35,5 +37,6 @@ impl State {
             is_read_only: false,
         }
     }
+    #[allow(dead_code)]
     pub fn mode_manager(&self) {}
 }
@@ -0,0 +1,619 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::mark::is_mark;
+use crate::modules::unicode::{next_grapheme, prev_grapheme};
+
+// motions move the cursor, and give the text an operator works on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Down,
+    Up,
+    /// `0`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `^`
+    FirstNonBlank,
+    /// `w`
+    WordForward,
+    /// `b`
+    WordBackward,
+    /// `e`
+    WordEnd,
+    /// `ge`
+    WordEndBackward,
+    /// `W`, words of any non-blank chars
+    BigWordForward,
+    /// `B`
+    BigWordBackward,
+    /// `E`
+    BigWordEnd,
+    /// `gE`
+    BigWordEndBackward,
+    /// `)`
+    SentenceForward,
+    /// `(`
+    SentenceBackward,
+    /// `}`
+    ParagraphForward,
+    /// `{`
+    ParagraphBackward,
+    /// `f`, `F`, `t` or `T` and a char
+    Find(Find),
+    /// `;`, or `,` the other way: the last `f`, `F`, `t` or `T` again
+    RepeatFind(bool),
+    /// `%`, the bracket that matches the next one in the line
+    MatchPair,
+    /// `H`, `M` and `L`: the top, middle or bottom line of the window
+    ScreenTop,
+    ScreenMiddle,
+    ScreenBottom,
+    /// the first non-blank of a line, which `H`, `M` and `L` turn into
+    Line(usize),
+    /// `gg`
+    FirstLine,
+    /// `G`
+    LastLine,
+    /// `'x`, the first non-blank of the line of mark `x`
+    MarkLine(char),
+    /// `` `x ``, the place of mark `x`
+    Mark(char),
+}
+/// a search for a char in the line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Find {
+    pub c: char,
+    pub forward: bool,
+    /// stop before the char (`t`) or after it (`T`)
+    pub till: bool,
+}
+/// how much of the text between the cursor and the target an operator takes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    /// up to the target, without it
+    Exclusive,
+    /// up to and with the char on the target
+    Inclusive,
+    /// the whole lines from the cursor to the target
+    Linewise,
+}
+/// how far a sequence of keys got.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parse<T> {
+    /// more keys are needed
+    Pending,
+    Invalid,
+    Done(T),
+}
+
+impl Motion {
+    /// the motion typed as `keys`.
+    pub fn parse(keys: &[char]) -> Parse<Motion> {
+        let motion = match keys {
+            ['h'] => Motion::Left,
+            ['l'] | [' '] => Motion::Right,
+            ['j'] => Motion::Down,
+            ['k'] => Motion::Up,
+            ['0'] => Motion::LineStart,
+            ['^'] => Motion::FirstNonBlank,
+            ['$'] => Motion::LineEnd,
+            ['w'] => Motion::WordForward,
+            ['b'] => Motion::WordBackward,
+            ['e'] => Motion::WordEnd,
+            ['W'] => Motion::BigWordForward,
+            ['B'] => Motion::BigWordBackward,
+            ['E'] => Motion::BigWordEnd,
+            [')'] => Motion::SentenceForward,
+            ['('] => Motion::SentenceBackward,
+            ['}'] => Motion::ParagraphForward,
+            ['{'] => Motion::ParagraphBackward,
+            ['f' | 'F' | 't' | 'T'] => return Parse::Pending,
+            [k @ ('f' | 'F' | 't' | 'T'), c] => Motion::Find(Find {
+                c: *c,
+                forward: *k == 'f' || *k == 't',
+                till: *k == 't' || *k == 'T',
+            }),
+            [';'] => Motion::RepeatFind(false),
+            [','] => Motion::RepeatFind(true),
+            ['%'] => Motion::MatchPair,
+            ['H'] => Motion::ScreenTop,
+            ['M'] => Motion::ScreenMiddle,
+            ['L'] => Motion::ScreenBottom,
+            ['G'] => Motion::LastLine,
+            ['g'] => return Parse::Pending,
+            ['g', 'g'] => Motion::FirstLine,
+            ['g', 'e'] => Motion::WordEndBackward,
+            ['g', 'E'] => Motion::BigWordEndBackward,
+            ['\''] | ['`'] => return Parse::Pending,
+            ['\'', c] if is_mark(*c) => Motion::MarkLine(*c),
+            ['`', c] if is_mark(*c) => Motion::Mark(*c),
+            _ => return Parse::Invalid,
+        };
+        Parse::Done(motion)
+    }
+    pub fn kind(self) -> MotionKind {
+        match self {
+            Motion::Down
+            | Motion::Up
+            | Motion::FirstLine
+            | Motion::LastLine
+            | Motion::MarkLine(_)
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom
+            | Motion::Line(_) => MotionKind::Linewise,
+            Motion::LineEnd
+            | Motion::WordEnd
+            | Motion::WordEndBackward
+            | Motion::BigWordEnd
+            | Motion::BigWordEndBackward
+            | Motion::MatchPair => MotionKind::Inclusive,
+            Motion::Find(f) if f.forward => MotionKind::Inclusive,
+            _ => MotionKind::Exclusive,
+        }
+    }
+    /// the cursor jumps far. where it was is kept in the jump list.
+    pub fn is_jump(self) -> bool {
+        matches!(
+            self,
+            Motion::FirstLine
+                | Motion::LastLine
+                | Motion::SentenceForward
+                | Motion::SentenceBackward
+                | Motion::ParagraphForward
+                | Motion::ParagraphBackward
+                | Motion::MatchPair
+                | Motion::Line(_)
+                | Motion::MarkLine(_)
+                | Motion::Mark(_)
+        )
+    }
+    /// where the motion goes from `from`. None when it cannot move at all, and for `;`,
+    /// `,`, `H`, `M` and `L`, which normal mode turns into other motions first.
+    /// an operator may take the end of the line (`dl` on the last char), the cursor may not.
+    pub fn target(self, buf: &FileBuffer, from: Point) -> Option<Point> {
+        let last = buf.get_row_length().saturating_sub(1);
+        let len = buf.get_col_length(from.row);
+        let target = match self {
+            Motion::Left if from.col > 0 => Point {
+                col: prev_grapheme(&buf.get_line(from.row), from.col),
+                row: from.row,
+            },
+            Motion::Right if from.col < len => Point {
+                col: next_grapheme(&buf.get_line(from.row), from.col),
+                row: from.row,
+            },
+            Motion::Down if from.row < last => Point {
+                col: from.col,
+                row: from.row + 1,
+            },
+            Motion::Up if from.row > 0 => Point {
+                col: from.col,
+                row: from.row - 1,
+            },
+            Motion::Left | Motion::Right | Motion::Down | Motion::Up => return None,
+            Motion::LineStart => Point {
+                col: 0,
+                row: from.row,
+            },
+            Motion::LineEnd => Point {
+                col: len.saturating_sub(1),
+                row: from.row,
+            },
+            Motion::FirstNonBlank => first_non_blank(buf, from.row),
+            Motion::WordForward => word_forward(buf, from, false),
+            Motion::WordBackward => word_backward(buf, from, false),
+            Motion::WordEnd => word_end(buf, from, false),
+            Motion::WordEndBackward => word_end_backward(buf, from, false),
+            Motion::BigWordForward => word_forward(buf, from, true),
+            Motion::BigWordBackward => word_backward(buf, from, true),
+            Motion::BigWordEnd => word_end(buf, from, true),
+            Motion::BigWordEndBackward => word_end_backward(buf, from, true),
+            Motion::SentenceForward => sentence_forward(buf, from),
+            Motion::SentenceBackward => sentence_backward(buf, from),
+            Motion::Find(f) => f.target(buf, from, 1)?,
+            Motion::MatchPair => match_pair(buf, from)?,
+            Motion::Line(row) => first_non_blank(buf, row.min(last)),
+            Motion::RepeatFind(_)
+            | Motion::ScreenTop
+            | Motion::ScreenMiddle
+            | Motion::ScreenBottom => return None,
+            Motion::ParagraphForward => paragraph(buf, from, true),
+            Motion::ParagraphBackward => paragraph(buf, from, false),
+            Motion::FirstLine => first_non_blank(buf, 0),
+            Motion::LastLine => first_non_blank(buf, last),
+            Motion::MarkLine(c) => first_non_blank(buf, buf.get_marks().get(c)?.row.min(last)),
+            Motion::Mark(c) => {
+                let p = buf.get_marks().get(c)?;
+                let row = p.row.min(last);
+                Point {
+                    col: p.col.min(buf.get_col_length(row)),
+                    row,
+                }
+            }
+        };
+        Some(target)
+    }
+}
+impl Find {
+    /// the `count`th match of the char from `from`, or the char before (after) it.
+    pub fn target(self, buf: &FileBuffer, from: Point, count: usize) -> Option<Point> {
+        let line: Vec<char> = buf.get_line(from.row).chars().collect();
+        let mut col = from.col;
+        for _ in 0..count {
+            col = match self.forward {
+                true => (col + 1..line.len()).find(|i| line[*i] == self.c)?,
+                false => (0..col).rev().find(|i| line[*i] == self.c)?,
+            };
+        }
+        if self.till {
+            col = if self.forward { col - 1 } else { col + 1 };
+        }
+        Some(Point { col, row: from.row })
+    }
+}
+/// the first char of a line that is not a blank.
+pub fn first_non_blank(buf: &FileBuffer, row: usize) -> Point {
+    let col = buf
+        .get_line(row)
+        .chars()
+        .position(|c| c != ' ' && c != '\t')
+        .unwrap_or(0);
+    Point { col, row }
+}
+/// words are runs of letters, digits and `_`, or runs of other non-blank chars.
+pub fn class(c: Option<char>) -> u8 {
+    match c {
+        None => 0,
+        Some(c) if c.is_whitespace() => 0,
+        Some(c) if c.is_alphanumeric() || c == '_' => 2,
+        Some(_) => 1,
+    }
+}
+/// the class of `c` for the words of `w` (or the WORDs of `W` when `big`), where any
+/// non-blank chars make a WORD.
+fn word_class(c: Option<char>, big: bool) -> u8 {
+    match big {
+        true => class(c).min(1),
+        false => class(c),
+    }
+}
+/// steps through the buffer a char at a time. the end of a line counts as a char (None).
+struct Walker<'a> {
+    buf: &'a FileBuffer,
+    line: Vec<char>,
+    row: usize,
+    col: usize,
+}
+
+impl<'a> Walker<'a> {
+    fn new(buf: &'a FileBuffer, at: Point) -> Walker<'a> {
+        let line: Vec<char> = buf.get_line(at.row).chars().collect();
+        Walker {
+            buf,
+            col: at.col.min(line.len()),
+            line,
+            row: at.row,
+        }
+    }
+    fn point(&self) -> Point {
+        Point {
+            col: self.col,
+            row: self.row,
+        }
+    }
+    fn get(&self) -> Option<char> {
+        self.line.get(self.col).copied()
+    }
+    /// on an empty line, which counts as a word of its own.
+    fn empty_line(&self) -> bool {
+        self.line.is_empty()
+    }
+    fn goto_row(&mut self, row: usize) {
+        self.row = row;
+        self.line = self.buf.get_line(row).chars().collect();
+    }
+    /// step forward. false at the end of the buffer.
+    fn next(&mut self) -> bool {
+        if self.col < self.line.len() {
+            self.col += 1;
+        } else if self.row + 1 < self.buf.get_row_length() {
+            self.goto_row(self.row + 1);
+            self.col = 0;
+        } else {
+            return false;
+        }
+        true
+    }
+    /// step back. false at the start of the buffer.
+    fn prev(&mut self) -> bool {
+        if self.col > 0 {
+            self.col -= 1;
+        } else if self.row > 0 {
+            self.goto_row(self.row - 1);
+            self.col = self.line.len();
+        } else {
+            return false;
+        }
+        true
+    }
+}
+fn word_forward(buf: &FileBuffer, from: Point, big: bool) -> Point {
+    let mut w = Walker::new(buf, from);
+    let start = word_class(w.get(), big);
+    if start != 0 {
+        while word_class(w.get(), big) == start {
+            if !w.next() {
+                return w.point();
+            }
+        }
+    }
+    while word_class(w.get(), big) == 0 {
+        if w.empty_line() && w.row != from.row {
+            break;
+        }
+        if !w.next() {
+            break;
+        }
+    }
+    w.point()
+}
+fn word_backward(buf: &FileBuffer, from: Point, big: bool) -> Point {
+    let mut w = Walker::new(buf, from);
+    if !w.prev() {
+        return w.point();
+    }
+    while word_class(w.get(), big) == 0 && !w.empty_line() {
+        if !w.prev() {
+            return w.point();
+        }
+    }
+    let c = word_class(w.get(), big);
+    while w.col > 0 && word_class(w.line.get(w.col - 1).copied(), big) == c && c != 0 {
+        w.col -= 1;
+    }
+    w.point()
+}
+/// the last char of the word (WORD) under the cursor, for `cw` and `cW`. None on a blank.
+pub fn current_word_end(buf: &FileBuffer, from: Point, big: bool) -> Option<Point> {
+    let line: Vec<char> = buf.get_line(from.row).chars().collect();
+    let c = word_class(line.get(from.col).copied(), big);
+    if c == 0 {
+        return None;
+    }
+    let mut col = from.col;
+    while word_class(line.get(col + 1).copied(), big) == c {
+        col += 1;
+    }
+    Some(Point { col, row: from.row })
+}
+fn word_end(buf: &FileBuffer, from: Point, big: bool) -> Point {
+    let mut w = Walker::new(buf, from);
+    if !w.next() {
+        return from;
+    }
+    while word_class(w.get(), big) == 0 {
+        if !w.next() {
+            return w.point();
+        }
+    }
+    let c = word_class(w.get(), big);
+    while word_class(w.line.get(w.col + 1).copied(), big) == c {
+        w.col += 1;
+    }
+    w.point()
+}
+/// the last char of the word before the one under the cursor. an empty line is a word.
+fn word_end_backward(buf: &FileBuffer, from: Point, big: bool) -> Point {
+    let mut w = Walker::new(buf, from);
+    let start = word_class(w.get(), big);
+    if start != 0 {
+        while word_class(w.get(), big) == start {
+            if !w.prev() {
+                return w.point();
+            }
+        }
+    }
+    while word_class(w.get(), big) == 0 {
+        if w.empty_line() && w.row != from.row {
+            break;
+        }
+        if !w.prev() {
+            break;
+        }
+    }
+    w.point()
+}
+/// the start of the next sentence. a sentence ends at `.`, `!` or `?` followed by
+/// closing brackets or quotes and a blank or the end of the line. an empty line
+/// ends one too, and is a sentence of its own.
+fn sentence_forward(buf: &FileBuffer, from: Point) -> Point {
+    let mut w = Walker::new(buf, from);
+    loop {
+        let (c, was_empty) = (w.get(), w.empty_line());
+        if !w.next() {
+            return w.point();
+        }
+        if w.empty_line() {
+            if was_empty {
+                continue;
+            }
+            return w.point();
+        }
+        let end = matches!(c, Some('.' | '!' | '?'));
+        if end {
+            while matches!(w.get(), Some(')' | ']' | '"' | '\'')) {
+                w.next();
+            }
+        }
+        if was_empty || (end && class(w.get()) == 0) {
+            while class(w.get()) == 0 && !w.empty_line() && w.next() {}
+            return w.point();
+        }
+    }
+}
+/// the start of the sentence the cursor is in, or of the one before when the cursor
+/// is on its start. the sentences are found from the start of the paragraph.
+fn sentence_backward(buf: &FileBuffer, from: Point) -> Point {
+    let before = |p: Point| (p.row, p.col) < (from.row, from.col);
+    let mut at = paragraph(buf, from, false);
+    loop {
+        let next = sentence_forward(buf, at);
+        if !before(next) || next == at {
+            return at;
+        }
+        at = next;
+    }
+}
+/// the bracket that matches the first one of `()[]{}` at or after the cursor in the line.
+fn match_pair(buf: &FileBuffer, from: Point) -> Option<Point> {
+    const PAIRS: &str = "()[]{}";
+    let line: Vec<char> = buf.get_line(from.row).chars().collect();
+    let col = (from.col..line.len()).find(|i| PAIRS.contains(line[*i]))?;
+    let c = line[col];
+    let i = PAIRS.find(c)?;
+    let forward = i % 2 == 0;
+    let other = PAIRS.chars().nth(i ^ 1)?;
+    let mut w = Walker::new(buf, Point { col, row: from.row });
+    let mut depth = 0;
+    loop {
+        if w.get() == Some(c) {
+            depth += 1;
+        } else if w.get() == Some(other) {
+            depth -= 1;
+            if depth == 0 {
+                return Some(w.point());
+            }
+        }
+        let moved = if forward { w.next() } else { w.prev() };
+        if !moved {
+            return None;
+        }
+    }
+}
+/// the next (or previous) empty line after the current paragraph.
+/// the last (first) line of the buffer when there is none.
+fn paragraph(buf: &FileBuffer, from: Point, forward: bool) -> Point {
+    let last = buf.get_row_length().saturating_sub(1);
+    let empty = |row: usize| buf.get_col_length(row) == 0;
+    let mut row = from.row;
+    let step = |row: usize| if forward { row + 1 } else { row - 1 };
+    let at_end = |row: usize| if forward { row >= last } else { row == 0 };
+    while !at_end(row) && empty(step(row)) {
+        row = step(row);
+    }
+    while !at_end(row) {
+        row = step(row);
+        if empty(row) {
+            return Point { col: 0, row };
+        }
+    }
+    let col = if forward { buf.get_col_length(row) } else { 0 };
+    Point { col, row }
+}
+#[cfg(test)]
+mod motion_test {
+    use super::*;
+
+    fn p(col: usize, row: usize) -> Point {
+        Point { col, row }
+    }
+    fn go(buf: &FileBuffer, motion: Motion, col: usize, row: usize) -> (usize, usize) {
+        let p = motion.target(buf, Point { col, row }).unwrap();
+        (p.col, p.row)
+    }
+    #[test]
+    fn test_words() {
+        let buf = FileBuffer::from_text("words.txt", "foo.bar  baz\n\n  qux\n");
+        assert_eq!(go(&buf, Motion::WordForward, 0, 0), (3, 0));
+        assert_eq!(go(&buf, Motion::WordForward, 3, 0), (4, 0));
+        assert_eq!(go(&buf, Motion::WordForward, 4, 0), (9, 0));
+        // an empty line is a word.
+        assert_eq!(go(&buf, Motion::WordForward, 9, 0), (0, 1));
+        assert_eq!(go(&buf, Motion::WordForward, 0, 1), (2, 2));
+        assert_eq!(go(&buf, Motion::WordBackward, 2, 2), (0, 1));
+        assert_eq!(go(&buf, Motion::WordBackward, 0, 1), (9, 0));
+        assert_eq!(go(&buf, Motion::WordBackward, 10, 0), (9, 0));
+        assert_eq!(go(&buf, Motion::WordBackward, 4, 0), (3, 0));
+        assert_eq!(go(&buf, Motion::WordEnd, 0, 0), (2, 0));
+        assert_eq!(go(&buf, Motion::WordEnd, 2, 0), (3, 0));
+        assert_eq!(go(&buf, Motion::WordEnd, 9, 0), (11, 0));
+        assert_eq!(go(&buf, Motion::WordEnd, 11, 0), (4, 2));
+        assert_eq!(go(&buf, Motion::WordEndBackward, 4, 2), (0, 1));
+        assert_eq!(go(&buf, Motion::WordEndBackward, 0, 1), (11, 0));
+        assert_eq!(go(&buf, Motion::WordEndBackward, 9, 0), (6, 0));
+        assert_eq!(go(&buf, Motion::WordEndBackward, 5, 0), (3, 0));
+        // WORDs are runs of any non-blank chars.
+        assert_eq!(go(&buf, Motion::BigWordForward, 0, 0), (9, 0));
+        assert_eq!(go(&buf, Motion::BigWordBackward, 9, 0), (0, 0));
+        assert_eq!(go(&buf, Motion::BigWordEnd, 0, 0), (6, 0));
+        assert_eq!(go(&buf, Motion::BigWordEndBackward, 10, 0), (6, 0));
+        assert_eq!(
+            current_word_end(&buf, Point { col: 1, row: 0 }, true)
+                .unwrap()
+                .col,
+            6
+        );
+    }
+    #[test]
+    fn test_sentences() {
+        let buf = FileBuffer::from_text("sentences.txt", "One. Two!  (Three\nfour.)\n\nFive?\n");
+        assert_eq!(go(&buf, Motion::SentenceForward, 0, 0), (5, 0));
+        assert_eq!(go(&buf, Motion::SentenceForward, 5, 0), (11, 0));
+        assert_eq!(go(&buf, Motion::SentenceForward, 11, 0), (0, 2));
+        assert_eq!(go(&buf, Motion::SentenceForward, 0, 2), (0, 3));
+        assert_eq!(go(&buf, Motion::SentenceBackward, 2, 1), (11, 0));
+        assert_eq!(go(&buf, Motion::SentenceBackward, 11, 0), (5, 0));
+        assert_eq!(go(&buf, Motion::SentenceBackward, 0, 3), (0, 2));
+        assert_eq!(go(&buf, Motion::SentenceBackward, 0, 2), (11, 0));
+    }
+    #[test]
+    fn test_in_line() {
+        let buf = FileBuffer::from_text("in_line.txt", "  f(a, [b]) {\n  x(\n}\n");
+        assert_eq!(go(&buf, Motion::FirstNonBlank, 6, 0), (2, 0));
+        assert_eq!(go(&buf, Motion::MatchPair, 0, 0), (10, 0));
+        assert_eq!(go(&buf, Motion::MatchPair, 10, 0), (3, 0));
+        assert_eq!(go(&buf, Motion::MatchPair, 7, 0), (9, 0));
+        assert_eq!(go(&buf, Motion::MatchPair, 11, 0), (0, 2));
+        assert!(Motion::MatchPair
+            .target(&buf, Point { col: 3, row: 1 })
+            .is_none());
+        let find = |c, forward, till| Find { c, forward, till };
+        let at = Point { col: 0, row: 0 };
+        assert_eq!(find(',', true, false).target(&buf, at, 1), Some(p(5, 0)));
+        assert_eq!(find('b', true, true).target(&buf, at, 1), Some(p(7, 0)));
+        assert_eq!(find(')', true, false).target(&buf, at, 2), None);
+        assert_eq!(
+            find('(', false, true).target(&buf, p(6, 0), 1),
+            Some(p(4, 0))
+        );
+        assert_eq!(
+            Motion::parse(&['t', 'x']),
+            Parse::Done(Motion::Find(find('x', true, true)))
+        );
+        assert_eq!(Motion::parse(&['F']), Parse::Pending);
+        assert_eq!(
+            Motion::parse(&['g', 'E']),
+            Parse::Done(Motion::BigWordEndBackward)
+        );
+        assert_eq!(go(&buf, Motion::Line(1), 0, 0), (2, 1));
+    }
+    #[test]
+    fn test_lines() {
+        let buf = FileBuffer::from_text("lines.txt", "a\nb\n\n\nc\n  d\n");
+        assert_eq!(go(&buf, Motion::ParagraphForward, 0, 0), (0, 2));
+        assert_eq!(go(&buf, Motion::ParagraphForward, 0, 2), (0, 6));
+        assert_eq!(go(&buf, Motion::ParagraphBackward, 0, 5), (0, 3));
+        assert_eq!(go(&buf, Motion::ParagraphBackward, 0, 1), (0, 0));
+        assert_eq!(go(&buf, Motion::LastLine, 0, 0), (0, 6));
+        assert_eq!(go(&buf, Motion::FirstLine, 0, 5), (0, 0));
+        assert_eq!(go(&buf, Motion::LineEnd, 0, 5), (2, 5));
+        assert!(Motion::Up.target(&buf, Point { col: 0, row: 0 }).is_none());
+        assert_eq!(Motion::parse(&['g']), Parse::Pending);
+        assert_eq!(Motion::parse(&['g', 'g']), Parse::Done(Motion::FirstLine));
+        assert_eq!(Motion::parse(&['q']), Parse::Invalid);
+    }
+}
@@ -1,100 +1,1750 @@
+use crate::modules::buffer::{Buffer, BufferList};
+use crate::modules::command;
 use crate::modules::coordinate::Point;
 use crate::modules::file::FileBuffer;
-use crate::modules::insert::delback;
+use crate::modules::insert;
+use crate::modules::macros::{text_to_keys, Macro};
+use crate::modules::mark::is_settable;
 use crate::modules::mode::MODE;
-use crate::modules::show::{Display, MoveDirection};
-use crate::modules::undo::Undo;
+use crate::modules::motion::{current_word_end, first_non_blank, Find, Motion, MotionKind, Parse};
+use crate::modules::operator::{self, Operator, Range};
+use crate::modules::register::{is_register, is_writable, Register, RegisterKind, Registers};
+use crate::modules::show::{Display, MoveDirection, Place};
+use crate::modules::textobject::TextObject;
+use crate::modules::unicode::{display_col_to_char, next_grapheme, prev_grapheme};
+use crate::modules::visual::{column_at, BlockInsert, Selection, VisualKind};
 use crossterm::cursor::SetCursorStyle;
-use crossterm::event::KeyCode;
+use crossterm::event::{KeyCode, KeyEvent};
 
-use super::history::Operation;
+/// a complete normal mode command.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Move(Motion),
+    Operate(Operator, Target),
+    /// a key that is a command by itself, like `i` or `u`
+    Key(char),
+    /// `q{register}`
+    Record(char),
+    /// `@{register}`
+    Play(char),
+    /// `r{char}`
+    ReplaceChar(char),
+    /// `m{mark}`
+    SetMark(char),
+    /// `gv`, select the text selected last again
+    Reselect,
+    /// `z` and a key that places the line of the cursor in the window
+    Redraw(char),
+    /// a text object typed in visual mode, added to the selection
+    Select(TextObject),
+}
+/// the text an operator works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Target {
+    Motion(Motion),
+    /// the current line, when the operator is doubled (`dd`)
+    Line,
+    Object(TextObject),
+}
+/// a command with the count and the register typed before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Parsed {
+    count: Option<usize>,
+    register: Option<char>,
+    command: Command,
+}
 
+/// the keys of a change, replayed by `.`. the count is kept apart so that `.` can
+/// take a new one.
+#[derive(Debug, Clone)]
+struct Change {
+    keys: Vec<KeyCode>,
+    count: Option<usize>,
+}
+
+/// normal mode. keys are gathered until they make a command, like `d` then `w`.
 #[derive(Debug)]
-pub struct Normal {}
+pub struct Normal {
+    /// keys of a command typed so far
+    keys: Vec<char>,
+    /// register named by `"x` for the command being run
+    register: Option<char>,
+    /// the last complete change
+    last_change: Option<Change>,
+    /// a change still being typed, until the insert it started ends
+    change: Option<Change>,
+    /// times the text of the insert is put, `3ifoo`
+    insert_count: usize,
+    /// where the keys typed in the insert start in `change`
+    insert_start: usize,
+    macros: Macro,
+    /// the selection while in visual mode
+    visual: Option<Selection>,
+    /// the other lines of a block that the text of the insert goes to
+    block: Option<BlockInsert>,
+    /// in replace mode, the chars each typed key wrote over, put back by Backspace
+    replaced: Option<Vec<Vec<char>>>,
+    /// the last `f`, `F`, `t` or `T`, for `;` and `,`
+    last_find: Option<Find>,
+    /// in insert mode, a `Ctrl-R` or `Ctrl-V` waiting for the key after it
+    pending: Option<char>,
+    /// after `Ctrl-O` in an insert, the key that starts it again after one command
+    resume: Option<char>,
+    /// the insert was started by `o` or `O`, the text is put on a line of its own each
+    /// time for a count
+    open_lines: bool,
+}
+
+/// split the count off the front of `keys`. a `0` there is the motion, not a count.
+fn split_count(keys: &[char]) -> (Option<usize>, &[char]) {
+    let digits = match keys.first() {
+        Some('1'..='9') => keys.iter().take_while(|c| c.is_ascii_digit()).count(),
+        _ => return (None, keys),
+    };
+    let count = keys[..digits].iter().fold(0usize, |n, c| {
+        n.saturating_mul(10)
+            .saturating_add(c.to_digit(10).unwrap() as usize)
+    });
+    (Some(count), &keys[digits..])
+}
+/// `keys` of a command without its counts, `2d3w` is `dw`.
+fn without_counts(keys: &[char]) -> Vec<char> {
+    let (_, mut rest) = split_count(keys);
+    let mut out = vec![];
+    if let ['"', name, tail @ ..] = rest {
+        out.extend(['"', *name]);
+        rest = split_count(tail).1;
+    }
+    if let Some((_, n)) = Operator::parse(rest) {
+        out.extend(&rest[..n]);
+        rest = split_count(&rest[n..]).1;
+    }
+    out.extend(rest);
+    out
+}
+/// the command changes the text, `.` repeats it.
+fn is_change(command: Command) -> bool {
+    match command {
+        Command::Operate(op, _) => op.changes(),
+        Command::Key(c) => "iIaAoOpPR~".contains(c),
+        Command::ReplaceChar(_) => true,
+        Command::Move(_)
+        | Command::SetMark(_)
+        | Command::Record(_)
+        | Command::Play(_)
+        | Command::Reselect
+        | Command::Redraw(_)
+        | Command::Select(_) => false,
+    }
+}
+/// the command may move the cursor far. where it was is kept in the jump list.
+fn is_jump(command: Command) -> bool {
+    match command {
+        Command::Move(m) => m.is_jump(),
+        Command::Key(c) => c == 'n' || c == 'N',
+        _ => false,
+    }
+}
+/// a key after `z` that places the line of the cursor: `zt`, `zz`, `zb`, and `z<CR>`,
+/// `z.` and `z-`, which also go to the first non-blank.
+fn is_redraw(c: char) -> bool {
+    "tzb\r.-".contains(c)
+}
+/// the keys that scroll the window: `Ctrl-D`, `Ctrl-U`, `Ctrl-F`, `Ctrl-B`, `Ctrl-E`
+/// and `Ctrl-Y`.
+fn is_scroll(c: char) -> bool {
+    "\x04\x15\x06\x02\x05\x19".contains(c)
+}
+/// two counts of a command multiply.
+fn multiply(a: Option<usize>, b: Option<usize>) -> Option<usize> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.saturating_mul(b)),
+        (a, b) => a.or(b),
+    }
+}
+/// split the count and the register `"x` off the front of `keys`. a count may come
+/// on both sides of the register, the two are multiplied.
+fn split_prefix(keys: &[char]) -> Parse<(Option<usize>, Option<char>, &[char])> {
+    let (count, keys) = split_count(keys);
+    match keys {
+        ['"'] => Parse::Pending,
+        ['"', c, ..] if !is_register(*c) => Parse::Invalid,
+        ['"', c, rest @ ..] => {
+            let (count2, rest) = split_count(rest);
+            Parse::Done((multiply(count, count2), Some(*c), rest))
+        }
+        _ => Parse::Done((count, None, keys)),
+    }
+}
+/// parse the keys of a command: `{motion}`, `{operator}{motion}`, `{operator}{operator}`
+/// or a key of its own. a count may come before the command and before the motion
+/// (`2d3w`), the two are multiplied. a register `"x` may come before the command.
+fn parse(keys: &[char]) -> Parse<Parsed> {
+    let (mut count, register, keys) = match split_prefix(keys) {
+        Parse::Done(prefix) => prefix,
+        Parse::Pending => return Parse::Pending,
+        Parse::Invalid => return Parse::Invalid,
+    };
+    let command = match keys {
+        [] => return Parse::Pending,
+        ['x'] => Command::Operate(Operator::Delete, Target::Motion(Motion::Right)),
+        ['X'] => Command::Operate(Operator::Delete, Target::Motion(Motion::Left)),
+        ['D'] => Command::Operate(Operator::Delete, Target::Motion(Motion::LineEnd)),
+        ['C'] => Command::Operate(Operator::Change, Target::Motion(Motion::LineEnd)),
+        ['s'] => Command::Operate(Operator::Change, Target::Motion(Motion::Right)),
+        ['S'] => Command::Operate(Operator::Change, Target::Line),
+        ['Y'] => Command::Operate(Operator::Yank, Target::Line),
+        ['r'] => return Parse::Pending,
+        ['r', c] => Command::ReplaceChar(*c),
+        ['m'] => return Parse::Pending,
+        ['m', c] if is_settable(*c) => Command::SetMark(*c),
+        _ if Operator::parse(keys).is_some() => {
+            let (op, n) = Operator::parse(keys).unwrap();
+            let (count2, rest) = split_count(&keys[n..]);
+            count = multiply(count, count2);
+            match rest {
+                [] => return Parse::Pending,
+                // doubled, or with its last key doubled: `dd`, `g~~`, `gUgU`.
+                _ if rest == &keys[..n] || rest == &keys[n - 1..n] => {
+                    Command::Operate(op, Target::Line)
+                }
+                ['i' | 'a', ..] => match TextObject::parse(rest) {
+                    Parse::Done(o) => Command::Operate(op, Target::Object(o)),
+                    Parse::Pending => return Parse::Pending,
+                    Parse::Invalid => return Parse::Invalid,
+                },
+                _ => match Motion::parse(rest) {
+                    Parse::Done(m) => Command::Operate(op, Target::Motion(m)),
+                    Parse::Pending => return Parse::Pending,
+                    Parse::Invalid => return Parse::Invalid,
+                },
+            }
+        }
+        ['q'] | ['@'] => return Parse::Pending,
+        ['q', c] if c.is_ascii_alphanumeric() || *c == '"' => Command::Record(*c),
+        ['@', c] if is_register(*c) || *c == '@' => Command::Play(*c),
+        ['g', 'v'] => Command::Reselect,
+        ['z'] => return Parse::Pending,
+        ['z', c] if is_redraw(*c) => Command::Redraw(*c),
+        _ => match Motion::parse(keys) {
+            Parse::Done(m) => Command::Move(m),
+            Parse::Pending => return Parse::Pending,
+            Parse::Invalid if keys.len() == 1 => Command::Key(keys[0]),
+            Parse::Invalid => return Parse::Invalid,
+        },
+    };
+    Parse::Done(Parsed {
+        count,
+        register,
+        command,
+    })
+}
+/// parse the keys of a command in visual mode: a motion, a text object, or a key
+/// that works on the selection. a count and a register may come first.
+fn parse_visual(keys: &[char]) -> Parse<Parsed> {
+    let (count, register, keys) = match split_prefix(keys) {
+        Parse::Done(prefix) => prefix,
+        Parse::Pending => return Parse::Pending,
+        Parse::Invalid => return Parse::Invalid,
+    };
+    let command = match keys {
+        [] => return Parse::Pending,
+        ['i' | 'a', ..] => match TextObject::parse(keys) {
+            Parse::Done(o) => Command::Select(o),
+            Parse::Pending => return Parse::Pending,
+            Parse::Invalid => return Parse::Invalid,
+        },
+        ['z'] => return Parse::Pending,
+        ['z', c] if is_redraw(*c) => Command::Redraw(*c),
+        _ => match Motion::parse(keys) {
+            Parse::Done(m) => Command::Move(m),
+            Parse::Pending => return Parse::Pending,
+            Parse::Invalid if keys.len() == 1 => Command::Key(keys[0]),
+            Parse::Invalid => return Parse::Invalid,
+        },
+    };
+    Parse::Done(Parsed {
+        count,
+        register,
+        command,
+    })
+}
 
 impl Normal {
-    pub fn proc_normal(
+    pub fn new() -> Normal {
+        Normal {
+            keys: vec![],
+            register: None,
+            last_change: None,
+            change: None,
+            insert_count: 1,
+            insert_start: 0,
+            macros: Macro::new(),
+            visual: None,
+            block: None,
+            replaced: None,
+            last_find: None,
+            pending: None,
+            resume: None,
+            open_lines: false,
+        }
+    }
+    /// keys of view mode, paging through the file like a pager.
+    /// other keys work as in normal mode.
+    pub fn proc_view(
+        &mut self,
         code: KeyCode,
         display: &mut Display,
-        buf: &mut FileBuffer,
-        undo: &mut Undo,
+        list: &mut BufferList,
+        registers: &mut Registers,
     ) -> MODE {
+        if !self.keys.is_empty() {
+            return self.proc_normal(code, display, list, registers);
+        }
+        let buf = &list.current().file;
         match code {
-            KeyCode::Char(c) => match c {
-                ':' => MODE::Command,
-                'i' => {
-                    display.set_cursor_type(SetCursorStyle::BlinkingBar);
-                    MODE::Insert
-                }
-                'I' => {
-                    display.move_cursor_nextpos(MoveDirection::Head, buf);
-                    display.set_cursor_type(SetCursorStyle::BlinkingBar);
-                    MODE::Insert
-                }
-                'a' => {
-                    display.move_cursor_nextpos(MoveDirection::Right, buf);
-                    display.set_cursor_type(SetCursorStyle::BlinkingBar);
-                    MODE::Insert
-                }
-                'A' => {
-                    display.move_cursor_nextpos(MoveDirection::Tail, buf);
-                    display.set_cursor_type(SetCursorStyle::BlinkingBar);
-                    MODE::Insert
-                }
-                'v' => MODE::Visual,
-                'j' => {
-                    display.move_cursor_nextpos(MoveDirection::Down, &buf);
-                    MODE::Normal
-                }
-                'k' => {
-                    display.move_cursor_nextpos(MoveDirection::Up, &buf);
-                    MODE::Normal
-                }
-                'h' => {
-                    display.move_cursor_nextpos(MoveDirection::Left, buf);
-                    MODE::Normal
-                }
-                'l' => {
-                    display.move_cursor_nextpos(MoveDirection::Right, buf);
-                    MODE::Normal
-                }
-                'x' => {
-                    let col = display.get_cursor_coordinate_in_file().col;
-                    let row = display.get_cursor_coordinate_in_file().row;
-                    let (result, delchar) = delback(
-                        display.get_cursor_coordinate_in_file().col,
-                        display.get_cursor_coordinate_in_file().row,
-                        buf.get_contents(),
-                    );
-                    buf.update_contents(result);
-                    undo.add_do_history(Operation::DELETE, delchar, [col as u32, row as u32]);
-                    display.update_all(buf.get_contents()).unwrap();
-                    MODE::Normal
-                }
-                'u' => {
-                    let pos = undo.undo(buf);
-                    display.update_all(buf.get_contents()).unwrap();
-                    display.move_to_point(
-                        buf,
-                        Point {
-                            col: pos[0] as u16,
-                            row: pos[1] as u16,
-                        },
-                    );
-                    MODE::Normal
-                }
-                '/' => MODE::Search,
-                'n' => {
-                    match buf.get_next_searchresult() {
-                        Some(point) => display.move_to_point(buf, point),
-                        None => (),
-                    };
-                    MODE::Normal
+            KeyCode::Char(' ') | KeyCode::Char('f') | KeyCode::PageDown => {
+                display.scroll_page(buf, true)
+            }
+            KeyCode::Char('b') | KeyCode::PageUp => display.scroll_page(buf, false),
+            KeyCode::Char('g') | KeyCode::Home => {
+                display.move_to_point(buf, Point { col: 0, row: 0 })
+            }
+            KeyCode::Char('G') | KeyCode::End => {
+                let row = buf.get_row_length().saturating_sub(1);
+                display.move_to_point(buf, Point { col: 0, row });
+            }
+            KeyCode::Enter | KeyCode::Down => display.move_cursor_nextpos(MoveDirection::Down, buf),
+            KeyCode::Up => display.move_cursor_nextpos(MoveDirection::Up, buf),
+            _ => return self.proc_normal(code, display, list, registers),
+        }
+        MODE::Normal
+    }
+    pub fn proc_normal(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        list: &mut BufferList,
+        registers: &mut Registers,
+    ) -> MODE {
+        let key = match code {
+            KeyCode::Char(c) => c,
+            KeyCode::Left => 'h',
+            KeyCode::Right => 'l',
+            KeyCode::Up => 'k',
+            KeyCode::Down => 'j',
+            KeyCode::Enter => '\r',
+            KeyCode::Tab => '\t',
+            _ => {
+                // Esc and other keys drop a command typed halfway.
+                self.keys.clear();
+                display.set_pending_keys("");
+                let resume = self.resume.take();
+                return self.resume_insert(
+                    display,
+                    list.current_mut(),
+                    registers,
+                    resume,
+                    MODE::Normal,
+                );
+            }
+        };
+        if key == 'q' && self.keys.is_empty() && self.macros.get_recording().is_some() {
+            self.end_recording(display, registers);
+            return MODE::Normal;
+        }
+        self.keys.push(key);
+        let parsed = parse(&self.keys);
+        if parsed == Parse::Invalid {
+            display.bell();
+        }
+        if parsed == Parse::Pending {
+            display.set_pending_keys(&self.keys.iter().collect::<String>());
+            return MODE::Normal;
+        }
+        let keys = std::mem::take(&mut self.keys);
+        display.set_pending_keys("");
+        let resume = self.resume.take();
+        let Parsed {
+            count,
+            register,
+            command,
+        } = match parsed {
+            Parse::Done(parsed) => parsed,
+            _ => {
+                return self.resume_insert(
+                    display,
+                    list.current_mut(),
+                    registers,
+                    resume,
+                    MODE::Normal,
+                )
+            }
+        };
+        self.register = register;
+        let cur = list.current_mut();
+        let buf = &mut cur.file;
+        let (command, count) = match self.resolve(display, buf, command, count) {
+            Some(resolved) => resolved,
+            None => {
+                display.bell();
+                return MODE::Normal;
+            }
+        };
+        let edits = is_change(command) || matches!(command, Command::Key('u' | '\x12' | '.'));
+        if let (true, Err(e)) = (edits, buf.check_edit()) {
+            display.update_info_line(&e);
+            return MODE::Normal;
+        }
+        if is_change(command) {
+            self.change = Some(Change {
+                keys: without_counts(&keys)
+                    .into_iter()
+                    .map(KeyCode::Char)
+                    .collect(),
+                count,
+            });
+            self.insert_start = self.change.as_ref().unwrap().keys.len();
+            self.insert_count = match command {
+                Command::Key('i' | 'I' | 'a' | 'A' | 'o' | 'O' | 'R') => count.unwrap_or(1),
+                _ => 1,
+            };
+            self.open_lines = matches!(command, Command::Key('o' | 'O'));
+        }
+        buf.get_marks_mut().start_change();
+        let (id, from) = (cur.id, display.get_cursor_coordinate_in_file());
+        let mode = match command {
+            Command::Key('.') => self.repeat(display, list, registers, count),
+            Command::Key('\x0f') => self.follow_jump(display, list, count.unwrap_or(1), true),
+            Command::Key('\t') => self.follow_jump(display, list, count.unwrap_or(1), false),
+            Command::SetMark(c) if c.is_ascii_uppercase() => {
+                list.set_file_mark(c, from);
+                MODE::Normal
+            }
+            Command::SetMark(c) => {
+                list.current_mut().file.get_marks_mut().set(c, from);
+                MODE::Normal
+            }
+            Command::Move(Motion::MarkLine(c) | Motion::Mark(c))
+                if c.is_ascii_uppercase() && list.find_mark(c) != Some(list.current_index()) =>
+            {
+                self.file_mark(display, list, command, c)
+            }
+            _ => self.run(display, list.current_mut(), registers, command, count),
+        };
+        let moved = list.current().id == id && display.get_cursor_coordinate_in_file() != from;
+        if is_jump(command) && moved {
+            list.record_jump(id, from);
+        }
+        // a change that goes on in insert or replace mode ends with the insert.
+        if mode != MODE::Insert && mode != MODE::Replace {
+            self.finish_change();
+        }
+        self.resume_insert(display, list.current_mut(), registers, resume, mode)
+    }
+    /// the command typed after `Ctrl-O` in an insert is done, go on with the insert
+    /// when the command stayed in normal mode.
+    fn resume_insert(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        resume: Option<char>,
+        mode: MODE,
+    ) -> MODE {
+        match resume {
+            Some(key) if mode == MODE::Normal => {
+                self.restart_change(key);
+                display.update_info_line("");
+                self.key(display, cur, registers, key, 1)
+            }
+            _ => mode,
+        }
+    }
+    /// the keys typed from now on in the insert make a change of their own, as if the
+    /// insert started where the cursor is with `key`.
+    fn restart_change(&mut self, key: char) {
+        self.change = Some(Change {
+            keys: vec![KeyCode::Char(key)],
+            count: None,
+        });
+        self.insert_start = 1;
+        self.insert_count = 1;
+        self.open_lines = false;
+    }
+    /// `'A` or `` `A ``: go to a file mark in another buffer, opening its file again
+    /// when the buffer was closed.
+    fn file_mark(
+        &mut self,
+        display: &mut Display,
+        list: &mut BufferList,
+        command: Command,
+        name: char,
+    ) -> MODE {
+        let (id, from) = (list.current().id, display.get_cursor_coordinate_in_file());
+        let result = match (
+            list.find_mark(name),
+            list.closed_mark(name).map(String::from),
+        ) {
+            (Some(i), _) => command::switch(display, list, false, i),
+            (None, Some(path)) => command::open(display, list, false, &path, None),
+            (None, None) => Err(String::from("[E20] mark not set")),
+        };
+        match result {
+            Ok(msg) => display.update_info_line(&msg),
+            Err(e) => {
+                display.update_info_line(&e);
+                return MODE::Normal;
+            }
+        }
+        let buf = &list.current().file;
+        if let Command::Move(m) = command {
+            if let Some(to) = m.target(buf, display.get_cursor_coordinate_in_file()) {
+                display.move_to_point(buf, to);
+                list.record_jump(id, from);
+            }
+        }
+        MODE::Normal
+    }
+    /// `Ctrl-O` (`Ctrl-I` when not `back`): go `count` places back (or forward) in the
+    /// jump list, to another buffer when the jump was there.
+    fn follow_jump(
+        &mut self,
+        display: &mut Display,
+        list: &mut BufferList,
+        count: usize,
+        back: bool,
+    ) -> MODE {
+        let jump = match back {
+            true => list.jump_back(display.get_cursor_coordinate_in_file(), count),
+            false => list.jump_forward(count),
+        };
+        let index = match jump.and_then(|j| list.find_id(j.id)) {
+            Some(i) => i,
+            None => {
+                display.bell();
+                return MODE::Normal;
+            }
+        };
+        if index != list.current_index() {
+            match command::switch(display, list, false, index) {
+                Ok(msg) => display.update_info_line(&msg),
+                Err(e) => {
+                    display.update_info_line(&e);
+                    return MODE::Normal;
+                }
+            }
+        }
+        display.move_to_point(&list.current().file, jump.unwrap().at);
+        MODE::Normal
+    }
+    /// turn the motions that depend on more than the text into ones that do not: `;`
+    /// and `,` into the last find, `H`, `M` and `L` into a line of the window. a find
+    /// is kept for `;`. None when there is no find to repeat.
+    fn resolve(
+        &mut self,
+        display: &Display,
+        buf: &FileBuffer,
+        command: Command,
+        count: Option<usize>,
+    ) -> Option<(Command, Option<usize>)> {
+        let motion = match command {
+            Command::Move(m) | Command::Operate(_, Target::Motion(m)) => m,
+            _ => return Some((command, count)),
+        };
+        let n = count.unwrap_or(1);
+        let (motion, count) = match motion {
+            Motion::Find(f) => {
+                self.last_find = Some(f);
+                (motion, count)
+            }
+            Motion::RepeatFind(reverse) => {
+                let mut f = self.last_find?;
+                f.forward ^= reverse;
+                // a till next to its char would not move, it goes on to the next one.
+                let from = display.get_cursor_coordinate_in_file();
+                let stuck = f.till && f.target(buf, from, 1) == Some(from);
+                (Motion::Find(f), Some(n + stuck as usize))
+            }
+            Motion::ScreenTop | Motion::ScreenMiddle | Motion::ScreenBottom => {
+                let (top, bottom) = display.window_rows(buf);
+                let row = match motion {
+                    Motion::ScreenTop => top.saturating_add(n - 1).min(bottom),
+                    Motion::ScreenBottom => bottom.saturating_sub(n - 1).max(top),
+                    _ => (top + bottom) / 2,
+                };
+                (Motion::Line(row), None)
+            }
+            _ => (motion, count),
+        };
+        match command {
+            Command::Operate(op, _) => Some((Command::Operate(op, Target::Motion(motion)), count)),
+            _ => Some((Command::Move(motion), count)),
+        }
+    }
+    /// `q` while recording: keep the keys typed in the register.
+    fn end_recording(&mut self, display: &mut Display, registers: &mut Registers) {
+        self.macros.unrecord();
+        if let Some((name, text)) = self.macros.stop_recording() {
+            registers.record(name, text);
+        }
+        display.update_info_line("");
+    }
+    /// keys of visual mode. motions and text objects move the cursor end of the
+    /// selection, other keys work on the selected text.
+    pub fn proc_visual(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+    ) -> MODE {
+        let key = match code {
+            KeyCode::Char(c) => c,
+            KeyCode::Left => 'h',
+            KeyCode::Right => 'l',
+            KeyCode::Up => 'k',
+            KeyCode::Down => 'j',
+            KeyCode::Esc => {
+                self.keys.clear();
+                display.set_pending_keys("");
+                return self.end_visual(display, cur);
+            }
+            _ => {
+                self.keys.clear();
+                display.set_pending_keys("");
+                return MODE::Visual;
+            }
+        };
+        if key == 'q' && self.keys.is_empty() && self.macros.get_recording().is_some() {
+            self.end_recording(display, registers);
+            return MODE::Visual;
+        }
+        self.keys.push(key);
+        let parsed = parse_visual(&self.keys);
+        if parsed == Parse::Pending {
+            display.set_pending_keys(&self.keys.iter().collect::<String>());
+            return MODE::Visual;
+        }
+        self.keys.clear();
+        display.set_pending_keys("");
+        let (mut sel, parsed) = match (self.visual, parsed) {
+            (Some(sel), Parse::Done(parsed)) => (sel, parsed),
+            (None, _) => return MODE::Normal,
+            _ => {
+                display.bell();
+                return MODE::Visual;
+            }
+        };
+        self.register = parsed.register;
+        sel.cursor = display.get_cursor_coordinate_in_file();
+        cur.file.get_marks_mut().start_change();
+        let buf = &cur.file;
+        match parsed.command {
+            Command::Move(_) => match self.resolve(display, buf, parsed.command, parsed.count) {
+                Some((Command::Move(m), count)) => {
+                    move_cursor(display, buf, m, count);
+                    // a block after `$` stays at the ends of the lines while going up and down.
+                    sel.to_end = m == Motion::LineEnd
+                        || (sel.to_end && matches!(m, Motion::Down | Motion::Up));
+                }
+                _ => display.bell(),
+            },
+            Command::Select(o) => select_object(display, buf, &mut sel, o, parsed.count),
+            Command::Redraw(c) => redraw(display, buf, c, parsed.count),
+            Command::Key(c) if is_scroll(c) => scroll(display, buf, c, parsed.count),
+            Command::Key(c) => return self.visual_key(display, cur, registers, sel, c),
+            _ => display.bell(),
+        }
+        sel.cursor = display.get_cursor_coordinate_in_file();
+        self.show_selection(display, buf, sel);
+        MODE::Visual
+    }
+    /// start selecting at the cursor.
+    fn start_visual(&mut self, display: &mut Display, buf: &FileBuffer, kind: VisualKind) {
+        let sel = Selection::new(kind, display.get_cursor_coordinate_in_file());
+        display.update_info_line(kind.name());
+        self.show_selection(display, buf, sel);
+    }
+    fn show_selection(&mut self, display: &mut Display, buf: &FileBuffer, sel: Selection) {
+        self.visual = Some(sel);
+        display.set_selection(Some(sel));
+        display.update_all(buf).unwrap();
+    }
+    /// leave visual mode. the selection is kept for `gv`, its ends as the marks `<`
+    /// and `>`.
+    fn end_visual(&mut self, display: &mut Display, cur: &mut Buffer) -> MODE {
+        if let Some(sel) = self.visual.take() {
+            let sel = Selection {
+                cursor: display.get_cursor_coordinate_in_file(),
+                ..sel
+            };
+            let marks = cur.file.get_marks_mut();
+            marks.set('<', sel.start());
+            marks.set('>', sel.end());
+            cur.visual = Some(sel);
+        }
+        display.set_selection(None);
+        display.update_info_line("");
+        display.update_all(&cur.file).unwrap();
+        MODE::Normal
+    }
+    /// `gv`: select the text selected last in the buffer again. its ends are the marks
+    /// `<` and `>`, which moved with the edits since.
+    fn reselect(&mut self, display: &mut Display, cur: &Buffer) -> MODE {
+        let buf = &cur.file;
+        let marks = buf.get_marks();
+        let mut sel = match (cur.visual, marks.get('<'), marks.get('>')) {
+            (Some(sel), Some(start), Some(end)) => match sel.cursor == sel.end() {
+                true => Selection {
+                    anchor: start,
+                    cursor: end,
+                    ..sel
+                },
+                false => Selection {
+                    anchor: end,
+                    cursor: start,
+                    ..sel
+                },
+            },
+            _ => {
+                display.bell();
+                return MODE::Normal;
+            }
+        };
+        let row = sel.anchor.row.min(buf.get_row_length().saturating_sub(1));
+        sel.anchor = Point {
+            col: sel.anchor.col.min(buf.get_col_length(row)),
+            row,
+        };
+        display.jump_to(buf, sel.cursor);
+        sel.cursor = display.get_cursor_coordinate_in_file();
+        display.update_info_line(sel.kind.name());
+        self.show_selection(display, buf, sel);
+        MODE::Visual
+    }
+    /// a key of visual mode that is not a motion.
+    fn visual_key(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        mut sel: Selection,
+        c: char,
+    ) -> MODE {
+        let buf = &cur.file;
+        match c {
+            'v' | 'V' | '\x16' => {
+                let kind = VisualKind::from_key(c).unwrap();
+                if kind == sel.kind {
+                    return self.end_visual(display, cur);
+                }
+                sel.kind = kind;
+                display.update_info_line(kind.name());
+            }
+            // `O` in a block goes to the other corner on the same line.
+            'O' if sel.kind == VisualKind::Block => {
+                let (anchor, cursor) = (sel.anchor, sel.cursor);
+                sel.anchor.col = cursor.col;
+                sel.cursor.col = anchor.col;
+                display.jump_to(buf, sel.cursor);
+            }
+            'o' | 'O' => {
+                (sel.anchor, sel.cursor) = (sel.cursor, sel.anchor);
+                display.jump_to(buf, sel.cursor);
+            }
+            ':' => {
+                self.end_visual(display, cur);
+                return MODE::Command;
+            }
+            'd' | 'x' | 'c' | 's' | 'y' | '>' | '<' | '=' | '~' | 'u' | 'U' | 'J' | 'I' | 'A' => {
+                return self.visual_operate(display, cur, registers, sel, c);
+            }
+            _ => display.bell(),
+        }
+        self.show_selection(display, buf, sel);
+        MODE::Visual
+    }
+    /// work on the selected text with the key `c`, leaving visual mode. a change is
+    /// kept for `.` as the keys that select as much text from the cursor.
+    fn visual_operate(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        sel: Selection,
+        c: char,
+    ) -> MODE {
+        let op = match c {
+            'd' | 'x' => Some(Operator::Delete),
+            'c' | 's' => Some(Operator::Change),
+            'y' => Some(Operator::Yank),
+            '>' => Some(Operator::ShiftRight),
+            '<' => Some(Operator::ShiftLeft),
+            '=' => Some(Operator::Reindent),
+            '~' => Some(Operator::ToggleCase),
+            'u' => Some(Operator::Lowercase),
+            'U' => Some(Operator::Uppercase),
+            _ => None,
+        };
+        self.end_visual(display, cur);
+        let changes = op.is_none_or(|op| op.changes());
+        if let (true, Err(e)) = (changes, cur.file.check_edit()) {
+            display.update_info_line(&e);
+            return MODE::Normal;
+        }
+        if changes {
+            let mut keys: Vec<KeyCode> = sel.keys().chars().map(KeyCode::Char).collect();
+            if let Some(name) = self.register {
+                keys.extend([KeyCode::Char('"'), KeyCode::Char(name)]);
+            }
+            keys.push(KeyCode::Char(c));
+            self.insert_start = keys.len();
+            self.insert_count = 1;
+            self.change = Some(Change { keys, count: None });
+        }
+        let buf = &cur.file;
+        let (start, end) = (sel.start(), sel.end());
+        let mode = match op {
+            Some(op @ (Operator::ShiftRight | Operator::ShiftLeft | Operator::Reindent)) => self
+                .apply(
+                    display,
+                    cur,
+                    registers,
+                    op,
+                    Range::lines(start.row, end.row),
+                ),
+            Some(op) if sel.kind == VisualKind::Block => {
+                self.apply_block(display, cur, registers, op, sel)
+            }
+            Some(op) => {
+                let range = sel.range(buf);
+                self.apply(display, cur, registers, op, range)
+            }
+            None if c == 'J' => {
+                let last = buf.get_row_length().saturating_sub(1);
+                if start.row >= last {
+                    display.bell();
+                } else {
+                    cur.undo.start_change();
+                    let end = end.row.max(start.row + 1).min(last);
+                    let at = operator::join(&mut cur.file, &mut cur.undo, start.row, end);
+                    display.update_all(&cur.file).unwrap();
+                    display.jump_to(&cur.file, at);
+                }
+                MODE::Normal
+            }
+            None => self.visual_insert(display, cur, registers, sel, c == 'A'),
+        };
+        if mode != MODE::Insert {
+            self.finish_change();
+        }
+        mode
+    }
+    /// `d`, `c`, `y` and `~` on a block, each line on its own. the text yanked is kept
+    /// as a block.
+    fn apply_block(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        op: Operator,
+        sel: Selection,
+    ) -> MODE {
+        let (buf, undo) = (&mut cur.file, &mut cur.undo);
+        if let Some(name) = self.register.filter(|c| !is_writable(*c)) {
+            if matches!(op, Operator::Yank | Operator::Delete | Operator::Change) {
+                display.update_info_line(&format!("[E354] invalid register name: '{}'", name));
+                return MODE::Normal;
+            }
+        }
+        let parts = sel.block_ranges(buf);
+        let left = sel.block_columns(buf).0;
+        let text: Vec<String> = parts.iter().map(|r| buf.get_text(r.start, r.end)).collect();
+        let reg = Register::new(text.join("\n"), RegisterKind::Blockwise);
+        match op {
+            Operator::Yank => registers.yank(self.register, reg),
+            Operator::Delete | Operator::Change => registers.delete(self.register, reg),
+            _ => (),
+        }
+        if op.changes() {
+            undo.start_change();
+            for r in parts.iter().filter(|r| r.start != r.end) {
+                match op {
+                    Operator::Delete | Operator::Change => operator::delete(buf, undo, *r, false),
+                    _ => operator::change_case(buf, undo, *r, op),
+                };
+            }
+            display.update_all(buf).unwrap();
+        }
+        display.jump_to(buf, parts[0].start);
+        if op == Operator::Change {
+            // the text typed goes to the lines that had text in the block.
+            self.block = Some(BlockInsert {
+                rows: parts[1..]
+                    .iter()
+                    .filter(|r| r.start != r.end)
+                    .map(|r| r.start.row)
+                    .collect(),
+                col: Some(left),
+            });
+            registers.start_insert();
+            display.set_cursor_type(SetCursorStyle::BlinkingBar);
+            return MODE::Insert;
+        }
+        MODE::Normal
+    }
+    /// `I` and `A`: insert before (or after) the selection. on a block the text goes
+    /// on every line of it, `A` fills up short lines with blanks and `I` leaves them.
+    fn visual_insert(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        sel: Selection,
+        after: bool,
+    ) -> MODE {
+        let (buf, undo) = (&mut cur.file, &mut cur.undo);
+        let (start, end) = (sel.start(), sel.end());
+        undo.start_change();
+        registers.start_insert();
+        let at = match sel.kind {
+            VisualKind::Char if after => Point {
+                col: (end.col + 1).min(buf.get_col_length(end.row)),
+                row: end.row,
+            },
+            VisualKind::Char => start,
+            VisualKind::Line if after => Point {
+                col: buf.get_col_length(end.row),
+                row: end.row,
+            },
+            VisualKind::Line => first_non_blank(buf, start.row),
+            VisualKind::Block => {
+                let tabstop = buf.get_options().tabstop;
+                let (left, right) = sel.block_columns(buf);
+                let col = match (after, sel.to_end) {
+                    (true, true) => None,
+                    (true, false) => Some(right),
+                    (false, _) => Some(left),
+                };
+                let rows = (start.row + 1..=end.row).filter(|row| {
+                    let line = buf.get_line(*row);
+                    after || display_col_to_char(&line, left, tabstop) < line.chars().count()
+                });
+                self.block = Some(BlockInsert {
+                    rows: rows.collect(),
+                    col,
+                });
+                Point {
+                    col: column_at(buf, undo, start.row, col),
+                    row: start.row,
+                }
+            }
+        };
+        display.update_all(buf).unwrap();
+        display.jump_to(buf, at);
+        display.set_cursor_type(SetCursorStyle::BlinkingBar);
+        MODE::Insert
+    }
+    /// `@{name}`: play the keys in register `name` `count` times. `@@` plays the
+    /// register played last, `@:` the last command line.
+    fn play(
+        &mut self,
+        display: &mut Display,
+        buf: &FileBuffer,
+        registers: &Registers,
+        name: char,
+        count: usize,
+    ) {
+        let name = match name {
+            '@' => match self.macros.get_last() {
+                Some(name) => name,
+                None => {
+                    display.update_info_line("[E748] no previously used register");
+                    return;
                 }
-
-                _ => MODE::Normal,
             },
+            name => name,
+        };
+        let text = match registers.get(Some(name), buf.get_path()) {
+            Some(reg) if name == ':' => format!(":{}\n", reg.text),
+            Some(reg) => reg.text,
+            None => {
+                display.update_info_line(&format!("[E353] nothing in register {}", name));
+                return;
+            }
+        };
+        let keys = text_to_keys(&text);
+        let mut all = vec![];
+        for _ in 0..count {
+            all.extend(keys.iter().copied());
+        }
+        self.macros.play(name, all);
+    }
+    /// keep a key typed by the user while a macro is recorded.
+    pub fn record_key(&mut self, key: KeyEvent) {
+        self.macros.record(key);
+    }
+    /// the next key of a macro being played.
+    pub fn next_macro_key(&mut self) -> Option<KeyEvent> {
+        self.macros.next_key()
+    }
+    /// stop the macros being played, after a command failed.
+    pub fn stop_macro(&mut self) {
+        self.macros.stop();
+    }
+    fn finish_change(&mut self) {
+        if let Some(change) = self.change.take() {
+            self.last_change = Some(change);
+        }
+    }
+    /// keys of insert and replace mode. they are kept with the command that started the
+    /// insert for `.`, and with a count the text typed is put that many times.
+    pub fn proc_insert(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+    ) -> MODE {
+        // `Ctrl-O` ends the change like Esc, for `.` to repeat.
+        let suspend = code == KeyCode::Char('\x0f') && self.pending.is_none();
+        let insert_key = if self.replaced.is_some() { 'R' } else { 'i' };
+        if let Some(change) = &mut self.change {
+            change.keys.push(if suspend { KeyCode::Esc } else { code });
+        }
+        let (buf, undo) = (&mut cur.file, &mut cur.undo);
+        let at = display.get_cursor_coordinate_in_file();
+        let mut edit = |code, replaced: &mut Option<Vec<Vec<char>>>, pending: &mut Option<char>| {
+            match replaced {
+                Some(replaced) => {
+                    insert::proc_replace(code, display, buf, undo, registers, replaced)
+                }
+                None => insert::proc_insert(code, display, buf, undo, registers, pending),
+            }
+        };
+        let mode = edit(code, &mut self.replaced, &mut self.pending);
+        let moved = matches!(
+            code,
+            KeyCode::Left
+                | KeyCode::Right
+                | KeyCode::Up
+                | KeyCode::Down
+                | KeyCode::Home
+                | KeyCode::End
+        );
+        if moved {
+            self.block = None;
+            self.restart_change(insert_key);
+        }
+        if mode != MODE::Insert && mode != MODE::Replace {
+            if let Some(change) = &self.change {
+                let typed = &change.keys[self.insert_start..change.keys.len() - 1];
+                for _ in 1..self.insert_count {
+                    if self.open_lines {
+                        edit(KeyCode::Enter, &mut self.replaced, &mut self.pending);
+                    }
+                    for key in typed {
+                        edit(*key, &mut self.replaced, &mut self.pending);
+                    }
+                }
+            }
+            if self.replaced.take().is_some() {
+                display.update_info_line("");
+            }
+            if let Some(block) = self.block.take() {
+                if let Some(reg) = registers.get(Some('.'), "") {
+                    block.insert(buf, undo, &reg.text);
+                }
+            }
+            buf.get_marks_mut().set('^', at);
+            self.finish_change();
+            if suspend {
+                self.resume = Some(insert_key);
+                display.update_info_line("-- (insert) --");
+            }
+        }
+        display.update_all(buf).unwrap();
+        mode
+    }
+    /// `.`: run the last change again, with `count` instead of its own when given.
+    fn repeat(
+        &mut self,
+        display: &mut Display,
+        list: &mut BufferList,
+        registers: &mut Registers,
+        count: Option<usize>,
+    ) -> MODE {
+        let change = match &self.last_change {
+            Some(change) => change.clone(),
+            None => return MODE::Normal,
+        };
+        let mut keys: Vec<KeyCode> = match count.or(change.count) {
+            Some(n) => n.to_string().chars().map(KeyCode::Char).collect(),
+            None => vec![],
+        };
+        keys.extend(change.keys);
+        let mut mode = MODE::Normal;
+        for key in keys {
+            mode = match mode {
+                MODE::Normal => self.proc_normal(key, display, list, registers),
+                MODE::Insert | MODE::Replace => {
+                    self.proc_insert(key, display, list.current_mut(), registers)
+                }
+                MODE::Visual => self.proc_visual(key, display, list.current_mut(), registers),
+                _ => break,
+            };
+        }
+        mode
+    }
+    fn run(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        command: Command,
+        count: Option<usize>,
+    ) -> MODE {
+        let buf = &mut cur.file;
+        match command {
+            Command::Move(m) => {
+                move_cursor(display, buf, m, count);
+                MODE::Normal
+            }
+            Command::Operate(op, target) => {
+                self.operate(display, cur, registers, op, target, count)
+            }
+            Command::Record(name) => {
+                self.macros.start_recording(name);
+                display.update_info_line(&format!("recording @{}", name));
+                MODE::Normal
+            }
+            Command::Play(name) => {
+                self.play(display, buf, registers, name, count.unwrap_or(1));
+                MODE::Normal
+            }
+            Command::Reselect => self.reselect(display, cur),
+            Command::Redraw(c) => {
+                redraw(display, buf, c, count);
+                MODE::Normal
+            }
+            // run by `proc_normal`, which has the other buffers.
+            Command::SetMark(_) => MODE::Normal,
+            Command::Select(_) => MODE::Normal,
+            Command::ReplaceChar(c) => {
+                let at = display.get_cursor_coordinate_in_file();
+                cur.undo.start_change();
+                match operator::replace_chars(buf, &mut cur.undo, at, count.unwrap_or(1), c) {
+                    Some(to) => {
+                        display.update_all(buf).unwrap();
+                        display.jump_to(buf, to);
+                    }
+                    None => display.bell(),
+                }
+                MODE::Normal
+            }
+            Command::Key(c) if is_scroll(c) => {
+                scroll(display, buf, c, count);
+                MODE::Normal
+            }
+            Command::Key(c) => self.key(display, cur, registers, c, count.unwrap_or(1)),
+        }
+    }
+    /// apply `op` to the text from the cursor to `target`.
+    fn operate(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        op: Operator,
+        target: Target,
+        count: Option<usize>,
+    ) -> MODE {
+        let buf = &cur.file;
+        let cursor = display.get_cursor_coordinate_in_file();
+        let to = match target {
+            Target::Line => {
+                let last = buf.get_row_length().saturating_sub(1);
+                let end = cursor.row.saturating_add(count.unwrap_or(1) - 1).min(last);
+                let range = Range::lines(cursor.row, end);
+                return self.apply(display, cur, registers, op, range);
+            }
+            // `cw` on a word changes to its end, leaving the blanks after it. `cW` too.
+            Target::Motion(m @ (Motion::WordForward | Motion::BigWordForward))
+                if op == Operator::Change =>
+            {
+                let big = m == Motion::BigWordForward;
+                let word_end = if big {
+                    Motion::BigWordEnd
+                } else {
+                    Motion::WordEnd
+                };
+                match current_word_end(buf, cursor, big) {
+                    Some(end) => {
+                        let end = match count {
+                            Some(n) if n > 1 => counted_target(buf, end, word_end, Some(n - 1)),
+                            _ => Some(end),
+                        };
+                        end.map(|end| (end, MotionKind::Inclusive))
+                    }
+                    None => {
+                        counted_target(buf, cursor, m, count).map(|t| (t, MotionKind::Exclusive))
+                    }
+                }
+            }
+            Target::Object(o) => {
+                return match o.select(buf, cursor, count.unwrap_or(1)) {
+                    Some(range) => self.apply(display, cur, registers, op, range),
+                    None => {
+                        display.bell();
+                        MODE::Normal
+                    }
+                };
+            }
+            Target::Motion(m) => counted_target(buf, cursor, m, count).map(|t| (t, m.kind())),
+        };
+        match (to, target) {
+            (Some((to, kind)), _) => {
+                let range = Range::new(buf, cursor, to, kind);
+                self.apply(display, cur, registers, op, range)
+            }
+            (None, Target::Motion(m)) => {
+                no_target(display, m);
+                MODE::Normal
+            }
+            (None, _) => {
+                display.bell();
+                MODE::Normal
+            }
+        }
+    }
+    fn apply(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        op: Operator,
+        range: Range,
+    ) -> MODE {
+        let (buf, undo) = (&mut cur.file, &mut cur.undo);
+        if let Some(name) = self.register.filter(|c| !is_writable(*c)) {
+            if matches!(op, Operator::Yank | Operator::Delete | Operator::Change) {
+                display.update_info_line(&format!("[E354] invalid register name: '{}'", name));
+                return MODE::Normal;
+            }
+        }
+        let lines = range.end.row - range.start.row + 1;
+        if op.changes() {
+            undo.start_change();
+        }
+        let cursor = match op {
+            Operator::Yank => {
+                registers.yank(self.register, operator::yank(buf, range));
+                // nothing changes, but `[` and `]` go around the text yanked.
+                let last = match range.end {
+                    Point { col, row } if !range.linewise && col > 0 => Point { col: col - 1, row },
+                    end => end,
+                };
+                let marks = buf.get_marks_mut();
+                marks.set('[', range.start);
+                marks.set(']', last);
+                if range.linewise && lines > 2 {
+                    display.update_info_line(&format!("{} lines yanked", lines));
+                }
+                let cursor = display.get_cursor_coordinate_in_file();
+                if range.linewise {
+                    Point {
+                        col: cursor.col,
+                        row: range.start.row,
+                    }
+                } else {
+                    range.start
+                }
+            }
+            Operator::Delete | Operator::Change => {
+                registers.delete(self.register, operator::yank(buf, range));
+                let change = op == Operator::Change;
+                let cursor = operator::delete(buf, undo, range, change);
+                if range.linewise && !change && lines > 2 {
+                    display.update_info_line(&format!("{} fewer lines", lines));
+                }
+                cursor
+            }
+            Operator::ShiftRight | Operator::ShiftLeft => {
+                let right = op == Operator::ShiftRight;
+                if lines > 2 {
+                    let sign = if right { '>' } else { '<' };
+                    display.update_info_line(&format!("{} lines {}ed 1 time", lines, sign));
+                }
+                operator::shift(buf, undo, range, right)
+            }
+            Operator::Reindent => {
+                if lines > 2 {
+                    display.update_info_line(&format!("{} lines indented", lines));
+                }
+                operator::reindent(buf, undo, range)
+            }
+            Operator::ToggleCase | Operator::Lowercase | Operator::Uppercase => {
+                operator::change_case(buf, undo, range, op)
+            }
+        };
+        if op.changes() {
+            display.update_all(buf).unwrap();
+        }
+        display.jump_to(buf, cursor);
+        if op == Operator::Change {
+            registers.start_insert();
+            display.set_cursor_type(SetCursorStyle::BlinkingBar);
+            return MODE::Insert;
+        }
+        MODE::Normal
+    }
+    /// commands of a single key. `u`, `n`, `p` and `P` are repeated `count` times.
+    fn key(
+        &mut self,
+        display: &mut Display,
+        cur: &mut Buffer,
+        registers: &mut Registers,
+        c: char,
+        count: usize,
+    ) -> MODE {
+        let buf = &mut cur.file;
+        match c {
+            ':' => MODE::Command,
+            'R' => {
+                cur.undo.start_change();
+                registers.start_insert();
+                self.replaced = Some(vec![]);
+                display.update_info_line("-- REPLACE --");
+                display.set_cursor_type(SetCursorStyle::SteadyUnderScore);
+                MODE::Replace
+            }
+            '~' => {
+                let at = display.get_cursor_coordinate_in_file();
+                let line = buf.get_line(at.row).into_owned();
+                let mut end = at.col;
+                for _ in 0..count {
+                    end = next_grapheme(&line, end);
+                }
+                if end == at.col {
+                    display.bell();
+                    return MODE::Normal;
+                }
+                cur.undo.start_change();
+                let range = Range {
+                    start: at,
+                    end: Point {
+                        col: end,
+                        row: at.row,
+                    },
+                    linewise: false,
+                };
+                operator::change_case(buf, &mut cur.undo, range, Operator::ToggleCase);
+                // the cursor goes past the chars switched, staying on the line.
+                let col = match end < line.chars().count() {
+                    true => end,
+                    false => prev_grapheme(&line, end),
+                };
+                display.update_all(buf).unwrap();
+                display.jump_to(buf, Point { col, row: at.row });
+                MODE::Normal
+            }
+            'i' | 'I' | 'a' | 'A' | 'o' | 'O' => {
+                // the text typed until Esc is one change.
+                cur.undo.start_change();
+                registers.start_insert();
+                match c {
+                    'I' => display.move_cursor_nextpos(MoveDirection::Head, buf),
+                    'a' => display.move_cursor_nextpos(MoveDirection::Right, buf),
+                    'A' => display.move_cursor_nextpos(MoveDirection::Tail, buf),
+                    'o' | 'O' => {
+                        insert::open_line(display, buf, &mut cur.undo, c == 'o');
+                        display.update_all(buf).unwrap();
+                    }
+                    _ => (),
+                }
+                display.set_cursor_type(SetCursorStyle::BlinkingBar);
+                MODE::Insert
+            }
+            'v' | 'V' | '\x16' => {
+                self.start_visual(display, buf, VisualKind::from_key(c).unwrap());
+                MODE::Visual
+            }
+            'p' | 'P' => {
+                let reg = match registers.get(self.register, buf.get_path()) {
+                    Some(reg) => reg,
+                    None => {
+                        let name = self.register.unwrap_or('"');
+                        display.update_info_line(&format!("[E353] nothing in register {}", name));
+                        return MODE::Normal;
+                    }
+                };
+                let at = display.get_cursor_coordinate_in_file();
+                let cursor = operator::put(buf, &mut cur.undo, &reg, at, c == 'p', count);
+                let lines = reg.text.matches('\n').count() * count;
+                if reg.kind == RegisterKind::Linewise && lines > 2 {
+                    display.update_info_line(&format!("{} more lines", lines));
+                }
+                display.update_all(buf).unwrap();
+                display.jump_to(buf, cursor);
+                MODE::Normal
+            }
+            'u' => {
+                let mut pos = None;
+                for _ in 0..count {
+                    if !cur.undo.can_undo() {
+                        break;
+                    }
+                    pos = Some(cur.undo.undo(buf));
+                }
+                match pos {
+                    Some(pos) => {
+                        display.update_all(buf).unwrap();
+                        display.move_to_point(buf, pos);
+                    }
+                    None => display.update_info_line("Already at oldest change"),
+                }
+                MODE::Normal
+            }
+            // Ctrl-R
+            '\x12' => {
+                let mut pos = None;
+                for _ in 0..count {
+                    if !cur.undo.can_redo() {
+                        break;
+                    }
+                    pos = Some(cur.undo.redo(buf));
+                }
+                match pos {
+                    Some(pos) => {
+                        display.update_all(buf).unwrap();
+                        display.move_to_point(buf, pos);
+                    }
+                    None => display.update_info_line("Already at newest change"),
+                }
+                MODE::Normal
+            }
+            '/' | '?' => {
+                buf.set_search_backward(c == '?');
+                cur.search.set_count(count);
+                MODE::Search
+            }
+            'n' | 'N' => {
+                let mut point = None;
+                for _ in 0..count {
+                    point = buf.repeat_search(c == 'N');
+                }
+                if let Some(point) = point {
+                    display.move_to_point(buf, point);
+                }
+                MODE::Normal
+            }
             _ => MODE::Normal,
         }
     }
 }
+/// where `motion` goes from `from` when it is repeated `count` times.
+/// with a count, `G` and `gg` go to that line and `$` to the end of a later line.
+fn counted_target(
+    buf: &FileBuffer,
+    from: Point,
+    motion: Motion,
+    count: Option<usize>,
+) -> Option<Point> {
+    let last = buf.get_row_length().saturating_sub(1);
+    match (motion, count) {
+        (Motion::FirstLine | Motion::LastLine, Some(n)) => {
+            return Some(first_non_blank(buf, (n - 1).min(last)))
+        }
+        (Motion::LineEnd, Some(n)) => {
+            let row = from.row.saturating_add(n - 1).min(last);
+            return Motion::LineEnd.target(buf, Point { col: 0, row });
+        }
+        (Motion::Find(f), n) => return f.target(buf, from, n.unwrap_or(1)),
+        _ => (),
+    }
+    let mut to = motion.target(buf, from)?;
+    for _ in 1..count.unwrap_or(1) {
+        match motion.target(buf, to) {
+            Some(t) if t != to => to = t,
+            _ => break,
+        }
+    }
+    Some(to)
+}
+/// move the cursor by a motion. up and down keep the column the cursor had, or the
+/// end of the line after `$`.
+fn move_cursor(display: &mut Display, buf: &FileBuffer, motion: Motion, count: Option<usize>) {
+    match motion {
+        Motion::Down | Motion::Up => {
+            display.move_cursor_lines(buf, motion == Motion::Down, count.unwrap_or(1))
+        }
+        _ => {
+            let from = display.get_cursor_coordinate_in_file();
+            match counted_target(buf, from, motion, count) {
+                Some(to) => {
+                    display.jump_to(buf, to);
+                    if motion == Motion::LineEnd {
+                        display.want_line_end();
+                    }
+                }
+                None => no_target(display, motion),
+            }
+        }
+    }
+}
+/// scroll the window with the key `c`, one of `is_scroll`. a count gives the lines
+/// for `Ctrl-D` and `Ctrl-U`, and repeats the others.
+fn scroll(display: &mut Display, buf: &FileBuffer, c: char, count: Option<usize>) {
+    match c {
+        '\x04' | '\x15' => display.scroll_half(buf, c == '\x04', count),
+        '\x06' | '\x02' => {
+            for _ in 0..count.unwrap_or(1) {
+                display.scroll_page(buf, c == '\x06');
+            }
+        }
+        _ => display.scroll_lines(buf, c == '\x05', count.unwrap_or(1)),
+    }
+}
+/// `z` and `c`: place the line of the cursor, or line `count`, in the window.
+fn redraw(display: &mut Display, buf: &FileBuffer, c: char, count: Option<usize>) {
+    let at = display.get_cursor_coordinate_in_file();
+    let last = buf.get_row_length().saturating_sub(1);
+    let row = count.map_or(at.row, |n| (n - 1).min(last));
+    if "\r.-".contains(c) {
+        display.jump_to(buf, first_non_blank(buf, row));
+    } else if row != at.row {
+        display.jump_to(buf, Point { col: at.col, row });
+    }
+    let place = match c {
+        't' | '\r' => Place::Top,
+        'z' | '.' => Place::Middle,
+        _ => Place::Bottom,
+    };
+    display.place_cursor_line(buf, place);
+}
+/// tell that `motion` cannot move: a mark that is not set, or the bell.
+fn no_target(display: &mut Display, motion: Motion) {
+    match motion {
+        Motion::MarkLine(_) | Motion::Mark(_) => display.update_info_line("[E20] mark not set"),
+        _ => display.bell(),
+    }
+}
+/// add the text object `object` to the selection. a single char selected is replaced
+/// by the object at the cursor, a larger selection takes the object after it. whole
+/// lines make the selection linewise.
+fn select_object(
+    display: &mut Display,
+    buf: &FileBuffer,
+    sel: &mut Selection,
+    object: TextObject,
+    count: Option<usize>,
+) {
+    let mut cursor = display.get_cursor_coordinate_in_file();
+    if sel.anchor != sel.cursor && sel.cursor == sel.end() {
+        let len = buf.get_col_length(cursor.row);
+        if cursor.col + 1 < len {
+            cursor.col = next_grapheme(&buf.get_line(cursor.row), cursor.col);
+        } else if cursor.row + 1 < buf.get_row_length() {
+            cursor = Point {
+                col: 0,
+                row: cursor.row + 1,
+            };
+        }
+    }
+    let range = match object.select(buf, cursor, count.unwrap_or(1)) {
+        Some(range) if range.linewise || range.start != range.end => range,
+        _ => {
+            display.bell();
+            return;
+        }
+    };
+    // the selection ends on the last char of the range, past the end of a line
+    // for its line break.
+    let end = match range.end {
+        _ if range.linewise => range.end,
+        Point { col: 0, row } => Point {
+            col: buf.get_col_length(row - 1),
+            row: row - 1,
+        },
+        Point { col, row } => Point {
+            col: prev_grapheme(&buf.get_line(row), col),
+            row,
+        },
+    };
+    if range.linewise && sel.kind == VisualKind::Char {
+        sel.kind = VisualKind::Line;
+        display.update_info_line(sel.kind.name());
+    }
+    let key = |p: &Point| (p.row, p.col);
+    let (start, end) = if sel.anchor == sel.cursor {
+        (range.start, end)
+    } else {
+        (
+            std::cmp::min_by_key(sel.start(), range.start, key),
+            std::cmp::max_by_key(sel.end(), end, key),
+        )
+    };
+    sel.anchor = start;
+    display.jump_to(buf, end);
+}
+#[cfg(test)]
+mod normal_test {
+    use super::*;
+    use crate::modules::textobject::ObjectKind;
+
+    #[test]
+    fn test_parse() {
+        let done = |keys: &str| match parse(&keys.chars().collect::<Vec<char>>()) {
+            Parse::Done(Parsed {
+                count: None,
+                register: None,
+                command,
+            }) => Some(command),
+            _ => None,
+        };
+        assert_eq!(
+            done("dw"),
+            Some(Command::Operate(
+                Operator::Delete,
+                Target::Motion(Motion::WordForward)
+            ))
+        );
+        assert_eq!(
+            done("cc"),
+            Some(Command::Operate(Operator::Change, Target::Line))
+        );
+        assert_eq!(
+            done(">}"),
+            Some(Command::Operate(
+                Operator::ShiftRight,
+                Target::Motion(Motion::ParagraphForward)
+            ))
+        );
+        assert_eq!(
+            done("ygg"),
+            Some(Command::Operate(
+                Operator::Yank,
+                Target::Motion(Motion::FirstLine)
+            ))
+        );
+        assert_eq!(
+            done("ci("),
+            Some(Command::Operate(
+                Operator::Change,
+                Target::Object(TextObject {
+                    kind: ObjectKind::Bracket('(', ')'),
+                    around: false
+                })
+            ))
+        );
+        assert_eq!(parse(&['d', 'a']), Parse::Pending);
+        assert_eq!(parse(&['d', 'i', 'q']), Parse::Invalid);
+        assert_eq!(done("G"), Some(Command::Move(Motion::LastLine)));
+        assert_eq!(done("u"), Some(Command::Key('u')));
+        assert_eq!(parse(&['d']), Parse::Pending);
+        assert_eq!(parse(&['y', 'g']), Parse::Pending);
+        assert_eq!(parse(&['d', 'y']), Parse::Invalid);
+        assert_eq!(parse(&['g', 'x']), Parse::Invalid);
+        assert_eq!(done("qa"), Some(Command::Record('a')));
+        assert_eq!(done("@@"), Some(Command::Play('@')));
+        assert_eq!(parse(&['q', '!']), Parse::Invalid);
+        assert_eq!(done("rx"), Some(Command::ReplaceChar('x')));
+        assert_eq!(parse(&['r']), Parse::Pending);
+        assert_eq!(
+            done("g~~"),
+            Some(Command::Operate(Operator::ToggleCase, Target::Line))
+        );
+        assert_eq!(
+            done("gUgU"),
+            Some(Command::Operate(Operator::Uppercase, Target::Line))
+        );
+        assert_eq!(
+            done("guw"),
+            Some(Command::Operate(
+                Operator::Lowercase,
+                Target::Motion(Motion::WordForward)
+            ))
+        );
+        assert_eq!(parse(&['g', 'u', 'g']), Parse::Pending);
+        assert_eq!(done("zt"), Some(Command::Redraw('t')));
+        assert_eq!(parse(&['z', 'q']), Parse::Invalid);
+        assert_eq!(
+            done("d,"),
+            Some(Command::Operate(
+                Operator::Delete,
+                Target::Motion(Motion::RepeatFind(true))
+            ))
+        );
+    }
+    #[test]
+    fn test_count() {
+        let keys = |keys: &str| parse(&keys.chars().collect::<Vec<char>>());
+        let done = |keys: &str| match parse(&keys.chars().collect::<Vec<char>>()) {
+            Parse::Done(p) => Some((p.count, p.register, p.command)),
+            _ => None,
+        };
+        let delete = |target| Command::Operate(Operator::Delete, target);
+        assert_eq!(done("10dd"), Some((Some(10), None, delete(Target::Line))));
+        assert_eq!(
+            done("2d3w"),
+            Some((Some(6), None, delete(Target::Motion(Motion::WordForward))))
+        );
+        assert_eq!(
+            done("3x"),
+            Some((Some(3), None, delete(Target::Motion(Motion::Right))))
+        );
+        assert_eq!(
+            done("0"),
+            Some((None, None, Command::Move(Motion::LineStart)))
+        );
+        assert_eq!(
+            done("10j"),
+            Some((Some(10), None, Command::Move(Motion::Down)))
+        );
+        assert_eq!(done("2/"), Some((Some(2), None, Command::Key('/'))));
+        assert_eq!(keys("5"), Parse::Pending);
+        assert_eq!(keys("d2"), Parse::Pending);
+        let strip = |keys: &str| -> String {
+            without_counts(&keys.chars().collect::<Vec<char>>())
+                .into_iter()
+                .collect()
+        };
+        assert_eq!(strip("2d3w"), "dw");
+        assert_eq!(strip("3\"a2yy"), "\"ayy");
+        assert_eq!(strip("d0"), "d0");
+        assert_eq!(strip("10x"), "x");
+        assert_eq!(strip("2g~3w"), "g~w");
+    }
+    #[test]
+    fn test_register() {
+        let keys = |keys: &str| parse(&keys.chars().collect::<Vec<char>>());
+        let done = |keys: &str| match parse(&keys.chars().collect::<Vec<char>>()) {
+            Parse::Done(p) => Some((p.count, p.register, p.command)),
+            _ => None,
+        };
+        assert_eq!(
+            done("2\"a3yy"),
+            Some((
+                Some(6),
+                Some('a'),
+                Command::Operate(Operator::Yank, Target::Line)
+            ))
+        );
+        assert_eq!(done("\"_x").map(|d| d.1), Some(Some('_')));
+        assert_eq!(done("\"%p"), Some((None, Some('%'), Command::Key('p'))));
+        assert_eq!(keys("\""), Parse::Pending);
+        assert_eq!(keys("\"!"), Parse::Invalid);
+    }
+    #[test]
+    fn test_counted_target() {
+        let buf = FileBuffer::from_text("counted_target.txt", "one two three\n  four\nfive\n");
+        let at = |col, row| Point { col, row };
+        let go = |m, count| counted_target(&buf, at(0, 0), m, count);
+        assert_eq!(go(Motion::WordForward, Some(2)), Some(at(8, 0)));
+        assert_eq!(go(Motion::Down, Some(100)), Some(at(0, 3)));
+        assert_eq!(go(Motion::LastLine, Some(2)), Some(at(2, 1)));
+        assert_eq!(go(Motion::FirstLine, None), Some(at(0, 0)));
+        assert_eq!(go(Motion::LineEnd, Some(2)), Some(at(5, 1)));
+    }
+}
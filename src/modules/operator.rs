@@ -0,0 +1,566 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::history::Operation;
+use crate::modules::motion::{first_non_blank, MotionKind};
+use crate::modules::option::Options;
+use crate::modules::register::{Register, RegisterKind};
+use crate::modules::undo::Undo;
+use crate::modules::unicode::{char_to_display_col, next_grapheme};
+
+// operators work on the text between the cursor and the target of a motion.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    /// `d`
+    Delete,
+    /// `c`
+    Change,
+    /// `y`
+    Yank,
+    /// `>`
+    ShiftRight,
+    /// `<`
+    ShiftLeft,
+    /// `=`, indent the lines again by the braces
+    Reindent,
+    /// `g~`
+    ToggleCase,
+    /// `gu`
+    Lowercase,
+    /// `gU`
+    Uppercase,
+}
+/// the text an operator works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Range {
+    pub start: Point,
+    /// the first char after the range. the last row for whole lines
+    pub end: Point,
+    pub linewise: bool,
+}
+
+impl Operator {
+    /// the operator typed at the front of `keys`, and the number of keys it takes.
+    pub fn parse(keys: &[char]) -> Option<(Operator, usize)> {
+        let op = match keys {
+            ['d', ..] => (Operator::Delete, 1),
+            ['c', ..] => (Operator::Change, 1),
+            ['y', ..] => (Operator::Yank, 1),
+            ['>', ..] => (Operator::ShiftRight, 1),
+            ['<', ..] => (Operator::ShiftLeft, 1),
+            ['=', ..] => (Operator::Reindent, 1),
+            ['g', '~', ..] => (Operator::ToggleCase, 2),
+            ['g', 'u', ..] => (Operator::Lowercase, 2),
+            ['g', 'U', ..] => (Operator::Uppercase, 2),
+            _ => return None,
+        };
+        Some(op)
+    }
+    /// whether the operator edits the buffer.
+    pub fn changes(self) -> bool {
+        self != Operator::Yank
+    }
+}
+impl Range {
+    /// the range from `from` to the target `to` of a motion of `kind`.
+    pub fn new(buf: &FileBuffer, from: Point, to: Point, kind: MotionKind) -> Range {
+        let (mut start, mut end) = if (to.row, to.col) < (from.row, from.col) {
+            (to, from)
+        } else {
+            (from, to)
+        };
+        match kind {
+            MotionKind::Linewise => return Range::lines(start.row, end.row),
+            MotionKind::Inclusive => end.col = (end.col + 1).min(buf.get_col_length(end.row)),
+            MotionKind::Exclusive => {
+                // an exclusive motion to the start of a later line stops at the end of the
+                // line before, and takes whole lines when it also started before the text.
+                if end.col == 0 && end.row > start.row {
+                    end.row -= 1;
+                    end.col = buf.get_col_length(end.row);
+                    if start.col <= first_non_blank(buf, start.row).col {
+                        start.col = 0;
+                        return Range::lines(start.row, end.row);
+                    }
+                }
+            }
+        }
+        Range {
+            start,
+            end,
+            linewise: false,
+        }
+    }
+    /// the lines `first` to `last`.
+    pub fn lines(first: usize, last: usize) -> Range {
+        Range {
+            start: Point { col: 0, row: first },
+            end: Point { col: 0, row: last },
+            linewise: true,
+        }
+    }
+    fn line_count(&self) -> usize {
+        self.end.row - self.start.row + 1
+    }
+}
+/// the text of `range`. whole lines end with a line break.
+pub fn yank(buf: &FileBuffer, range: Range) -> Register {
+    if !range.linewise {
+        return Register::new(buf.get_text(range.start, range.end), RegisterKind::Charwise);
+    }
+    let mut text = String::new();
+    for line in buf.get_lines(range.start.row).take(range.line_count()) {
+        text.push_str(&line);
+        text.push('\n');
+    }
+    Register::new(text, RegisterKind::Linewise)
+}
+/// remove the text of `range` and record it in `undo`. returns where the cursor goes.
+/// for `change`, whole lines leave an empty line to insert on.
+pub fn delete(buf: &mut FileBuffer, undo: &mut Undo, range: Range, change: bool) -> Point {
+    let (start, end) = if !range.linewise {
+        (range.start, range.end)
+    } else if change {
+        let last = range.end.row;
+        (
+            range.start,
+            Point {
+                col: buf.get_col_length(last),
+                row: last,
+            },
+        )
+    } else if range.end.row + 1 < buf.get_row_length() {
+        (
+            range.start,
+            Point {
+                col: 0,
+                row: range.end.row + 1,
+            },
+        )
+    } else if range.start.row > 0 {
+        // the last line has no line break to take, take the one before the range.
+        let row = range.start.row - 1;
+        (
+            Point {
+                col: buf.get_col_length(row),
+                row,
+            },
+            Point {
+                col: buf.get_col_length(range.end.row),
+                row: range.end.row,
+            },
+        )
+    } else {
+        (
+            range.start,
+            Point {
+                col: buf.get_col_length(range.end.row),
+                row: range.end.row,
+            },
+        )
+    };
+    let count = buf.get_text(start, end).chars().count();
+    let removed = buf.remove_chars(start.col, start.row, count);
+    undo.add_do_history(Operation::DELETE, removed, start);
+    if range.linewise && !change {
+        let row = range.start.row.min(buf.get_row_length().saturating_sub(1));
+        return first_non_blank(buf, row);
+    }
+    range.start
+}
+/// display columns of the blanks that start `line`.
+pub fn indent_width(line: &str, tabstop: usize) -> usize {
+    let blanks = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    char_to_display_col(line, blanks, tabstop)
+}
+/// the indent width of a line that starts with `text` below the line `above`: the
+/// indent of `above`. when `smart`, a `shiftwidth` more after a `{` and one less
+/// before a `}`.
+pub fn indent_below(opt: &Options, above: &str, text: &str, smart: bool) -> usize {
+    let mut width = indent_width(above, opt.tabstop);
+    if smart {
+        if above.trim_end().ends_with('{') {
+            width += opt.get_shiftwidth();
+        }
+        if text.trim_start().starts_with('}') {
+            width = width.saturating_sub(opt.get_shiftwidth());
+        }
+    }
+    width
+}
+/// put `indent` in place of the blanks that start `row`.
+fn set_indent(buf: &mut FileBuffer, undo: &mut Undo, row: usize, indent: &str) {
+    let line = buf.get_line(row);
+    let blanks = line.chars().take_while(|c| *c == ' ' || *c == '\t').count();
+    if line.chars().take(blanks).eq(indent.chars()) {
+        return;
+    }
+    let at = Point { col: 0, row };
+    let removed = buf.remove_chars(0, row, blanks);
+    undo.add_do_history(Operation::DELETE, removed, at);
+    buf.insert_str(0, row, indent);
+    undo.add_do_history(Operation::ADD, indent.chars().collect(), at);
+}
+/// indent (or dedent) the lines of `range` by a `shiftwidth`. empty lines are left alone.
+pub fn shift(buf: &mut FileBuffer, undo: &mut Undo, range: Range, right: bool) -> Point {
+    let opt = buf.get_options().clone();
+    let step = opt.get_shiftwidth();
+    for row in range.start.row..=range.end.row {
+        let line = buf.get_line(row);
+        if line.is_empty() {
+            continue;
+        }
+        let width = indent_width(&line, opt.tabstop);
+        let width = if right {
+            width + step
+        } else {
+            width.saturating_sub(step)
+        };
+        set_indent(buf, undo, row, &opt.indent_string(width));
+    }
+    first_non_blank(buf, range.start.row)
+}
+/// `=`: indent the lines of `range` by the braces, each like the line with text above
+/// it and a `shiftwidth` more after a `{` or less before a `}`. lines of blanks are
+/// emptied.
+pub fn reindent(buf: &mut FileBuffer, undo: &mut Undo, range: Range) -> Point {
+    let opt = buf.get_options().clone();
+    for row in range.start.row..=range.end.row {
+        let line = buf.get_line(row).into_owned();
+        let text = line.trim_start_matches([' ', '\t']);
+        let above = (0..row)
+            .rev()
+            .map(|r| buf.get_line(r))
+            .find(|l| !l.trim().is_empty());
+        let indent = match above {
+            _ if text.is_empty() => String::new(),
+            Some(above) => opt.indent_string(indent_below(&opt, &above, text, true)),
+            None => String::new(),
+        };
+        set_indent(buf, undo, row, &indent);
+    }
+    first_non_blank(buf, range.start.row)
+}
+/// change the case of the letters in `range` as `op` does: switch it, or make them
+/// lowercase or uppercase.
+pub fn change_case(buf: &mut FileBuffer, undo: &mut Undo, range: Range, op: Operator) -> Point {
+    let (start, end) = match range.linewise {
+        true => (
+            range.start,
+            Point {
+                col: buf.get_col_length(range.end.row),
+                row: range.end.row,
+            },
+        ),
+        false => (range.start, range.end),
+    };
+    let text = buf.get_text(start, end);
+    let toggled: String = text
+        .chars()
+        .map(|c| match op {
+            Operator::Lowercase => c.to_lowercase().collect::<String>(),
+            Operator::Uppercase => c.to_uppercase().collect::<String>(),
+            _ if c.is_uppercase() => c.to_lowercase().collect::<String>(),
+            _ => c.to_uppercase().collect::<String>(),
+        })
+        .collect();
+    if toggled != text {
+        let removed = buf.remove_chars(start.col, start.row, text.chars().count());
+        undo.add_do_history(Operation::DELETE, removed, start);
+        buf.insert_str(start.col, start.row, &toggled);
+        undo.add_do_history(Operation::ADD, toggled.chars().collect(), start);
+    }
+    start
+}
+/// join the lines `first` to `last` into one. the blanks that start a joined line
+/// become one space, or none after a blank, before `)` or for an empty line.
+/// returns where the last lines were joined.
+pub fn join(buf: &mut FileBuffer, undo: &mut Undo, first: usize, last: usize) -> Point {
+    let mut at = Point {
+        col: buf.get_col_length(first),
+        row: first,
+    };
+    for _ in first..last.min(buf.get_row_length().saturating_sub(1)) {
+        let line = buf.get_line(first).into_owned();
+        let next = buf.get_line(first + 1).into_owned();
+        let rest = next.trim_start_matches([' ', '\t']);
+        let blanks = next.chars().count() - rest.chars().count();
+        at.col = line.chars().count();
+        let removed = buf.remove_chars(at.col, first, 1 + blanks);
+        undo.add_do_history(Operation::DELETE, removed, at);
+        let space = !line.is_empty()
+            && !line.ends_with([' ', '\t'])
+            && !rest.is_empty()
+            && !rest.starts_with(')');
+        if space {
+            buf.insert_str(at.col, first, " ");
+            undo.add_do_history(Operation::ADD, vec![' '], at);
+        }
+    }
+    at
+}
+/// replace `count` chars from `at` with `c`. a line break replaces them all with one.
+/// returns where the cursor goes, None when the line has fewer chars left.
+pub fn replace_chars(
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    at: Point,
+    count: usize,
+    c: char,
+) -> Option<Point> {
+    let line = buf.get_line(at.row);
+    let mut end = at.col;
+    for _ in 0..count {
+        let next = next_grapheme(&line, end);
+        if next == end {
+            return None;
+        }
+        end = next;
+    }
+    let removed = buf.remove_chars(at.col, at.row, end - at.col);
+    undo.add_do_history(Operation::DELETE, removed, at);
+    if c == '\r' || c == '\n' {
+        buf.insert_str(at.col, at.row, "\n");
+        undo.add_do_history(Operation::ADD, vec!['\n'], at);
+        return Some(Point {
+            col: 0,
+            row: at.row + 1,
+        });
+    }
+    let text = c.to_string().repeat(count);
+    buf.insert_str(at.col, at.row, &text);
+    undo.add_do_history(Operation::ADD, text.chars().collect(), at);
+    Some(Point {
+        col: at.col + count - 1,
+        row: at.row,
+    })
+}
+/// put the text of `reg` `count` times after (or before) the cursor `at`, as one change.
+/// returns where the cursor goes: the last char put, the first line put or the
+/// corner of the block.
+pub fn put(
+    buf: &mut FileBuffer,
+    undo: &mut Undo,
+    reg: &Register,
+    at: Point,
+    after: bool,
+    count: usize,
+) -> Point {
+    undo.start_change();
+    let mut add = |buf: &mut FileBuffer, col: usize, row: usize, text: &str| {
+        buf.insert_str(col, row, text);
+        undo.add_do_history(Operation::ADD, text.chars().collect(), Point { col, row });
+    };
+    match reg.kind {
+        RegisterKind::Charwise => {
+            let col = match after {
+                true if buf.get_col_length(at.row) > 0 => {
+                    next_grapheme(&buf.get_line(at.row), at.col)
+                }
+                _ => at.col,
+            };
+            let text = reg.text.repeat(count);
+            add(buf, col, at.row, &text);
+            // the cursor goes on the last char put.
+            let mut end = Point { col, row: at.row };
+            for c in text.chars().take(text.chars().count().saturating_sub(1)) {
+                if c == '\n' {
+                    end = Point {
+                        col: 0,
+                        row: end.row + 1,
+                    };
+                } else {
+                    end.col += 1;
+                }
+            }
+            end
+        }
+        RegisterKind::Linewise => {
+            let text = reg.text.repeat(count);
+            let row = if after { at.row + 1 } else { at.row };
+            if row < buf.get_row_length() {
+                add(buf, 0, row, &text);
+            } else {
+                // after the last line, which has no line break of its own.
+                let last = row - 1;
+                let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                let col = buf.get_col_length(last);
+                add(buf, col, last, &text);
+            }
+            first_non_blank(buf, row)
+        }
+        RegisterKind::Blockwise => {
+            let line = buf.get_line(at.row);
+            let col = match after {
+                true if !line.is_empty() => next_grapheme(&line, at.col),
+                _ => at.col,
+            };
+            let tabstop = buf.get_options().tabstop;
+            let width = char_to_display_col(&line, col, tabstop);
+            for (i, part) in reg.text.split('\n').enumerate() {
+                let row = at.row + i;
+                if row >= buf.get_row_length() {
+                    let last = row - 1;
+                    let col = buf.get_col_length(last);
+                    add(buf, col, last, "\n");
+                }
+                // lines shorter than the column are filled with blanks.
+                let line = buf.get_line(row);
+                let len = line.chars().count();
+                let end = char_to_display_col(&line, len, tabstop);
+                let (col, fill) = if end < width {
+                    (len, " ".repeat(width - end))
+                } else {
+                    let col = (0..=len)
+                        .find(|c| char_to_display_col(&line, *c, tabstop) >= width)
+                        .unwrap_or(len);
+                    (col, String::new())
+                };
+                add(buf, col, row, &format!("{}{}", fill, part.repeat(count)));
+            }
+            Point { col, row: at.row }
+        }
+    }
+}
+#[cfg(test)]
+mod operator_test {
+    use super::*;
+
+    fn p(col: usize, row: usize) -> Point {
+        Point { col, row }
+    }
+    #[test]
+    fn test_delete() {
+        let mut buf = FileBuffer::from_text("delete.txt", "one two\nthree\nfour\n");
+        let mut undo = Undo::new();
+        undo.start_change();
+        let range = Range::new(&buf, p(4, 0), p(6, 0), MotionKind::Inclusive);
+        assert_eq!(yank(&buf, range).text, "two");
+        assert_eq!(delete(&mut buf, &mut undo, range, false), p(4, 0));
+        assert_eq!(buf.get_contents(), "one \nthree\nfour\n");
+        // `dw` on the last word of a line stops at its end.
+        let range = Range::new(&buf, p(1, 1), p(0, 2), MotionKind::Exclusive);
+        assert_eq!(
+            range,
+            Range::new(&buf, p(1, 1), p(5, 1), MotionKind::Exclusive)
+        );
+        // from the start of the text it takes the lines.
+        let range = Range::new(&buf, p(0, 1), p(0, 2), MotionKind::Exclusive);
+        assert!(range.linewise);
+        undo.start_change();
+        delete(&mut buf, &mut undo, Range::lines(1, 2), false);
+        assert_eq!(buf.get_contents(), "one \n");
+        undo.start_change();
+        delete(&mut buf, &mut undo, Range::lines(0, 1), false);
+        assert_eq!(buf.get_contents(), "");
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        assert_eq!(buf.get_contents(), "one \nthree\nfour\n");
+        assert_eq!(undo.undo(&mut buf), p(4, 0));
+        assert_eq!(buf.get_contents(), "one two\nthree\nfour\n");
+    }
+    #[test]
+    fn test_lines() {
+        let mut buf = FileBuffer::from_text("lines.txt", "a\n\tb\nc");
+        let mut undo = Undo::new();
+        assert_eq!(yank(&buf, Range::lines(1, 2)).text, "\tb\nc\n");
+        delete(&mut buf, &mut undo, Range::lines(2, 2), false);
+        assert_eq!(buf.get_contents(), "a\n\tb");
+        delete(&mut buf, &mut undo, Range::lines(0, 0), true);
+        assert_eq!(buf.get_contents(), "\n\tb");
+        buf.get_options_mut().shiftwidth = 4;
+        assert_eq!(
+            shift(&mut buf, &mut undo, Range::lines(0, 1), true),
+            p(0, 0)
+        );
+        assert_eq!(buf.get_contents(), "\n\t    b");
+        shift(&mut buf, &mut undo, Range::lines(1, 1), false);
+        shift(&mut buf, &mut undo, Range::lines(1, 1), false);
+        assert_eq!(buf.get_contents(), "\n    b");
+    }
+    #[test]
+    fn test_undo_shift() {
+        // two records a line, past the size of the history.
+        let text = "  a\n".repeat(600);
+        let mut buf = FileBuffer::from_text("undo_shift.txt", &text);
+        let mut undo = Undo::new();
+        undo.start_change();
+        undo.add_do_history(Operation::ADD, vec!['b'], p(0, 0));
+        buf.insert_str(0, 0, "b");
+        undo.start_change();
+        shift(&mut buf, &mut undo, Range::lines(0, 599), true);
+        assert_eq!(buf.get_line(599), "\t  a");
+        // the whole shift is undone at once, the change before it is dropped.
+        undo.undo(&mut buf);
+        assert_eq!(buf.get_contents(), format!("b{}", text));
+        assert!(!undo.can_undo());
+        assert_eq!(undo.redo(&mut buf), p(0, 0));
+        assert_eq!(buf.get_line(599), "\t  a");
+        assert!(undo.can_undo() && !undo.can_redo());
+    }
+    #[test]
+    fn test_reindent() {
+        let mut buf = FileBuffer::from_text("reindent.txt", "fn f() {\nif x {\n  y\n\t}\n  \n}\n");
+        let mut undo = Undo::new();
+        buf.get_options_mut().shiftwidth = 4;
+        buf.get_options_mut().expandtab = true;
+        assert_eq!(reindent(&mut buf, &mut undo, Range::lines(0, 5)), p(0, 0));
+        assert_eq!(
+            buf.get_contents(),
+            "fn f() {\n    if x {\n        y\n    }\n\n}\n"
+        );
+        let opt = buf.get_options();
+        assert_eq!(indent_below(opt, "  a {", "}", false), 2);
+        assert_eq!(indent_below(opt, "\ta {", "b", true), 12);
+    }
+    #[test]
+    fn test_put() {
+        let mut buf = FileBuffer::from_text("put.txt", "ab\ncd");
+        let mut undo = Undo::new();
+        let chars = Register::new(String::from("xy"), RegisterKind::Charwise);
+        assert_eq!(put(&mut buf, &mut undo, &chars, p(0, 0), true, 2), p(4, 0));
+        assert_eq!(buf.get_contents(), "axyxyb\ncd");
+        undo.undo(&mut buf);
+        let lines = Register::new(String::from("l\n"), RegisterKind::Linewise);
+        assert_eq!(put(&mut buf, &mut undo, &lines, p(1, 1), true, 1), p(0, 2));
+        assert_eq!(buf.get_contents(), "ab\ncd\nl");
+        put(&mut buf, &mut undo, &lines, p(0, 0), false, 1);
+        assert_eq!(buf.get_contents(), "l\nab\ncd\nl");
+        undo.undo(&mut buf);
+        undo.undo(&mut buf);
+        let block = Register::new(String::from("1\n2\n3"), RegisterKind::Blockwise);
+        assert_eq!(put(&mut buf, &mut undo, &block, p(1, 0), true, 1), p(2, 0));
+        assert_eq!(buf.get_contents(), "ab1\ncd2\n  3");
+        undo.undo(&mut buf);
+        assert_eq!(buf.get_contents(), "ab\ncd");
+    }
+    #[test]
+    fn test_join() {
+        let mut buf = FileBuffer::from_text("join.txt", "a\n  b\n\n)c\nd \ne\n");
+        let mut undo = Undo::new();
+        undo.start_change();
+        assert_eq!(join(&mut buf, &mut undo, 0, 3), p(3, 0));
+        assert_eq!(buf.get_contents(), "a b)c\nd \ne\n");
+        join(&mut buf, &mut undo, 1, 2);
+        assert_eq!(buf.get_contents(), "a b)c\nd e\n");
+        change_case(
+            &mut buf,
+            &mut undo,
+            Range::lines(0, 0),
+            Operator::ToggleCase,
+        );
+        assert_eq!(buf.get_contents(), "A B)C\nd e\n");
+        let range = Range::new(&buf, p(0, 0), p(0, 1), MotionKind::Linewise);
+        change_case(&mut buf, &mut undo, range, Operator::Lowercase);
+        assert_eq!(buf.get_contents(), "a b)c\nd e\n");
+        assert_eq!(
+            replace_chars(&mut buf, &mut undo, p(1, 1), 2, 'x'),
+            Some(p(2, 1))
+        );
+        assert_eq!(replace_chars(&mut buf, &mut undo, p(1, 1), 3, 'x'), None);
+        replace_chars(&mut buf, &mut undo, p(1, 0), 1, '\r');
+        assert_eq!(buf.get_contents(), "a\nb)c\ndxx\n");
+        undo.undo(&mut buf);
+        assert_eq!(buf.get_contents(), "a\n  b\n\n)c\nd \ne\n");
+    }
+}
@@ -0,0 +1,236 @@
+use crate::modules::encoding::{encoding_from_name, encoding_name};
+use crate::modules::fileformat::FileFormat;
+use encoding_rs::{Encoding, UTF_8};
+
+/// editor options. each buffer keeps its own copy.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub tabstop: usize,
+    /// columns of one step of `>` and `<`. 0 takes the tabstop
+    pub shiftwidth: usize,
+    /// indent with spaces instead of tabs
+    pub expandtab: bool,
+    /// columns a Tab and a Backspace over blanks take in insert mode. 0 inserts a tab
+    pub softtabstop: usize,
+    /// a new line takes the indent of the line above
+    pub autoindent: bool,
+    /// a new line is indented one step more after a `{`, and a `}` one step less
+    pub smartindent: bool,
+    pub fileencoding: &'static Encoding,
+    pub bomb: bool,
+    pub fileformat: FileFormat,
+    /// keep the previous version of a file as `file~` when writing it
+    pub backup: bool,
+    /// read the file again when it changed outside and the buffer has no edits
+    pub autoread: bool,
+    /// switch away from a buffer with unsaved edits, keeping it in the buffer list
+    pub hidden: bool,
+    /// refuse edits and writing the file without `!`
+    pub readonly: bool,
+}
+
+impl Options {
+    pub fn new() -> Options {
+        Options {
+            tabstop: 8,
+            shiftwidth: 8,
+            expandtab: false,
+            softtabstop: 0,
+            autoindent: false,
+            smartindent: false,
+            fileencoding: UTF_8,
+            bomb: false,
+            fileformat: FileFormat::Unix,
+            backup: false,
+            autoread: false,
+            hidden: false,
+            readonly: false,
+        }
+    }
+    /// take the options that are not about the file itself, for a new buffer.
+    pub fn inherit(&mut self, from: &Options) {
+        self.tabstop = from.tabstop;
+        self.shiftwidth = from.shiftwidth;
+        self.expandtab = from.expandtab;
+        self.softtabstop = from.softtabstop;
+        self.autoindent = from.autoindent;
+        self.smartindent = from.smartindent;
+        self.backup = from.backup;
+        self.autoread = from.autoread;
+        self.hidden = from.hidden;
+    }
+    /// whether the option named in a `:set` argument applies to every buffer.
+    pub fn is_global(arg: &str) -> bool {
+        let name = arg.split(['=', '?']).next().unwrap_or("");
+        let name = name.strip_prefix("no").unwrap_or(name);
+        matches!(name, "backup" | "bk" | "autoread" | "ar" | "hidden" | "hid")
+    }
+    /// apply one argument of `:set`: `name`, `noname`, `name=value` or `name?`.
+    /// returns the message to show.
+    pub fn set(&mut self, arg: &str) -> Result<String, String> {
+        if let Some(name) = arg.strip_suffix('?') {
+            return self.get(name);
+        }
+        if let Some((name, value)) = arg.split_once('=') {
+            match name {
+                "tabstop" | "ts" => match value.parse::<usize>() {
+                    Ok(n) if n > 0 => self.tabstop = n,
+                    _ => return Err(format!("[E474] invalid argument: {}", arg)),
+                },
+                "shiftwidth" | "sw" => match value.parse::<usize>() {
+                    Ok(n) => self.shiftwidth = n,
+                    _ => return Err(format!("[E474] invalid argument: {}", arg)),
+                },
+                "softtabstop" | "sts" => match value.parse::<usize>() {
+                    Ok(n) => self.softtabstop = n,
+                    _ => return Err(format!("[E474] invalid argument: {}", arg)),
+                },
+                "fileencoding" | "fenc" => match encoding_from_name(value) {
+                    Some(e) => self.fileencoding = e,
+                    None => return Err(format!("[E474] invalid argument: {}", arg)),
+                },
+                "fileformat" | "ff" => match FileFormat::from_name(value) {
+                    Some(f) => self.fileformat = f,
+                    None => return Err(format!("[E474] invalid argument: {}", arg)),
+                },
+                _ => return Err(format!("[E518] unknown option: {}", name)),
+            }
+            return Ok(String::new());
+        }
+        let (name, value) = match arg.strip_prefix("no") {
+            Some(name) => (name, false),
+            None => (arg, true),
+        };
+        match name {
+            "bomb" => self.bomb = value,
+            "expandtab" | "et" => self.expandtab = value,
+            "autoindent" | "ai" => self.autoindent = value,
+            "smartindent" | "si" => self.smartindent = value,
+            "backup" | "bk" => self.backup = value,
+            "autoread" | "ar" => self.autoread = value,
+            "hidden" | "hid" => self.hidden = value,
+            "readonly" | "ro" => self.readonly = value,
+            _ if value => return self.get(name),
+            _ => return Err(format!("[E518] unknown option: {}", arg)),
+        }
+        Ok(String::new())
+    }
+    /// columns of one indent step.
+    pub fn get_shiftwidth(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+    /// whitespace that indents to display column `width`, with tabs unless `expandtab`.
+    pub fn indent_string(&self, width: usize) -> String {
+        self.blanks(0, width)
+    }
+    /// whitespace from display column `from` to `to`, with tabs unless `expandtab`.
+    pub fn blanks(&self, from: usize, to: usize) -> String {
+        if self.expandtab {
+            return " ".repeat(to.saturating_sub(from));
+        }
+        let mut col = from;
+        let mut blanks = String::new();
+        while (col / self.tabstop + 1) * self.tabstop <= to {
+            blanks.push('\t');
+            col = (col / self.tabstop + 1) * self.tabstop;
+        }
+        blanks + &" ".repeat(to.saturating_sub(col))
+    }
+    /// the value of an option as shown by `:set name?`.
+    pub fn get(&self, name: &str) -> Result<String, String> {
+        match name {
+            "tabstop" | "ts" => Ok(format!("tabstop={}", self.tabstop)),
+            "shiftwidth" | "sw" => Ok(format!("shiftwidth={}", self.shiftwidth)),
+            "expandtab" | "et" => Ok(String::from(if self.expandtab {
+                "expandtab"
+            } else {
+                "noexpandtab"
+            })),
+            "softtabstop" | "sts" => Ok(format!("softtabstop={}", self.softtabstop)),
+            "autoindent" | "ai" => Ok(String::from(if self.autoindent {
+                "autoindent"
+            } else {
+                "noautoindent"
+            })),
+            "smartindent" | "si" => Ok(String::from(if self.smartindent {
+                "smartindent"
+            } else {
+                "nosmartindent"
+            })),
+            "fileencoding" | "fenc" => {
+                Ok(format!("fileencoding={}", encoding_name(self.fileencoding)))
+            }
+            "bomb" => Ok(String::from(if self.bomb { "bomb" } else { "nobomb" })),
+            "fileformat" | "ff" => Ok(format!("fileformat={}", self.fileformat.name())),
+            "backup" | "bk" => Ok(String::from(if self.backup {
+                "backup"
+            } else {
+                "nobackup"
+            })),
+            "autoread" | "ar" => Ok(String::from(if self.autoread {
+                "autoread"
+            } else {
+                "noautoread"
+            })),
+            "hidden" | "hid" => Ok(String::from(if self.hidden {
+                "hidden"
+            } else {
+                "nohidden"
+            })),
+            "readonly" | "ro" => Ok(String::from(if self.readonly {
+                "readonly"
+            } else {
+                "noreadonly"
+            })),
+            _ => Err(format!("[E518] unknown option: {}", name)),
+        }
+    }
+}
+#[cfg(test)]
+mod option_test {
+    use super::Options;
+
+    #[test]
+    fn test_set() {
+        let mut opt = Options::new();
+        assert_eq!(opt.set("ts=4"), Ok(String::new()));
+        assert_eq!(opt.tabstop, 4);
+        assert_eq!(opt.set("tabstop?"), Ok(String::from("tabstop=4")));
+        assert_eq!(opt.set("ts"), Ok(String::from("tabstop=4")));
+        assert!(opt.set("ts=0").is_err());
+        assert_eq!(opt.set("fenc=sjis"), Ok(String::new()));
+        assert_eq!(opt.set("fenc?"), Ok(String::from("fileencoding=shift_jis")));
+        assert_eq!(opt.set("bomb"), Ok(String::new()));
+        assert!(opt.bomb);
+        assert_eq!(opt.set("nobomb"), Ok(String::new()));
+        assert!(!opt.bomb);
+        assert_eq!(opt.set("ff=dos"), Ok(String::new()));
+        assert_eq!(opt.set("ff?"), Ok(String::from("fileformat=dos")));
+        assert!(opt.set("ff=crlf").is_err());
+        assert_eq!(opt.set("ar"), Ok(String::new()));
+        assert_eq!(opt.set("autoread?"), Ok(String::from("autoread")));
+        assert!(Options::is_global("nohidden"));
+        assert!(Options::is_global("ar?"));
+        assert!(!Options::is_global("ts=4"));
+        assert_eq!(opt.set("ro"), Ok(String::new()));
+        assert!(opt.readonly);
+        assert_eq!(opt.set("noro"), Ok(String::new()));
+        assert_eq!(opt.set("readonly?"), Ok(String::from("noreadonly")));
+        assert_eq!(opt.set("sw=0"), Ok(String::new()));
+        assert_eq!(opt.get_shiftwidth(), 4);
+        assert_eq!(opt.indent_string(10), "\t\t  ");
+        assert_eq!(opt.blanks(2, 9), "\t\t ");
+        assert_eq!(opt.blanks(1, 3), "  ");
+        assert_eq!(opt.set("sts=2"), Ok(String::new()));
+        assert_eq!(opt.set("sts?"), Ok(String::from("softtabstop=2")));
+        assert_eq!(opt.set("ai"), Ok(String::new()));
+        assert_eq!(opt.set("si?"), Ok(String::from("nosmartindent")));
+        assert_eq!(opt.set("et"), Ok(String::new()));
+        assert_eq!(opt.indent_string(3), "   ");
+        assert!(opt.set("nosuchoption").is_err());
+    }
+}
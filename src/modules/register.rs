@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+
+// registers keep text for `p`. yanks and deletes fill them, `"x` before a command
+// names the register to use.
+
+/// how the text of a register is put.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegisterKind {
+    /// put inside a line
+    Charwise,
+    /// put as whole lines. the text ends with a line break
+    Linewise,
+    /// put as a block, each line of the text at the same column of the next line
+    Blockwise,
+}
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    pub kind: RegisterKind,
+}
+pub struct Registers {
+    /// the register `""` stands for, the one written last
+    unnamed: char,
+    /// `a-z`, `0-9` and `-`
+    stored: HashMap<char, Register>,
+    /// text typed in the last insert, `".`
+    inserted: String,
+    /// the last command line, `":`
+    command: String,
+    /// the last search pattern, `"/`
+    search: String,
+}
+
+impl Register {
+    pub fn new(text: String, kind: RegisterKind) -> Register {
+        Register { text, kind }
+    }
+    fn append(&mut self, other: Register) {
+        if self.kind == RegisterKind::Charwise && other.kind == RegisterKind::Charwise {
+            self.text.push_str(&other.text);
+            return;
+        }
+        // with whole lines on either side the result is whole lines.
+        if !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.text.push_str(&other.text);
+        if !self.text.ends_with('\n') {
+            self.text.push('\n');
+        }
+        self.kind = RegisterKind::Linewise;
+    }
+}
+/// a name that can follow `"`.
+pub fn is_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() || "\"-_.:/%".contains(name)
+}
+/// a register that a yank or delete may write.
+pub fn is_writable(name: char) -> bool {
+    name.is_ascii_alphanumeric() || "\"-_".contains(name)
+}
+
+impl Registers {
+    pub fn new() -> Registers {
+        Registers {
+            unnamed: '0',
+            stored: HashMap::new(),
+            inserted: String::new(),
+            command: String::new(),
+            search: String::new(),
+        }
+    }
+    /// store yanked text in register `name`, or in `"0` without a name.
+    pub fn yank(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            None | Some('"') => self.write('0', reg),
+            Some(name) => self.write(name, reg),
+        }
+    }
+    /// store deleted text in register `name`. without a name, a delete within a line
+    /// goes to `"-` and others go to `"1`, shifting the older ones up to `"9`.
+    pub fn delete(&mut self, name: Option<char>, reg: Register) {
+        match name {
+            None | Some('"') => {
+                if reg.kind == RegisterKind::Charwise && !reg.text.contains('\n') {
+                    self.write('-', reg);
+                    return;
+                }
+                for n in (1..9).rev() {
+                    let from = char::from_digit(n, 10).unwrap();
+                    if let Some(r) = self.stored.remove(&from) {
+                        self.stored.insert(char::from_digit(n + 1, 10).unwrap(), r);
+                    }
+                }
+                self.write('1', reg);
+            }
+            Some(name) => self.write(name, reg),
+        }
+    }
+    /// an uppercase name appends to the register. `"_` drops the text.
+    fn write(&mut self, name: char, reg: Register) {
+        if name == '_' {
+            return;
+        }
+        let lower = name.to_ascii_lowercase();
+        match self.stored.get_mut(&lower) {
+            Some(old) if name.is_ascii_uppercase() => old.append(reg),
+            _ => {
+                self.stored.insert(lower, reg);
+            }
+        }
+        self.unnamed = lower;
+    }
+    /// store the keys of a macro recorded with `q{name}`. `""` is left alone.
+    pub fn record(&mut self, name: char, text: String) {
+        let unnamed = self.unnamed;
+        self.write(name, Register::new(text, RegisterKind::Charwise));
+        self.unnamed = unnamed;
+    }
+    /// the text of register `name`, `""` without a name. `file` is the name for `"%`.
+    pub fn get(&self, name: Option<char>, file: &str) -> Option<Register> {
+        let charwise = |text: &str| {
+            if text.is_empty() {
+                None
+            } else {
+                Some(Register::new(String::from(text), RegisterKind::Charwise))
+            }
+        };
+        match name.unwrap_or('"') {
+            '"' => self.stored.get(&self.unnamed).cloned(),
+            '.' => charwise(&self.inserted),
+            ':' => charwise(&self.command),
+            '/' => charwise(&self.search),
+            '%' => charwise(file),
+            '_' => None,
+            name => self.stored.get(&name.to_ascii_lowercase()).cloned(),
+        }
+    }
+    /// an insert starts, `".` gathers the text typed from now on.
+    pub fn start_insert(&mut self) {
+        self.inserted.clear();
+    }
+    pub fn insert_typed(&mut self, c: char) {
+        self.inserted.push(c);
+    }
+    /// a char typed in the insert was erased again.
+    pub fn insert_erased(&mut self) {
+        self.inserted.pop();
+    }
+    pub fn set_command(&mut self, line: &str) {
+        self.command = String::from(line);
+    }
+    pub fn set_search(&mut self, ptn: &str) {
+        self.search = String::from(ptn);
+    }
+    /// the registers that hold text, or only those in `names`, a line for each
+    /// under a header, for `:registers`.
+    pub fn list(&self, names: &str, file: &str) -> Vec<String> {
+        let items = "\"0123456789abcdefghijklmnopqrstuvwxyz-.:%/"
+            .chars()
+            .filter(|c| names.is_empty() || names.contains(*c))
+            .filter_map(|c| {
+                let reg = self.get(Some(c), file)?;
+                let kind = match reg.kind {
+                    RegisterKind::Charwise => 'c',
+                    RegisterKind::Linewise => 'l',
+                    RegisterKind::Blockwise => 'b',
+                };
+                let text: String = reg
+                    .text
+                    .chars()
+                    .map(|c| match c {
+                        '\n' => String::from("^J"),
+                        '\t' => String::from("^I"),
+                        c => String::from(c),
+                    })
+                    .collect();
+                Some(format!("  {}  \"{}   {}", kind, c, text))
+            });
+        std::iter::once(String::from("Type Name Content"))
+            .chain(items)
+            .collect()
+    }
+}
+#[cfg(test)]
+mod register_test {
+    use super::*;
+
+    fn chars(text: &str) -> Register {
+        Register::new(String::from(text), RegisterKind::Charwise)
+    }
+    fn lines(text: &str) -> Register {
+        Register::new(String::from(text), RegisterKind::Linewise)
+    }
+    #[test]
+    fn test_registers() {
+        let mut r = Registers::new();
+        assert_eq!(r.get(None, ""), None);
+        r.yank(None, chars("foo"));
+        assert_eq!(r.get(None, ""), Some(chars("foo")));
+        r.delete(None, chars("x"));
+        assert_eq!(r.get(Some('-'), ""), Some(chars("x")));
+        assert_eq!(r.get(None, ""), Some(chars("x")));
+        assert_eq!(r.get(Some('0'), ""), Some(chars("foo")));
+        r.delete(None, lines("one\n"));
+        r.delete(None, lines("two\n"));
+        assert_eq!(r.get(Some('1'), ""), Some(lines("two\n")));
+        assert_eq!(r.get(Some('2'), ""), Some(lines("one\n")));
+        r.yank(Some('a'), chars("bar"));
+        r.yank(Some('A'), lines("baz\n"));
+        assert_eq!(r.get(Some('a'), ""), Some(lines("bar\nbaz\n")));
+        assert_eq!(r.get(None, ""), Some(lines("bar\nbaz\n")));
+        r.delete(Some('_'), chars("gone"));
+        assert_eq!(r.get(None, ""), Some(lines("bar\nbaz\n")));
+        assert_eq!(r.get(Some('%'), "a.txt"), Some(chars("a.txt")));
+        assert_eq!(
+            r.list("-a", ""),
+            ["Type Name Content", "  l  \"a   bar^Jbaz^J", "  c  \"-   x"]
+        );
+    }
+}
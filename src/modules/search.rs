@@ -1,57 +1,143 @@
 use crate::modules::coordinate::Point;
 use crate::modules::file::FileBuffer;
+use crate::modules::hex::{parse_pattern, point_of};
 use crate::modules::mode::MODE;
+use crate::modules::register::Registers;
+use crate::modules::show::Display;
+use crate::modules::unicode::byte_to_char;
 use crossterm::event::KeyCode;
 pub struct Search {
     ptn: String,
+    /// the match to go to, counted from the cursor (`2/foo`)
+    count: usize,
 }
 impl Search {
     pub fn new() -> Search {
-        Search { ptn: String::new() }
+        Search {
+            ptn: String::new(),
+            count: 1,
+        }
+    }
+    pub fn set_count(&mut self, count: usize) {
+        self.count = count;
     }
-    pub fn proc_search(&mut self, code: KeyCode, buf: &mut FileBuffer) -> MODE {
+    pub fn proc_search(
+        &mut self,
+        code: KeyCode,
+        display: &mut Display,
+        buf: &mut FileBuffer,
+        registers: &mut Registers,
+    ) -> MODE {
         match code {
             KeyCode::Char(c) => {
-                self.ptn = format!("{}{}", self.ptn, c);
+                self.ptn.push(c);
                 MODE::Search
             }
             KeyCode::Enter => {
-                buf.search_result_register(search_string(buf.get_contents(), &self.ptn));
+                buf.search_result_register(search_string(buf, &self.ptn));
+                if !self.ptn.is_empty() {
+                    registers.set_search(&self.ptn);
+                }
+                let from = display.get_cursor_coordinate_in_file();
+                let first = if buf.is_search_backward() {
+                    buf.get_searchresult_before(from)
+                } else {
+                    buf.get_searchresult_after(from)
+                };
+                match first {
+                    Some(mut point) => {
+                        for _ in 1..self.count {
+                            point = buf.repeat_search(false).unwrap();
+                        }
+                        display.move_to_point(buf, point);
+                    }
+                    None if !self.ptn.is_empty() => {
+                        display.update_info_line(&format!("[E486] pattern not found: {}", self.ptn))
+                    }
+                    None => (),
+                }
                 self.ptn.clear();
+                self.count = 1;
+                MODE::Normal
+            }
+            _ => {
+                self.ptn.clear();
+                self.count = 1;
                 MODE::Normal
             }
-            _ => MODE::Normal,
         }
     }
 }
-pub fn search_string(buf: String, ptn: &str) -> Vec<Point> {
+/// matches kept when searching a file in large-file mode.
+const LARGE_SEARCH_LIMIT: usize = 1000;
+
+/// find every occurrence of `ptn`. columns are char indexes in the line.
+/// in large-file mode only the first matches in the start of the indexed part are found.
+/// in hex mode `ptn` is a byte sequence, see `hex::parse_pattern`.
+pub fn search_string(buf: &FileBuffer, ptn: &str) -> Vec<Point> {
     let mut ret: Vec<Point> = vec![];
-    let mut ptnlst = ptn.chars();
-    let matchsize = ptn.len();
-    let mut row: u16 = 0;
-    for line in buf.split('\n') {
-        let mut matchindex = 0;
-        let mut col: u16 = 0;
-        for charactor in line.chars() {
-            let p = match ptn.chars().nth(matchindex) {
-                Some(a) => a,
-                None => 0x00 as char,
-            };
-            if p != charactor {
-                if matchindex > 0 {
-                    col += matchindex as u16;
-                }
-                matchindex = 0;
-                col += 1;
-            } else {
-                matchindex += 1;
-                if matchindex == matchsize {
-                    ret.push(Point { col: col, row: row });
-                }
-            }
+    if ptn.is_empty() {
+        return ret;
+    }
+    if let Some(hex) = buf.get_hex() {
+        for offset in hex.find(&parse_pattern(ptn)) {
+            ret.push(point_of(offset, 0));
+        }
+        return ret;
+    }
+    if buf.is_large() {
+        for (row, byte) in buf.find_large(ptn, LARGE_SEARCH_LIMIT) {
+            ret.push(Point {
+                col: byte_to_char(&buf.get_line(row), byte),
+                row,
+            });
+        }
+        return ret;
+    }
+    for (row, line) in buf.get_lines(0).enumerate() {
+        for (byte, _) in line.match_indices(ptn) {
+            ret.push(Point {
+                col: byte_to_char(&line, byte),
+                row,
+            });
         }
-        row += 1;
     }
-
     ret
 }
+#[cfg(test)]
+mod search_test {
+    use super::search_string;
+    use crate::modules::coordinate::Point;
+    use crate::modules::file::FileBuffer;
+
+    #[test]
+    fn test_search_string() {
+        let buf = FileBuffer::from_text("search_string.txt", "foo bar foo\nあfoo\nbar");
+        let result: Vec<(usize, usize)> = search_string(&buf, "foo")
+            .iter()
+            .map(|p| (p.col, p.row))
+            .collect();
+        assert_eq!(result, vec![(0, 0), (8, 0), (1, 1)]);
+        assert!(search_string(&buf, "baz").is_empty());
+    }
+    #[test]
+    fn test_search_backward() {
+        let mut buf = FileBuffer::from_text("search_backward.txt", "foo bar foo\nあfoo\nbar");
+        let result = search_string(&buf, "foo");
+        buf.search_result_register(result);
+        buf.set_search_backward(true);
+        let at = |p: Option<Point>| p.map(|p| (p.col, p.row));
+        assert_eq!(
+            at(buf.get_searchresult_before(Point { col: 2, row: 1 })),
+            Some((1, 1))
+        );
+        assert_eq!(at(buf.repeat_search(false)), Some((8, 0)));
+        assert_eq!(at(buf.repeat_search(true)), Some((1, 1)));
+        // `?` before the first match wraps around to the last.
+        assert_eq!(
+            at(buf.get_searchresult_before(Point { col: 0, row: 0 })),
+            Some((1, 1))
+        );
+        assert_eq!(at(buf.repeat_search(true)), Some((0, 0)));
+    }
+}
@@ -1,9 +1,15 @@
-use crate::modules::coordinate::Point;
+use crate::modules::coordinate::{Point, ScreenPoint};
+use crate::modules::encoding::encoding_name;
 use crate::modules::file::FileBuffer;
+use crate::modules::unicode::{
+    char_to_display_col, display_col_to_char, next_grapheme, prev_grapheme, visible_part,
+};
+use crate::modules::visual::Selection;
 use crossterm::cursor::MoveTo;
 use crossterm::cursor::{self, SetCursorStyle};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
 use crossterm::queue;
-use crossterm::style::Print;
+use crossterm::style::{Attribute, Print, SetAttribute, Stylize};
 use crossterm::terminal::{
     disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
 };
@@ -12,16 +18,49 @@ use crossterm::terminal::{ScrollDown, ScrollUp};
 use std::io::prelude::*;
 use std::io::BufWriter;
 use std::io::{stdout, Stdout};
-use std::thread::ScopedJoinHandle;
+use unicode_width::UnicodeWidthStr;
 
 pub struct Display {
     buffer: BufWriter<Stdout>,
-    point: Point,
+    /// cursor on the screen
+    point: ScreenPoint,
+    /// top row and left display column of the window in the file
     point_in_file: Point,
-    wsize: Point,
-    pos_tmp: Point,
+    /// cursor in the file. col is a char index in the line
+    cursor: Point,
+    wsize: ScreenPoint,
+    /// display column kept while moving up and down
+    want_col: usize,
+    /// message on the last row
+    message: String,
+    /// keys of a command typed so far, shown on the status line
+    pending: String,
+    /// a command failed since the last key, which stops a macro
+    error: bool,
+    /// the text selected in visual mode. its cursor end follows the cursor
+    selection: Option<Selection>,
     out: Stdout,
 }
+/// cursor and window of a buffer, kept while another buffer is shown.
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    cursor: Point,
+    point_in_file: Point,
+    want_col: usize,
+}
+
+impl View {
+    pub fn new() -> View {
+        View {
+            cursor: Point { col: 0, row: 0 },
+            point_in_file: Point { col: 0, row: 0 },
+            want_col: 0,
+        }
+    }
+    pub fn get_cursor(&self) -> Point {
+        self.cursor
+    }
+}
 pub enum MoveDirection {
     Up,
     Down,
@@ -30,181 +69,382 @@ pub enum MoveDirection {
     Head,
     Tail,
 }
+/// where `zt`, `zz` and `zb` put the line of the cursor in the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Top,
+    Middle,
+    Bottom,
+}
 
 impl Display {
-    fn update_line(&mut self, content: String, row: u16) {
-        let tmp_cursor_pos = row - self.point_in_file.row;
-        queue!(self.out, MoveTo(0, tmp_cursor_pos));
-        let tmp_content = String::from(content);
-        let printstring = tmp_content
-            .split('\n')
-            .into_iter()
-            .nth(row as usize)
-            .unwrap();
-        let printstring = format!("{}\n", printstring);
-
-        queue!(self.out, Print(printstring));
-        queue!(self.out, MoveTo(self.point.col, self.point.row));
+    fn update_line(&mut self, buf: &FileBuffer, row: usize) {
+        let tmp_cursor_pos = (row - self.point_in_file.row) as u16;
+        queue!(self.out, MoveTo(0, tmp_cursor_pos)).unwrap();
+        let printstring = self.visible_line(buf, &buf.get_line(row), row);
+        queue!(self.out, Print(printstring), Clear(ClearType::UntilNewLine)).unwrap();
+        queue!(self.out, MoveTo(self.point.col, self.point.row)).unwrap();
     }
-    pub fn update_all(&mut self, content: String) -> Result<(), String> {
-        let mut row_index = 0;
-        let tmp_content = String::from(content);
+    pub fn update_all(&mut self, buf: &FileBuffer) -> Result<(), String> {
+        let mut row_index = self.point_in_file.row;
         queue!(self.out, Clear(ClearType::All))
             .unwrap_or_else(|e| self.close_terminal(e.to_string()));
         queue!(self.out, MoveTo(0, 0))
             .unwrap_or_else(|_| self.close_terminal("[E101] failed to move cursor".to_string()));
-        for chara in tmp_content.split('\n') {
-            if row_index > self.wsize.row - 2 + self.point_in_file.row {
+        // only the visible window is walked, starting at the top row of the screen.
+        for line in buf.get_lines(self.point_in_file.row) {
+            if row_index >= self.text_height() + self.point_in_file.row {
                 break;
             }
-            if self.point_in_file.row > row_index {
-                row_index += 1;
-                continue;
-            } else {
-                row_index += 1;
-                self.buffer.write(chara.as_bytes()).unwrap();
-                self.buffer.write("\r\n".as_bytes()).unwrap();
-            }
+            let printstring = self.visible_line(buf, &line, row_index);
+            row_index += 1;
+            self.buffer.write_all(printstring.as_bytes()).unwrap();
+            self.buffer.write_all("\r\n".as_bytes()).unwrap();
         }
-        if row_index - self.point_in_file.row < self.wsize.row - 2 {
-            while row_index - self.point_in_file.row < self.wsize.row - 2 {
-                self.buffer.write("~\r\n".as_bytes()).unwrap();
-                row_index += 1;
-            }
+        while row_index - self.point_in_file.row < self.text_height() {
+            self.buffer.write_all("~\r\n".as_bytes()).unwrap();
+            row_index += 1;
         }
-        self.out.flush().unwrap();
         self.buffer.flush().unwrap();
+        self.draw_status_line(buf);
+        self.draw_info_line();
+        self.out.flush().unwrap();
         self.move_cursor_to_point(self.point);
         Ok(())
     }
-    pub fn update_wsize(&mut self, size: Point) {
+    pub fn update_wsize(&mut self, size: ScreenPoint) {
         self.wsize = size;
     }
-    pub fn new(size: Point) -> Display {
+    pub fn new(size: ScreenPoint) -> Display {
         Display {
             buffer: BufWriter::new(stdout()),
-            point: Point { col: 0, row: 0 },
+            point: ScreenPoint { col: 0, row: 0 },
             point_in_file: Point { col: 0, row: 0 },
+            cursor: Point { col: 0, row: 0 },
             wsize: size,
-            pos_tmp: Point { col: 0, row: 0 },
+            want_col: 0,
+            message: String::new(),
+            pending: String::new(),
+            error: false,
+            selection: None,
             out: stdout(),
         }
     }
-    pub fn move_to_point(&mut self, buf: &mut FileBuffer, point: Point) {
-        if point.row <= self.wsize.row / 2 {
-            self.point_in_file.row = 0;
+    /// the part of `line`, row `row` of the file, drawn in the window. the selection
+    /// is drawn in reverse video.
+    fn visible_line(&self, buf: &FileBuffer, line: &str, row: usize) -> String {
+        let tabstop = buf.get_options().tabstop;
+        let left = self.point_in_file.col;
+        let right = left + self.wsize.col as usize;
+        let selection = self.selection.and_then(|s| {
+            let s = Selection {
+                cursor: self.cursor,
+                ..s
+            };
+            s.columns(buf, row)
+        });
+        let (from, to) = match selection {
+            Some((from, to)) => (from.clamp(left, right), to.clamp(left, right)),
+            None => return visible_part(line, tabstop, left, right - left),
+        };
+        let to = to.max(from);
+        let mut selected = visible_part(line, tabstop, from, to - from);
+        // the selection goes on past the end of the line.
+        selected.push_str(&" ".repeat((to - from).saturating_sub(selected.width())));
+        format!(
+            "{}{}{}",
+            visible_part(line, tabstop, left, from - left),
+            selected.reverse(),
+            visible_part(line, tabstop, to, right - to)
+        )
+    }
+    /// select text in visual mode, or drop the selection with None.
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection;
+    }
+    /// rows of the window used for the text. the status line and the info line follow.
+    fn text_height(&self) -> usize {
+        (self.wsize.row as usize).saturating_sub(2).max(1)
+    }
+    /// display column of the cursor in the file.
+    fn cursor_display_col(&self, buf: &FileBuffer) -> usize {
+        char_to_display_col(
+            &buf.get_line(self.cursor.row),
+            self.cursor.col,
+            buf.get_options().tabstop,
+        )
+    }
+    /// scroll the window so that the cursor is visible and place the cursor on the screen.
+    fn scroll_to_cursor(&mut self, buf: &FileBuffer) {
+        let height = self.text_height();
+        let top = self.point_in_file.row;
+        let mut redraw = false;
+        if self.cursor.row < top {
+            if top - self.cursor.row == 1 {
+                queue!(self.out, ScrollDown(1)).unwrap();
+                self.point_in_file.row -= 1;
+                self.update_line(buf, self.cursor.row);
+                self.redraw_after_scroll(buf);
+            } else {
+                self.point_in_file.row = self.cursor.row;
+                redraw = true;
+            }
+        } else if self.cursor.row >= top + height {
+            if self.cursor.row - (top + height) == 0 {
+                queue!(self.out, ScrollUp(1)).unwrap();
+                self.point_in_file.row += 1;
+                self.redraw_after_scroll(buf);
+            } else {
+                self.point_in_file.row = self.cursor.row + 1 - height;
+                redraw = true;
+            }
+        }
+        let col = self.cursor_display_col(buf);
+        let left = self.point_in_file.col;
+        let width = (self.wsize.col as usize).max(1);
+        if col < left {
+            self.point_in_file.col = col;
+            redraw = true;
+        } else if col >= left + width {
+            self.point_in_file.col = col + 1 - width;
+            redraw = true;
+        }
+        self.point = ScreenPoint {
+            col: (col - self.point_in_file.col) as u16,
+            row: (self.cursor.row - self.point_in_file.row) as u16,
+        };
+        if redraw {
+            self.update_all(buf).unwrap();
+        }
+    }
+    /// the whole terminal scrolled by a line. draw the last text row, the status line
+    /// and the info line again, which were moved with the text.
+    fn redraw_after_scroll(&mut self, buf: &FileBuffer) {
+        let bottom = self.point_in_file.row + self.text_height() - 1;
+        if bottom < buf.get_row_length() {
+            self.update_line(buf, bottom);
         } else {
-            self.point_in_file.row = point.row - self.wsize.row / 2;
+            let row = self.text_height() as u16 - 1;
+            queue!(
+                self.out,
+                MoveTo(0, row),
+                Print("~"),
+                Clear(ClearType::UntilNewLine)
+            )
+            .unwrap();
         }
-        self.point.row = if point.row <= self.wsize.row / 2 {
-            point.row % self.wsize.row
+        self.draw_status_line(buf);
+        self.draw_info_line();
+    }
+    pub fn move_to_point(&mut self, buf: &FileBuffer, point: Point) {
+        let row = point.row.min(buf.get_row_length().saturating_sub(1));
+        self.cursor = Point {
+            col: point.col.min(buf.get_col_length(row)),
+            row,
+        };
+        self.want_col = self.cursor_display_col(buf);
+        self.point_in_file.row = row.saturating_sub(self.wsize.row as usize / 2);
+        self.scroll_to_cursor(buf);
+        self.update_all(buf).unwrap();
+        queue!(self.out, MoveTo(self.point.col, self.point.row)).unwrap();
+        self.out.flush().unwrap();
+    }
+    /// put the cursor at `point`, scrolling only as far as needed.
+    pub fn jump_to(&mut self, buf: &FileBuffer, point: Point) {
+        let row = point.row.min(buf.get_row_length().saturating_sub(1));
+        self.cursor = Point {
+            col: point.col.min(buf.get_col_length(row)),
+            row,
+        };
+        self.want_col = self.cursor_display_col(buf);
+        self.scroll_to_cursor(buf);
+        self.move_cursor_to_point(self.point);
+    }
+    /// after `$` up and down go to the ends of the lines.
+    pub fn want_line_end(&mut self) {
+        self.want_col = usize::MAX;
+    }
+    /// the char of `row` at the column kept while moving up and down. it stays on the
+    /// last char, as the cursor does in normal mode.
+    fn kept_col(&self, buf: &FileBuffer, row: usize) -> usize {
+        let line = buf.get_line(row);
+        let col = display_col_to_char(&line, self.want_col, buf.get_options().tabstop);
+        if col < line.chars().count() {
+            col
         } else {
-            self.wsize.row / 2
+            prev_grapheme(&line, col)
+        }
+    }
+    pub fn get_view(&self) -> View {
+        View {
+            cursor: self.cursor,
+            point_in_file: self.point_in_file,
+            want_col: self.want_col,
+        }
+    }
+    /// show `buf` with the cursor and window it had when it was last shown.
+    pub fn set_view(&mut self, buf: &FileBuffer, view: View) {
+        let row = view.cursor.row.min(buf.get_row_length().saturating_sub(1));
+        self.cursor = Point {
+            col: view.cursor.col.min(buf.get_col_length(row)),
+            row,
+        };
+        self.point_in_file = Point {
+            col: view.point_in_file.col,
+            row: view.point_in_file.row.min(row),
         };
-        self.point.col = point.col;
-        self.update_all(buf.get_contents()).unwrap();
-        queue!(self.out, MoveTo(self.point.col, self.point.row));
-        self.out.flush();
+        self.want_col = view.want_col;
+        self.scroll_to_cursor(buf);
+        self.update_all(buf).unwrap();
     }
-    pub fn move_cursor_to_point(&mut self, point: Point) {
+    pub fn move_cursor_to_point(&mut self, point: ScreenPoint) {
         queue!(self.out, MoveTo(point.col, point.row)).unwrap();
-        self.out.flush();
+        self.out.flush().unwrap();
     }
     pub fn move_cursor_nextpos(&mut self, direction: MoveDirection, buf: &FileBuffer) {
+        let tabstop = buf.get_options().tabstop;
         match direction {
             MoveDirection::Down => {
-                if buf.get_row_length() <= self.point.row + self.point_in_file.row + 1 {
-                } else if self.wsize.row > self.point.row + 2 {
-                    self.point.row = self.point.row + 1;
-                    if self.point.col > buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = buf.get_col_length(self.point.row + self.point_in_file.row)
-                    } else if self.pos_tmp.col
-                        < buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = self.pos_tmp.col;
-                    } else {
-                        self.point.col =
-                            buf.get_col_length(self.point.row + self.point_in_file.row);
-                    }
-                } else {
-                    queue!(self.out, ScrollUp(1)).unwrap();
-                    self.point_in_file.row += 1;
-                    self.update_line(buf.get_contents(), self.get_cursor_coordinate_in_file().row);
-                    if self.pos_tmp.col
-                        < buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = self.pos_tmp.col;
-                    } else {
-                        self.point.col =
-                            buf.get_col_length(self.point.row + self.point_in_file.row);
-                    }
+                if buf.get_row_length() > self.cursor.row + 1 {
+                    self.cursor.row += 1;
+                    let line = buf.get_line(self.cursor.row);
+                    self.cursor.col = display_col_to_char(&line, self.want_col, tabstop);
                 }
             }
             MoveDirection::Up => {
-                if self.point.row > 0 {
-                    self.point.row = self.point.row - 1;
-                    if self.point.col > buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = buf.get_col_length(self.point.row + self.point_in_file.row)
-                    } else if self.pos_tmp.col
-                        < buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = self.pos_tmp.col;
-                    } else {
-                        self.point.col =
-                            buf.get_col_length(self.point.row + self.point_in_file.row);
-                    }
-                } else {
-                    if self.point_in_file.row > 0 {
-                        queue!(self.out, ScrollDown(1)).unwrap();
-                        self.point_in_file.row -= 1;
-                        self.update_line(
-                            buf.get_contents(),
-                            self.get_cursor_coordinate_in_file().row,
-                        );
-                    }
-                    if self.pos_tmp.col
-                        < buf.get_col_length(self.point.row + self.point_in_file.row)
-                    {
-                        self.point.col = self.pos_tmp.col;
-                    } else {
-                        self.point.col =
-                            buf.get_col_length(self.point.row + self.point_in_file.row);
-                    }
+                if self.cursor.row > 0 {
+                    self.cursor.row -= 1;
+                    let line = buf.get_line(self.cursor.row);
+                    self.cursor.col = display_col_to_char(&line, self.want_col, tabstop);
                 }
             }
             MoveDirection::Left => {
-                if self.point.col > 0 {
-                    self.point.col = self.point.col - 1;
-                    self.pos_tmp.col = self.point.col;
-                }
+                let line = buf.get_line(self.cursor.row);
+                self.cursor.col = prev_grapheme(&line, self.cursor.col);
+                self.want_col = char_to_display_col(&line, self.cursor.col, tabstop);
             }
             MoveDirection::Right => {
-                if buf.get_col_length(self.point.row + self.point_in_file.row) <= self.point.col {
-                } else {
-                    self.point.col = self.point.col + 1;
-                    self.pos_tmp.col = self.point.col;
-                }
+                let line = buf.get_line(self.cursor.row);
+                self.cursor.col = next_grapheme(&line, self.cursor.col);
+                self.want_col = char_to_display_col(&line, self.cursor.col, tabstop);
+            }
+            MoveDirection::Head => {
+                self.cursor.col = 0;
+                self.want_col = 0;
             }
-            MoveDirection::Head => self.point.col = 0,
             MoveDirection::Tail => {
-                self.point.col = buf.get_col_length(self.get_cursor_coordinate_in_file().row)
+                self.cursor.col = buf.get_col_length(self.cursor.row);
+                self.want_col = usize::MAX;
             }
         }
+        self.scroll_to_cursor(buf);
         self.move_cursor_to_point(self.point);
     }
-    pub fn get_cursor_coordinate_in_file(&self) -> Point {
-        Point {
-            col: self.point.col + self.point_in_file.col,
-            row: self.point.row + self.point_in_file.row,
+    /// move the cursor `count` lines down (or up), keeping its column.
+    pub fn move_cursor_lines(&mut self, buf: &FileBuffer, down: bool, count: usize) {
+        let last = buf.get_row_length().saturating_sub(1);
+        let from = self.cursor.row;
+        self.cursor.row = if down {
+            self.cursor.row.saturating_add(count).min(last)
+        } else {
+            self.cursor.row.saturating_sub(count)
+        };
+        if self.cursor.row == from {
+            self.bell();
+        }
+        self.cursor.col = self.kept_col(buf, self.cursor.row);
+        self.scroll_to_cursor(buf);
+        self.move_cursor_to_point(self.point);
+    }
+    /// move the window a screen forward or back, keeping two lines of the old screen.
+    /// the cursor goes to the first (forward) or last (back) line of the new screen.
+    pub fn scroll_page(&mut self, buf: &FileBuffer, forward: bool) {
+        let height = self.text_height();
+        let step = height.saturating_sub(2).max(1);
+        let last = buf.get_row_length().saturating_sub(1);
+        let top = self.point_in_file.row;
+        if forward {
+            self.point_in_file.row = top.saturating_add(step).min(last);
+            self.cursor.row = self.point_in_file.row;
+        } else {
+            self.point_in_file.row = top.saturating_sub(step);
+            self.cursor.row = (self.point_in_file.row + height - 1).min(last);
+        }
+        self.cursor.col = self.kept_col(buf, self.cursor.row);
+        self.scroll_to_cursor(buf);
+        self.update_all(buf).unwrap();
+    }
+    /// the first and last line of the file shown in the window.
+    pub fn window_rows(&self, buf: &FileBuffer) -> (usize, usize) {
+        let top = self.point_in_file.row;
+        let last = buf.get_row_length().saturating_sub(1);
+        (top, (top + self.text_height() - 1).min(last.max(top)))
+    }
+    /// put the cursor on `row` at the column it keeps, and draw the window again.
+    fn redraw_at_row(&mut self, buf: &FileBuffer, row: usize) {
+        self.cursor.row = row;
+        self.cursor.col = self.kept_col(buf, row);
+        self.scroll_to_cursor(buf);
+        self.update_all(buf).unwrap();
+    }
+    /// `Ctrl-E` (`Ctrl-Y` when not `down`): move the window `count` lines. the cursor
+    /// stays on its line until the line goes out of the window.
+    pub fn scroll_lines(&mut self, buf: &FileBuffer, down: bool, count: usize) {
+        let last = buf.get_row_length().saturating_sub(1);
+        let top = self.point_in_file.row;
+        let new = match down {
+            true => top.saturating_add(count).min(last),
+            false => top.saturating_sub(count),
+        };
+        if new == top {
+            self.bell();
+            return;
         }
+        self.point_in_file.row = new;
+        let row = self.cursor.row.clamp(new, new + self.text_height() - 1);
+        self.redraw_at_row(buf, row.min(last));
     }
-    pub fn get_cursor_coordinate(&self) -> Point {
-        Point {
-            col: self.point.col,
-            row: self.point.row,
+    /// `Ctrl-D` (`Ctrl-U` when not `down`): move the window and the cursor `lines` down
+    /// (or up), half a screen when None.
+    pub fn scroll_half(&mut self, buf: &FileBuffer, down: bool, lines: Option<usize>) {
+        let height = self.text_height();
+        let n = lines.unwrap_or(height / 2).max(1);
+        let last = buf.get_row_length().saturating_sub(1);
+        let top = self.point_in_file.row;
+        if self.cursor.row == if down { last } else { 0 } {
+            self.bell();
+            return;
         }
+        let (top, row) = match down {
+            true => (
+                top.saturating_add(n)
+                    .min(last.saturating_sub(height - 1).max(top)),
+                self.cursor.row.saturating_add(n).min(last),
+            ),
+            false => (top.saturating_sub(n), self.cursor.row.saturating_sub(n)),
+        };
+        self.point_in_file.row = top;
+        self.redraw_at_row(buf, row);
+    }
+    /// `zt`, `zz` and `zb`: scroll so that the line of the cursor is at `place` in
+    /// the window.
+    pub fn place_cursor_line(&mut self, buf: &FileBuffer, place: Place) {
+        let height = self.text_height();
+        let row = self.cursor.row;
+        self.point_in_file.row = match place {
+            Place::Top => row,
+            Place::Middle => row.saturating_sub(height / 2),
+            Place::Bottom => (row + 1).saturating_sub(height),
+        };
+        self.scroll_to_cursor(buf);
+        self.update_all(buf).unwrap();
+    }
+    pub fn get_cursor_coordinate_in_file(&self) -> Point {
+        self.cursor
+    }
+    #[allow(dead_code)]
+    pub fn get_cursor_coordinate(&self) -> ScreenPoint {
+        self.point
     }
     pub fn set_cursor_type(&mut self, style: SetCursorStyle) {
         queue!(self.out, style).unwrap();
@@ -228,13 +468,145 @@ impl Display {
         disable_raw_mode().expect("");
         self.out.flush().unwrap();
     }
-    pub fn update_info_line(&mut self, msg: &String) {
-        let cursor_pos = self.point;
-        self.move_cursor_to_point(Point {
-            col: 0,
-            row: self.wsize.row,
-        });
-        println!("{}", msg);
-        self.move_cursor_to_point(cursor_pos);
+    /// draw the file name, its encoding and the cursor position.
+    pub fn draw_status_line(&mut self, buf: &FileBuffer) {
+        let left = format!(
+            " {}{}{}{} [{}{}][{}{}]",
+            buf.get_name(),
+            if buf.is_modified() { " [+]" } else { "" },
+            if buf.get_read_only() { " [RO]" } else { "" },
+            match (buf.is_large(), buf.large_progress()) {
+                (true, Some(p)) => format!(" [large {}%]", p),
+                (true, None) => String::from(" [large]"),
+                _ if buf.is_hex() => String::from(" [hex]"),
+                _ => String::new(),
+            },
+            encoding_name(buf.get_options().fileencoding),
+            if buf.get_options().bomb { ",bom" } else { "" },
+            buf.get_options().fileformat.name(),
+            if buf.has_mixed_line_endings() {
+                ",mixed"
+            } else {
+                ""
+            },
+        );
+        let right = format!(
+            "{}{},{} ",
+            if self.pending.is_empty() {
+                String::new()
+            } else {
+                format!("{}   ", self.pending)
+            },
+            self.cursor.row + 1,
+            self.cursor.col + 1
+        );
+        let width = self.wsize.col as usize;
+        let pad = width.saturating_sub(left.width() + right.width());
+        let line = visible_part(
+            &format!("{}{}{}", left, " ".repeat(pad), right),
+            1,
+            0,
+            width,
+        );
+        let row = self.text_height() as u16;
+        queue!(
+            self.out,
+            MoveTo(0, row),
+            SetAttribute(Attribute::Reverse),
+            Print(line),
+            SetAttribute(Attribute::Reset),
+            MoveTo(self.point.col, self.point.row)
+        )
+        .unwrap();
+        self.out.flush().unwrap();
+    }
+    fn draw_info_line(&mut self) {
+        let line = visible_part(&self.message, 1, 0, self.wsize.col as usize);
+        let row = self.text_height() as u16 + 1;
+        queue!(
+            self.out,
+            MoveTo(0, row),
+            Print(line),
+            Clear(ClearType::UntilNewLine),
+            MoveTo(self.point.col, self.point.row)
+        )
+        .unwrap();
+    }
+    /// show the keys of a command being typed, like `3d`.
+    pub fn set_pending_keys(&mut self, keys: &str) {
+        self.pending = String::from(keys);
+    }
+    /// a command could not be carried out.
+    pub fn bell(&mut self) {
+        self.error = true;
+    }
+    /// whether a command failed since the last call.
+    pub fn take_error(&mut self) -> bool {
+        std::mem::take(&mut self.error)
+    }
+    /// show a message on the last row until it is replaced. an error message rings
+    /// the bell.
+    pub fn update_info_line(&mut self, msg: &str) {
+        if msg.starts_with("[E") {
+            self.bell();
+        }
+        self.message = String::from(msg);
+        self.draw_info_line();
+        self.out.flush().unwrap();
+    }
+    /// show `msg` on the info line and wait until one of `choices` is typed.
+    pub fn ask(&mut self, msg: &str, choices: &str) -> char {
+        self.update_info_line(msg);
+        loop {
+            match event::read().unwrap() {
+                Event::Key(KeyEvent {
+                    code: KeyCode::Char(c),
+                    kind,
+                    ..
+                }) if kind != KeyEventKind::Release && choices.contains(c) => {
+                    self.update_info_line("");
+                    return c;
+                }
+                _ => (),
+            }
+        }
+    }
+    /// show `lines` over the window a screen at a time, for `:ls` and the like.
+    /// a key goes on to the next screen, `q` stops. the window is drawn again after.
+    pub fn show_lines(&mut self, buf: &FileBuffer, lines: &[String]) {
+        let height = (self.wsize.row as usize).saturating_sub(1).max(1);
+        let width = self.wsize.col as usize;
+        let pages = lines.len().div_ceil(height);
+        for (i, page) in lines.chunks(height).enumerate() {
+            queue!(self.out, Clear(ClearType::All), MoveTo(0, 0)).unwrap();
+            for line in page {
+                queue!(
+                    self.out,
+                    Print(visible_part(line, 1, 0, width)),
+                    Print("\r\n")
+                )
+                .unwrap();
+            }
+            let prompt = match i + 1 < pages {
+                true => "-- More --",
+                false => "Press ENTER to continue",
+            };
+            queue!(self.out, Print(visible_part(prompt, 1, 0, width).bold())).unwrap();
+            self.out.flush().unwrap();
+            if wait_key() == KeyCode::Char('q') {
+                break;
+            }
+        }
+        self.update_all(buf).unwrap();
+    }
+}
+/// the next key pressed.
+fn wait_key() -> KeyCode {
+    loop {
+        if let Event::Key(KeyEvent { code, kind, .. }) = event::read().unwrap() {
+            if kind != KeyEventKind::Release {
+                return code;
+            }
+        }
     }
 }
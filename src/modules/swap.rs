@@ -0,0 +1,247 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+
+// swap file format, one record per line:
+//   VE_SWAP <pid> <path>   header
+//   + <row> <col> <text>   text inserted at a point
+//   - <row> <col> <count>  chars removed from a point
+//   = <text>               the whole buffer replaced
+// text escapes `\`, LF and CR so that a record stays on one line.
+
+const HEADER: &str = "VE_SWAP";
+const EXTENSIONS: [&str; 4] = ["swp", "swo", "swn", "swm"];
+
+/// one edit in the journal.
+#[derive(Debug, PartialEq)]
+pub enum Record {
+    Insert(usize, usize, String),
+    Remove(usize, usize, usize),
+    Replace(String),
+}
+/// a swap file found when opening a file.
+#[derive(Debug)]
+pub struct SwapInfo {
+    pub path: PathBuf,
+    pub pid: u32,
+    /// the process that wrote the swap file still runs
+    pub running: bool,
+}
+/// journal of the unsaved edits of a buffer.
+/// the file is created on the first edit and removed once the buffer is saved.
+#[derive(Debug)]
+pub struct SwapFile {
+    path: PathBuf,
+    file_path: String,
+    file: Option<File>,
+}
+
+impl SwapFile {
+    /// journal for `file_path`, using the first swap name no other instance took.
+    pub fn new(file_path: &str) -> SwapFile {
+        let candidates = swap_paths(file_path);
+        let path = candidates
+            .iter()
+            .find(|p| !p.exists())
+            .unwrap_or(&candidates[candidates.len() - 1])
+            .clone();
+        SwapFile {
+            path,
+            file_path: String::from(file_path),
+            file: None,
+        }
+    }
+    /// continue the journal of a recovered swap file under this process.
+    pub fn take_over(info: &SwapInfo, file_path: &str) -> Result<SwapFile, String> {
+        let records = read_records(&info.path)?;
+        // the first record truncates the old file and writes our header.
+        let mut swap = SwapFile {
+            path: info.path.clone(),
+            file_path: String::from(file_path),
+            file: None,
+        };
+        for r in records {
+            swap.record(&r);
+        }
+        Ok(swap)
+    }
+    pub fn record(&mut self, record: &Record) {
+        let line = match record {
+            Record::Insert(row, col, text) => format!("+ {} {} {}\n", row, col, escape(text)),
+            Record::Remove(row, col, count) => format!("- {} {} {}\n", row, col, count),
+            Record::Replace(text) => format!("= {}\n", escape(text)),
+        };
+        if self.file.is_none() {
+            self.file = self.create().ok();
+        }
+        // a failing journal must not stop the editing itself.
+        if let Some(f) = &mut self.file {
+            let _ = f.write_all(line.as_bytes());
+        }
+    }
+    fn create(&self) -> std::io::Result<File> {
+        let mut f = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&self.path)?;
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            // the journal holds the text, so only the owner may read it.
+            f.set_permissions(fs::Permissions::from_mode(0o600))?;
+        }
+        f.write_all(format!("{} {} {}\n", HEADER, std::process::id(), self.file_path).as_bytes())?;
+        Ok(f)
+    }
+    /// forget the journal, the buffer matches the file on disk again.
+    pub fn remove(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+/// the swap file names tried for `file_path`: `dir/.name.swp`, `.swo`, ...
+fn swap_paths(file_path: &str) -> Vec<PathBuf> {
+    let path = Path::new(file_path);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    EXTENSIONS
+        .iter()
+        .map(|ext| dir.join(format!(".{}.{}", name, ext)))
+        .collect()
+}
+/// look for a swap file left by another instance or a crash.
+pub fn find_swap(file_path: &str) -> Option<SwapInfo> {
+    for path in swap_paths(file_path) {
+        let header = match fs::read_to_string(&path) {
+            Ok(text) => text.lines().next().map(String::from),
+            Err(_) => continue,
+        };
+        let pid = header
+            .as_deref()
+            .and_then(|h| h.strip_prefix(HEADER))
+            .and_then(|h| h.split_whitespace().next())
+            .and_then(|pid| pid.parse::<u32>().ok());
+        if let Some(pid) = pid {
+            let running = pid != std::process::id() && process_running(pid);
+            return Some(SwapInfo { path, pid, running });
+        }
+    }
+    None
+}
+fn process_running(pid: u32) -> bool {
+    if cfg!(target_os = "linux") {
+        Path::new(&format!("/proc/{}", pid)).exists()
+    } else {
+        // without procfs assume the worst, the user decides in the prompt.
+        true
+    }
+}
+/// read the edits journaled in a swap file.
+pub fn read_records(path: &Path) -> Result<Vec<Record>, String> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(e) => return Err(e.to_string()),
+    };
+    let mut lines = text.lines();
+    match lines.next() {
+        Some(h) if h.starts_with(HEADER) => (),
+        _ => return Err(format!("[E305] not a swap file: {}", path.display())),
+    }
+    let mut records = vec![];
+    for line in lines {
+        match parse_record(line) {
+            Some(r) => records.push(r),
+            // the last record may be cut by the crash. keep what came before it.
+            None => break,
+        }
+    }
+    Ok(records)
+}
+fn parse_record(line: &str) -> Option<Record> {
+    let (kind, rest) = line.split_once(' ')?;
+    match kind {
+        "+" | "-" => {
+            let mut fields = rest.splitn(3, ' ');
+            let row = fields.next()?.parse().ok()?;
+            let col = fields.next()?.parse().ok()?;
+            let last = fields.next()?;
+            if kind == "+" {
+                Some(Record::Insert(row, col, unescape(last)))
+            } else {
+                Some(Record::Remove(row, col, last.parse().ok()?))
+            }
+        }
+        "=" => Some(Record::Replace(unescape(rest))),
+        _ => None,
+    }
+}
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+fn unescape(text: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('r') => ret.push('\r'),
+            Some(c) => ret.push(c),
+            None => (),
+        }
+    }
+    ret
+}
+#[cfg(test)]
+mod swap_test {
+    use super::*;
+
+    #[test]
+    fn test_journal_round_trip() {
+        let file = std::env::temp_dir().join("ve_swap_test_journal.txt");
+        let file = file.to_str().unwrap();
+        for p in swap_paths(file) {
+            let _ = fs::remove_file(p);
+        }
+        let mut swap = SwapFile::new(file);
+        assert!(swap.path.ends_with(".ve_swap_test_journal.txt.swp"));
+        let records = [
+            Record::Insert(0, 3, String::from("a\\b\nc\r")),
+            Record::Remove(1, 0, 2),
+            Record::Replace(String::from("x y\n")),
+        ];
+        for r in &records {
+            swap.record(r);
+        }
+        let info = find_swap(file).unwrap();
+        assert_eq!(info.pid, std::process::id());
+        assert!(!info.running);
+        assert_eq!(read_records(&info.path).unwrap(), records);
+
+        // a second instance journals into the next name.
+        let other = SwapFile::new(file);
+        assert!(other.path.ends_with(".ve_swap_test_journal.txt.swo"));
+
+        swap.remove();
+        assert!(find_swap(file).is_none());
+    }
+    #[test]
+    fn test_cut_record() {
+        assert_eq!(
+            parse_record("+ 1 2 abc"),
+            Some(Record::Insert(1, 2, "abc".into()))
+        );
+        assert_eq!(parse_record("- 1 2"), None);
+        assert_eq!(parse_record("- 1 2 x"), None);
+    }
+}
@@ -0,0 +1,513 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::motion::{class, Parse};
+use crate::modules::operator::Range;
+
+// text objects select text around the cursor, like `iw` or `a(`, for an operator.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    /// `w`
+    Word,
+    /// `W`, a run of non-blank chars
+    BigWord,
+    /// `s`
+    Sentence,
+    /// `p`
+    Paragraph,
+    /// `"`, `'` or `` ` ``
+    Quote(char),
+    /// the open and close char: `(`, `{`, `[` or `<`
+    Bracket(char, char),
+    /// `t`, an XML/HTML tag pair
+    Tag,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextObject {
+    pub kind: ObjectKind,
+    /// `a`, with the blanks, quotes, brackets or tags around the text. `i` is without
+    pub around: bool,
+}
+/// lines searched on each side of the cursor for brackets, quotes and tags.
+const SPAN: usize = 2000;
+
+/// the lines around a row as one run of chars, the lines joined by '\n'.
+struct Flat {
+    chars: Vec<char>,
+    /// the first row of the text
+    first: usize,
+    /// index of the first char of each line
+    starts: Vec<usize>,
+}
+
+impl Flat {
+    fn new(buf: &FileBuffer, row: usize) -> Flat {
+        let first = row.saturating_sub(SPAN);
+        let mut flat = Flat {
+            chars: vec![],
+            first,
+            starts: vec![],
+        };
+        for (i, line) in buf.get_lines(first).take(2 * SPAN + 1).enumerate() {
+            if i > 0 {
+                flat.chars.push('\n');
+            }
+            flat.starts.push(flat.chars.len());
+            flat.chars.extend(line.chars());
+        }
+        flat
+    }
+    /// index after the last char of the `r`th line of the text.
+    fn line_end(&self, r: usize) -> usize {
+        match self.starts.get(r + 1) {
+            Some(next) => next - 1,
+            None => self.chars.len(),
+        }
+    }
+    fn index(&self, p: Point) -> usize {
+        let r = p.row - self.first;
+        (self.starts[r] + p.col).min(self.line_end(r))
+    }
+    fn point(&self, i: usize) -> Point {
+        let r = self.starts.partition_point(|s| *s <= i) - 1;
+        Point {
+            col: i - self.starts[r],
+            row: r + self.first,
+        }
+    }
+    fn get(&self, i: usize) -> Option<char> {
+        self.chars.get(i).copied()
+    }
+    /// the chars from `start` to before `end`.
+    fn range(&self, start: usize, end: usize) -> Range {
+        Range {
+            start: self.point(start),
+            end: self.point(end),
+            linewise: false,
+        }
+    }
+}
+fn is_blank(c: Option<char>) -> bool {
+    matches!(c, Some(' ') | Some('\t'))
+}
+
+impl TextObject {
+    /// the text object typed as `keys`, like `iw` or `a"`.
+    pub fn parse(keys: &[char]) -> Parse<TextObject> {
+        let (around, c) = match keys {
+            ['i'] | ['a'] => return Parse::Pending,
+            ['i', c] => (false, *c),
+            ['a', c] => (true, *c),
+            _ => return Parse::Invalid,
+        };
+        let kind = match c {
+            'w' => ObjectKind::Word,
+            'W' => ObjectKind::BigWord,
+            's' => ObjectKind::Sentence,
+            'p' => ObjectKind::Paragraph,
+            '"' | '\'' | '`' => ObjectKind::Quote(c),
+            '(' | ')' | 'b' => ObjectKind::Bracket('(', ')'),
+            '{' | '}' | 'B' => ObjectKind::Bracket('{', '}'),
+            '[' | ']' => ObjectKind::Bracket('[', ']'),
+            '<' | '>' => ObjectKind::Bracket('<', '>'),
+            't' => ObjectKind::Tag,
+            _ => return Parse::Invalid,
+        };
+        Parse::Done(TextObject { kind, around })
+    }
+    /// the text of the object at `cursor`. a count takes more words, sentences or
+    /// paragraphs, or brackets and tags further out. None when there is no such object.
+    pub fn select(self, buf: &FileBuffer, cursor: Point, count: usize) -> Option<Range> {
+        let count = count.max(1);
+        // past the end of the line the cursor is on the last char.
+        let cursor = Point {
+            col: cursor
+                .col
+                .min(buf.get_col_length(cursor.row).saturating_sub(1)),
+            row: cursor.row,
+        };
+        if self.kind == ObjectKind::Paragraph {
+            return Some(paragraph(buf, cursor.row, self.around, count));
+        }
+        let flat = Flat::new(buf, cursor.row);
+        let at = flat.index(cursor);
+        let (start, end) = match self.kind {
+            ObjectKind::Word => word(&flat, at, self.around, false, count)?,
+            ObjectKind::BigWord => word(&flat, at, self.around, true, count)?,
+            ObjectKind::Sentence => sentence(&flat, at, self.around, count)?,
+            ObjectKind::Quote(q) => quote(&flat, at, q, self.around)?,
+            ObjectKind::Bracket(open, close) => {
+                return bracket(&flat, at, open, close, self.around, count)
+            }
+            ObjectKind::Tag => tag(&flat, at, self.around, count)?,
+            ObjectKind::Paragraph => unreachable!(),
+        };
+        Some(flat.range(start, end))
+    }
+}
+/// a word, or the blanks, at `at` within its line. `aw` takes the blanks after the word,
+/// or before it when there are none after.
+fn word(flat: &Flat, at: usize, around: bool, big: bool, count: usize) -> Option<(usize, usize)> {
+    let cls = |i: usize| match flat.get(i) {
+        Some('\n') | None => None,
+        c if big => Some(class(c).min(1)),
+        c => Some(class(c)),
+    };
+    cls(at)?;
+    // the end of the run of chars of one class from `i`.
+    let run_end = |mut i: usize| {
+        let c = cls(i);
+        while c.is_some() && cls(i) == c {
+            i += 1;
+        }
+        i
+    };
+    let mut start = at;
+    while start > 0 && cls(start - 1) == cls(at) {
+        start -= 1;
+    }
+    let mut end = run_end(at);
+    let on_blank = cls(at) == Some(0);
+    for n in 0..count {
+        if n > 0 {
+            end = run_end(end);
+        }
+        if around {
+            if on_blank || cls(end) == Some(0) {
+                end = run_end(end);
+            } else if n == 0 {
+                while start > 0 && cls(start - 1) == Some(0) {
+                    start -= 1;
+                }
+            }
+        }
+    }
+    Some((start, end))
+}
+/// the sentence at `at`. sentences end with `.`, `!` or `?`, maybe followed by closing
+/// brackets or quotes, and then a blank or the end of a line. an empty line ends one too.
+fn sentence(flat: &Flat, at: usize, around: bool, count: usize) -> Option<(usize, usize)> {
+    let empty = |r: usize| flat.line_end(r) == flat.starts[r];
+    let row = flat.point(at).row - flat.first;
+    if empty(row) {
+        return None;
+    }
+    let white = |i: usize| matches!(flat.get(i), Some(' ') | Some('\t') | Some('\n') | None);
+    // the paragraph holding `at`.
+    let mut first = row;
+    while first > 0 && !empty(first - 1) {
+        first -= 1;
+    }
+    let mut last = row;
+    while last + 1 < flat.starts.len() && !empty(last + 1) {
+        last += 1;
+    }
+    let (lo, hi) = (flat.starts[first], flat.line_end(last));
+    // sentences as (start, end) with the blanks after each up to the next start.
+    let mut sentences = vec![];
+    let mut i = lo;
+    while i < hi && white(i) {
+        i += 1;
+    }
+    while i < hi {
+        let start = i;
+        let mut end = hi;
+        while i < hi {
+            if matches!(flat.get(i), Some('.') | Some('!') | Some('?')) {
+                let mut j = i + 1;
+                while matches!(flat.get(j), Some(')') | Some(']') | Some('"') | Some('\'')) {
+                    j += 1;
+                }
+                if j >= hi || white(j) {
+                    end = j;
+                    break;
+                }
+            }
+            i += 1;
+        }
+        i = end;
+        while i < hi && white(i) {
+            i += 1;
+        }
+        sentences.push((start, end));
+    }
+    let next_start = |k: usize| sentences.get(k + 1).map(|s| s.0).unwrap_or(hi);
+    let k = sentences.iter().rposition(|s| s.0 <= at).unwrap_or(0);
+    let (start, end) = sentences[k];
+    if at >= end {
+        // on the blanks after a sentence.
+        let white_end = next_start(k);
+        return match around {
+            false => Some((end, white_end)),
+            true => {
+                let last = (k + count).min(sentences.len() - 1);
+                Some((end, sentences[last].1))
+            }
+        };
+    }
+    let last = (k + count - 1).min(sentences.len() - 1);
+    let end = sentences[last].1;
+    if !around {
+        return Some((start, end));
+    }
+    let white_end = next_start(last);
+    if white_end > end {
+        return Some((start, white_end));
+    }
+    // the last sentence takes the blanks before it.
+    let mut start = start;
+    while start > lo && white(start - 1) {
+        start -= 1;
+    }
+    Some((start, end))
+}
+/// the lines of a paragraph, or the empty lines, at `row`. `ap` takes the empty lines
+/// after the paragraph, or before it when there are none after.
+fn paragraph(buf: &FileBuffer, row: usize, around: bool, count: usize) -> Range {
+    let last = buf.get_row_length().saturating_sub(1);
+    let empty = |r: usize| buf.get_col_length(r) == 0;
+    let mut start = row;
+    while start > 0 && empty(start - 1) == empty(row) {
+        start -= 1;
+    }
+    let runs = if around { 2 * count } else { count };
+    let mut end = row;
+    let mut taken = 0;
+    while taken < runs {
+        while end < last && empty(end + 1) == empty(end) {
+            end += 1;
+        }
+        taken += 1;
+        if taken == runs || end == last {
+            break;
+        }
+        end += 1;
+    }
+    if around && taken < runs && !empty(row) {
+        while start > 0 && empty(start - 1) {
+            start -= 1;
+        }
+    }
+    Range::lines(start, end)
+}
+/// the quoted text at `at` within its line. a quote after a backslash does not count.
+/// `a"` takes the quotes and the blanks after them, or before them when none are after.
+fn quote(flat: &Flat, at: usize, q: char, around: bool) -> Option<(usize, usize)> {
+    let line_start = flat.chars[..at]
+        .iter()
+        .rposition(|c| *c == '\n')
+        .map_or(0, |i| i + 1);
+    let line_end = flat.chars[at..]
+        .iter()
+        .position(|c| *c == '\n')
+        .map_or(flat.chars.len(), |i| at + i);
+    let mut quotes = vec![];
+    let mut i = line_start;
+    while i < line_end {
+        match flat.get(i) {
+            Some('\\') => i += 1,
+            Some(c) if c == q => quotes.push(i),
+            _ => (),
+        }
+        i += 1;
+    }
+    let pair = match quotes.iter().position(|i| *i >= at) {
+        // on a quote, it opens or closes by how many come before it.
+        Some(k) if quotes[k] == at => {
+            if k % 2 == 0 {
+                k
+            } else {
+                k - 1
+            }
+        }
+        Some(k) if k % 2 == 1 => k - 1,
+        // before the first quote, or between two pairs: the next pair.
+        Some(k) => k,
+        None => return None,
+    };
+    let (open, close) = (quotes[pair], *quotes.get(pair + 1)?);
+    if !around {
+        return Some((open + 1, close));
+    }
+    let mut end = close + 1;
+    while end < line_end && is_blank(flat.get(end)) {
+        end += 1;
+    }
+    let mut start = open;
+    if end == close + 1 {
+        while start > line_start && is_blank(flat.get(start - 1)) {
+            start -= 1;
+        }
+    }
+    Some((start, end))
+}
+/// the text inside the brackets around `at`, `count` pairs out. when the open bracket
+/// ends its line and the close bracket starts its line, `i(` takes the lines between.
+fn bracket(
+    flat: &Flat,
+    at: usize,
+    open: char,
+    close: char,
+    around: bool,
+    count: usize,
+) -> Option<Range> {
+    let mut from = match flat.get(at) {
+        Some(c) if c == open => at + 1,
+        Some(c) if c == close => at,
+        _ => at,
+    };
+    let mut start = 0;
+    for _ in 0..count {
+        // the open bracket before `from` that is not closed before it.
+        let mut depth = 0;
+        let mut i = from;
+        start = loop {
+            if i == 0 {
+                return None;
+            }
+            i -= 1;
+            match flat.get(i) {
+                Some(c) if c == close => depth += 1,
+                Some(c) if c == open && depth == 0 => break i,
+                Some(c) if c == open => depth -= 1,
+                _ => (),
+            }
+        };
+        from = start;
+    }
+    let mut depth = 0;
+    let mut end = start + 1;
+    loop {
+        match flat.get(end) {
+            None => return None,
+            Some(c) if c == open => depth += 1,
+            Some(c) if c == close && depth == 0 => break,
+            Some(c) if c == close => depth -= 1,
+            _ => (),
+        }
+        end += 1;
+    }
+    if around {
+        return Some(flat.range(start, end + 1));
+    }
+    let (first, last) = (flat.point(start), flat.point(end));
+    let line_end = flat.get(start + 1) == Some('\n');
+    let line_start = (flat.starts[last.row - flat.first]..end).all(|i| is_blank(flat.get(i)));
+    if line_end && line_start && last.row > first.row + 1 {
+        return Some(Range::lines(first.row + 1, last.row - 1));
+    }
+    Some(flat.range(start + 1, end))
+}
+/// the text inside the tag pair around `at`, `count` pairs out. `at` takes the tags too.
+fn tag(flat: &Flat, at: usize, around: bool, count: usize) -> Option<(usize, usize)> {
+    // (name, start, end) of open tags not closed yet, and the pairs found as
+    // (open start, open end, close start, close end).
+    let mut opened: Vec<(String, usize, usize)> = vec![];
+    let mut pairs = vec![];
+    let mut i = 0;
+    while i < flat.chars.len() {
+        if flat.get(i) != Some('<') {
+            i += 1;
+            continue;
+        }
+        let end = match flat.chars[i..].iter().position(|c| *c == '>') {
+            Some(n) => i + n + 1,
+            None => break,
+        };
+        let closing = flat.get(i + 1) == Some('/');
+        let name: String = flat.chars[i + 1 + closing as usize..end]
+            .iter()
+            .take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | ':' | '.'))
+            .collect();
+        let self_closing = flat.get(end - 2) == Some('/');
+        // comments and tags like `<br/>` have no pair.
+        if !name.is_empty() && !self_closing {
+            if !closing {
+                opened.push((name, i, end));
+            } else if let Some(k) = opened.iter().rposition(|o| o.0 == name) {
+                let (_, start, open_end) = opened[k];
+                opened.truncate(k);
+                pairs.push((start, open_end, i, end));
+            }
+        }
+        i = end;
+    }
+    let mut around_at: Vec<_> = pairs.iter().filter(|p| p.0 <= at && at < p.3).collect();
+    // the innermost pair first.
+    around_at.sort_by_key(|p| std::cmp::Reverse(p.0));
+    let &&(start, open_end, close_start, end) = around_at.get(count - 1)?;
+    if around {
+        Some((start, end))
+    } else {
+        Some((open_end, close_start))
+    }
+}
+#[cfg(test)]
+mod textobject_test {
+    use super::*;
+
+    /// the text selected by `keys` with the cursor at (col, row).
+    fn select(buf: &FileBuffer, keys: &str, col: usize, row: usize, count: usize) -> String {
+        let keys: Vec<char> = keys.chars().collect();
+        let object = match TextObject::parse(&keys) {
+            Parse::Done(o) => o,
+            _ => panic!("{:?}", keys),
+        };
+        match object.select(buf, Point { col, row }, count) {
+            Some(r) if r.linewise => format!("lines {}-{}", r.start.row, r.end.row),
+            Some(r) => buf.get_text(r.start, r.end),
+            None => String::from("none"),
+        }
+    }
+    #[test]
+    fn test_words() {
+        let buf = FileBuffer::from_text("words.txt", "foo.bar  baz qux\n");
+        assert_eq!(select(&buf, "iw", 1, 0, 1), "foo");
+        assert_eq!(select(&buf, "aw", 4, 0, 1), "bar  ");
+        assert_eq!(select(&buf, "iW", 1, 0, 1), "foo.bar");
+        assert_eq!(select(&buf, "aw", 7, 0, 1), "  baz");
+        assert_eq!(select(&buf, "aw", 14, 0, 1), " qux");
+        assert_eq!(select(&buf, "iw", 9, 0, 3), "baz qux");
+        assert_eq!(select(&buf, "aw", 0, 0, 2), "foo.");
+    }
+    #[test]
+    fn test_sentences() {
+        let buf = FileBuffer::from_text("sentences.txt", "One two.  Three\nfour! Five?\n\nSix.\n");
+        assert_eq!(select(&buf, "is", 2, 0, 1), "One two.");
+        assert_eq!(select(&buf, "as", 2, 0, 1), "One two.  ");
+        assert_eq!(select(&buf, "is", 12, 0, 1), "Three\nfour!");
+        assert_eq!(select(&buf, "as", 7, 1, 1), " Five?");
+        assert_eq!(select(&buf, "is", 0, 0, 2), "One two.  Three\nfour!");
+        assert_eq!(select(&buf, "is", 1, 3, 1), "Six.");
+    }
+    #[test]
+    fn test_paragraphs() {
+        let buf = FileBuffer::from_text("paragraphs.txt", "a\nb\n\n\nc\n\nd");
+        assert_eq!(select(&buf, "ip", 0, 1, 1), "lines 0-1");
+        assert_eq!(select(&buf, "ap", 0, 0, 1), "lines 0-3");
+        assert_eq!(select(&buf, "ap", 0, 2, 1), "lines 2-4");
+        assert_eq!(select(&buf, "ip", 0, 0, 3), "lines 0-4");
+        assert_eq!(select(&buf, "ap", 0, 6, 1), "lines 5-6");
+    }
+    #[test]
+    fn test_pairs() {
+        let buf = FileBuffer::from_text(
+            "pairs.txt",
+            "f(a, (b), \"c\\\"d\")  'x'\nif {\n    g[1];\n}\n<a><b>t</b> <i/></a>\n",
+        );
+        assert_eq!(select(&buf, "i(", 6, 0, 1), "b");
+        assert_eq!(select(&buf, "a(", 6, 0, 1), "(b)");
+        assert_eq!(select(&buf, "ib", 6, 0, 2), "a, (b), \"c\\\"d\"");
+        assert_eq!(select(&buf, "i(", 1, 0, 1), "a, (b), \"c\\\"d\"");
+        assert_eq!(select(&buf, "i\"", 12, 0, 1), "c\\\"d");
+        assert_eq!(select(&buf, "a'", 0, 0, 1), "  'x'");
+        assert_eq!(select(&buf, "i{", 0, 2, 1), "lines 2-2");
+        assert_eq!(select(&buf, "a{", 0, 2, 1), "{\n    g[1];\n}");
+        assert_eq!(select(&buf, "i[", 6, 2, 1), "1");
+        assert_eq!(select(&buf, "i{", 0, 0, 1), "none");
+        assert_eq!(select(&buf, "it", 6, 4, 1), "t");
+        assert_eq!(select(&buf, "at", 6, 4, 1), "<b>t</b>");
+        assert_eq!(select(&buf, "it", 6, 4, 2), "<b>t</b> <i/>");
+        assert_eq!(select(&buf, "it", 0, 0, 1), "none");
+    }
+}
@@ -1,10 +1,13 @@
+use crate::modules::coordinate::Point;
 use crate::modules::file::FileBuffer;
 use crate::modules::history::*;
-use crate::modules::insert::*;
 
 pub struct Undo {
     history: History,
+    /// the changes undone, the last one first. redone by `Ctrl-R`
     undo_history: History,
+    /// number of the change the records go to
+    change: usize,
 }
 
 impl Undo {
@@ -12,42 +15,96 @@ impl Undo {
         Undo {
             history: History::new(),
             undo_history: History::new(),
+            change: 0,
         }
     }
-    pub fn add_do_history(&mut self, op: Operation, target: Vec<char>, pos: [u32; 2]) {
-        self.history.add(op, target, pos);
+    /// the records from now on belong to a new change, undone by one `u`.
+    pub fn start_change(&mut self) {
+        self.change += 1;
     }
-    pub fn undo(&mut self, buf: &mut FileBuffer) -> [u32; 2] {
-        let record = self.history.undo();
-        match record.get_operation() {
-            Operation::HEAD => (),
-            Operation::ADD => {
-                let (result, delchar) = delback(
-                    record.get_pos()[0] as u16,
-                    record.get_pos()[1] as u16,
-                    buf.get_contents(),
-                );
-
-                buf.update_contents(result);
-                self.undo_history
-                    .add(Operation::DELETE, delchar, record.get_pos());
+    pub fn add_do_history(&mut self, op: Operation, target: Vec<char>, pos: Point) {
+        if target.is_empty() {
+            return;
+        }
+        self.history.add(op, target, pos, self.change);
+        // a new edit leaves nothing to redo.
+        self.undo_history.clear();
+    }
+    /// there is a change left to undo.
+    pub fn can_undo(&self) -> bool {
+        !self.history.is_empty()
+    }
+    /// undo the last change. returns where it started.
+    pub fn undo(&mut self, buf: &mut FileBuffer) -> Point {
+        let mut record = self.history.undo();
+        loop {
+            undo_record(buf, &record);
+            keep(&mut self.undo_history, &record);
+            if self.history.last_change() != Some(record.get_change()) {
+                return record.get_pos();
             }
-            Operation::DELETE => {
-                buf.update_contents(insert(
-                    record.get_pos()[0] as u16,
-                    record.get_pos()[1] as u16,
-                    buf.get_contents(),
-                    record.get_target()[0],
-                ));
-                self.undo_history.add(
-                    Operation::DELETE,
-                    vec![record.get_target()[0]],
-                    record.get_pos(),
-                );
+            record = self.history.undo();
+        }
+    }
+    /// there is an undone change left to redo.
+    pub fn can_redo(&self) -> bool {
+        !self.undo_history.is_empty()
+    }
+    /// redo the last change undone. returns where it started.
+    pub fn redo(&mut self, buf: &mut FileBuffer) -> Point {
+        let mut record = self.undo_history.undo();
+        let start = record.get_pos();
+        loop {
+            redo_record(buf, &record);
+            keep(&mut self.history, &record);
+            if self.undo_history.last_change() != Some(record.get_change()) {
+                return start;
             }
-            _ => (),
-        };
-        record.get_pos()
+            record = self.undo_history.undo();
+        }
+    }
+}
+/// move a record over to the other history.
+fn keep(history: &mut History, record: &HistoryRecord) {
+    history.add(
+        record.get_operation(),
+        record.get_target().clone(),
+        record.get_pos(),
+        record.get_change(),
+    );
+}
+/// take back one record of a change.
+fn undo_record(buf: &mut FileBuffer, record: &HistoryRecord) {
+    match record.get_operation() {
+        Operation::HEAD => (),
+        Operation::ADD => {
+            buf.remove_chars(
+                record.get_pos().col,
+                record.get_pos().row,
+                record.get_target().len(),
+            );
+        }
+        Operation::DELETE => {
+            let text: String = record.get_target().iter().collect();
+            buf.insert_str(record.get_pos().col, record.get_pos().row, &text);
+        }
+        _ => (),
+    }
+}
+/// make one record of a change again.
+fn redo_record(buf: &mut FileBuffer, record: &HistoryRecord) {
+    match record.get_operation() {
+        Operation::ADD => {
+            let text: String = record.get_target().iter().collect();
+            buf.insert_str(record.get_pos().col, record.get_pos().row, &text);
+        }
+        Operation::DELETE => {
+            buf.remove_chars(
+                record.get_pos().col,
+                record.get_pos().row,
+                record.get_target().len(),
+            );
+        }
+        _ => (),
     }
 }
-
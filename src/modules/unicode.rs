@@ -0,0 +1,162 @@
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
+
+// conversions between the column units of a line.
+// - byte: offset in the utf-8 string
+// - char: index of a unicode scalar value (the unit of FileBuffer positions)
+// - display: terminal cell, wide chars take 2 cells and tabs expand to `tabstop`
+// the cursor always sits on a grapheme cluster boundary.
+
+/// char index of the byte offset `byte`.
+pub fn byte_to_char(line: &str, byte: usize) -> usize {
+    line[..byte.min(line.len())].chars().count()
+}
+/// control chars other than tab are drawn in caret notation, like `^M` for CR.
+fn caret(grapheme: &str) -> Option<String> {
+    let mut chars = grapheme.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c != '\t' && (c < ' ' || c == '\x7f') => {
+            Some(format!("^{}", ((c as u8) ^ 0x40) as char))
+        }
+        _ => None,
+    }
+}
+/// cells taken by a grapheme starting on display column `col`.
+fn grapheme_width(grapheme: &str, col: usize, tabstop: usize) -> usize {
+    if grapheme == "\t" {
+        tabstop - col % tabstop
+    } else if caret(grapheme).is_some() {
+        2
+    } else {
+        grapheme.width()
+    }
+}
+/// iterate graphemes as (char index, display column, width, grapheme).
+fn graphemes(line: &str, tabstop: usize) -> impl Iterator<Item = (usize, usize, usize, &str)> {
+    let tabstop = tabstop.max(1);
+    let mut char_idx = 0;
+    let mut col = 0;
+    line.graphemes(true).map(move |g| {
+        let width = grapheme_width(g, col, tabstop);
+        let item = (char_idx, col, width, g);
+        char_idx += g.chars().count();
+        col += width;
+        item
+    })
+}
+/// char index of the grapheme boundary after `char_idx`.
+pub fn next_grapheme(line: &str, char_idx: usize) -> usize {
+    for (idx, _, _, g) in graphemes(line, 1) {
+        let end = idx + g.chars().count();
+        if end > char_idx {
+            return end;
+        }
+    }
+    char_idx
+}
+/// char index of the grapheme boundary before `char_idx`.
+pub fn prev_grapheme(line: &str, char_idx: usize) -> usize {
+    let mut prev = 0;
+    for (idx, _, _, _) in graphemes(line, 1) {
+        if idx >= char_idx {
+            break;
+        }
+        prev = idx;
+    }
+    prev
+}
+/// display column where the char at `char_idx` is drawn.
+pub fn char_to_display_col(line: &str, char_idx: usize, tabstop: usize) -> usize {
+    let mut end = 0;
+    for (idx, col, width, _) in graphemes(line, tabstop) {
+        if idx >= char_idx {
+            return col;
+        }
+        end = col + width;
+    }
+    end
+}
+/// char index of the grapheme covering display column `col`.
+/// columns past the end of the line map to the end of the line.
+pub fn display_col_to_char(line: &str, col: usize, tabstop: usize) -> usize {
+    let mut end = 0;
+    for (idx, start, width, g) in graphemes(line, tabstop) {
+        if col < start + width {
+            return idx;
+        }
+        end = idx + g.chars().count();
+    }
+    end
+}
+/// the part of a line drawn between display columns `left` and `left + width`.
+/// tabs are expanded and wide chars cut by an edge are replaced by spaces.
+pub fn visible_part(line: &str, tabstop: usize, left: usize, width: usize) -> String {
+    let right = left + width;
+    let mut ret = String::new();
+    for (_, start, w, g) in graphemes(line, tabstop) {
+        if start >= right {
+            break;
+        }
+        if start + w <= left {
+            continue;
+        }
+        if g == "\t" || start < left || start + w > right {
+            let from = start.max(left);
+            let to = (start + w).min(right);
+            ret.push_str(&" ".repeat(to - from));
+        } else if let Some(c) = caret(g) {
+            ret.push_str(&c);
+        } else {
+            ret.push_str(g);
+        }
+    }
+    ret
+}
+#[cfg(test)]
+mod unicode_test {
+    use super::*;
+
+    #[test]
+    fn test_byte_to_char() {
+        let line = "aあé";
+        assert_eq!(byte_to_char(line, 0), 0);
+        assert_eq!(byte_to_char(line, 1), 1);
+        assert_eq!(byte_to_char(line, 4), 2);
+        assert_eq!(byte_to_char(line, 6), 3);
+        assert_eq!(byte_to_char(line, 9), 3);
+    }
+    #[test]
+    fn test_grapheme_move() {
+        // "e" + combining acute accent is one grapheme of two chars.
+        let line = "ae\u{301}b👍";
+        assert_eq!(next_grapheme(line, 0), 1);
+        assert_eq!(next_grapheme(line, 1), 3);
+        assert_eq!(next_grapheme(line, 3), 4);
+        assert_eq!(next_grapheme(line, 4), 5);
+        assert_eq!(next_grapheme(line, 5), 5);
+        assert_eq!(prev_grapheme(line, 5), 4);
+        assert_eq!(prev_grapheme(line, 3), 1);
+        assert_eq!(prev_grapheme(line, 0), 0);
+    }
+    #[test]
+    fn test_display_col() {
+        let line = "a\tあb";
+        assert_eq!(char_to_display_col(line, 1, 4), 1);
+        assert_eq!(char_to_display_col(line, 2, 4), 4);
+        assert_eq!(char_to_display_col(line, 3, 4), 6);
+        assert_eq!(char_to_display_col(line, 4, 4), 7);
+        assert_eq!(display_col_to_char(line, 2, 4), 1);
+        assert_eq!(display_col_to_char(line, 5, 4), 2);
+        assert_eq!(display_col_to_char(line, 6, 4), 3);
+        assert_eq!(display_col_to_char(line, 30, 4), 4);
+    }
+    #[test]
+    fn test_visible_part() {
+        let line = "a\tあbc";
+        assert_eq!(visible_part(line, 4, 0, 80), "a   あbc");
+        assert_eq!(visible_part(line, 4, 5, 3), " bc");
+        assert_eq!(visible_part(line, 4, 0, 5), "a    ");
+        assert_eq!(visible_part("ab\r", 8, 0, 80), "ab^M");
+        assert_eq!(char_to_display_col("\x1bx", 1, 8), 2);
+    }
+}
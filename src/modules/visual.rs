@@ -0,0 +1,298 @@
+use crate::modules::coordinate::Point;
+use crate::modules::file::FileBuffer;
+use crate::modules::history::Operation;
+use crate::modules::motion::MotionKind;
+use crate::modules::operator::Range;
+use crate::modules::undo::Undo;
+use crate::modules::unicode::{char_to_display_col, display_col_to_char, next_grapheme};
+
+// visual mode selects the text between the cursor and the place it started,
+// for an operator to work on.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VisualKind {
+    /// `v`, the chars from one end to the other
+    Char,
+    /// `V`, whole lines
+    Line,
+    /// `Ctrl-V`, the same columns of every line
+    Block,
+}
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Selection {
+    pub kind: VisualKind,
+    /// the end that stays where visual mode started
+    pub anchor: Point,
+    /// the end that moves with the cursor
+    pub cursor: Point,
+    /// a block goes on to the end of every line, after `$`
+    pub to_end: bool,
+}
+/// text typed at the top of a block with `I`, `A` or `c`, put on the other lines
+/// when the insert ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockInsert {
+    /// the lines below the top that get the text
+    pub rows: Vec<usize>,
+    /// display column the text goes at, the end of each line when None
+    pub col: Option<usize>,
+}
+
+impl VisualKind {
+    /// the key that starts visual mode of this kind.
+    pub fn key(self) -> char {
+        match self {
+            VisualKind::Char => 'v',
+            VisualKind::Line => 'V',
+            VisualKind::Block => '\x16',
+        }
+    }
+    pub fn from_key(c: char) -> Option<VisualKind> {
+        match c {
+            'v' => Some(VisualKind::Char),
+            'V' => Some(VisualKind::Line),
+            '\x16' => Some(VisualKind::Block),
+            _ => None,
+        }
+    }
+    /// the message shown while selecting.
+    pub fn name(self) -> &'static str {
+        match self {
+            VisualKind::Char => "-- VISUAL --",
+            VisualKind::Line => "-- VISUAL LINE --",
+            VisualKind::Block => "-- VISUAL BLOCK --",
+        }
+    }
+}
+impl Selection {
+    pub fn new(kind: VisualKind, at: Point) -> Selection {
+        Selection {
+            kind,
+            anchor: at,
+            cursor: at,
+            to_end: false,
+        }
+    }
+    /// the end that comes first in the file.
+    pub fn start(&self) -> Point {
+        if (self.cursor.row, self.cursor.col) < (self.anchor.row, self.anchor.col) {
+            self.cursor
+        } else {
+            self.anchor
+        }
+    }
+    pub fn end(&self) -> Point {
+        if (self.cursor.row, self.cursor.col) < (self.anchor.row, self.anchor.col) {
+            self.anchor
+        } else {
+            self.cursor
+        }
+    }
+    /// the text selected by `v` or `V`. past the end of a line `v` takes the line break.
+    pub fn range(&self, buf: &FileBuffer) -> Range {
+        let (start, end) = (self.start(), self.end());
+        if self.kind != VisualKind::Char {
+            return Range::lines(start.row, end.row);
+        }
+        if end.col >= buf.get_col_length(end.row) && end.row + 1 < buf.get_row_length() {
+            let next = Point {
+                col: 0,
+                row: end.row + 1,
+            };
+            return Range {
+                start,
+                end: next,
+                linewise: false,
+            };
+        }
+        Range::new(buf, start, end, MotionKind::Inclusive)
+    }
+    /// display columns of the block, the right one past its last cell.
+    pub fn block_columns(&self, buf: &FileBuffer) -> (usize, usize) {
+        let tabstop = buf.get_options().tabstop;
+        let cells = |p: Point| {
+            let line = buf.get_line(p.row);
+            let left = char_to_display_col(&line, p.col, tabstop);
+            let right = char_to_display_col(&line, next_grapheme(&line, p.col), tabstop);
+            (left, right.max(left + 1))
+        };
+        let (a, b) = (cells(self.anchor), cells(self.cursor));
+        (a.0.min(b.0), a.1.max(b.1))
+    }
+    /// the part of each line within the block, from the top line down.
+    pub fn block_ranges(&self, buf: &FileBuffer) -> Vec<Range> {
+        let tabstop = buf.get_options().tabstop;
+        let (left, right) = self.block_columns(buf);
+        (self.start().row..=self.end().row)
+            .map(|row| {
+                let line = buf.get_line(row);
+                let from = display_col_to_char(&line, left, tabstop);
+                let to = if self.to_end {
+                    line.chars().count()
+                } else {
+                    display_col_to_char(&line, right, tabstop)
+                };
+                Range {
+                    start: Point { col: from, row },
+                    end: Point {
+                        col: to.max(from),
+                        row,
+                    },
+                    linewise: false,
+                }
+            })
+            .collect()
+    }
+    /// display columns of `row` to draw as selected, the right one past the last cell.
+    /// the line break of a line takes a cell of its own.
+    pub fn columns(&self, buf: &FileBuffer, row: usize) -> Option<(usize, usize)> {
+        let (start, end) = (self.start(), self.end());
+        if row < start.row || row > end.row {
+            return None;
+        }
+        let tabstop = buf.get_options().tabstop;
+        let line = buf.get_line(row);
+        let col = |c: usize| char_to_display_col(&line, c, tabstop);
+        let width = col(line.chars().count());
+        match self.kind {
+            VisualKind::Line => Some((0, width.max(1))),
+            VisualKind::Char => {
+                let from = if row == start.row { col(start.col) } else { 0 };
+                let to = if row == end.row {
+                    col(next_grapheme(&line, end.col)).max(from + 1)
+                } else {
+                    width + 1
+                };
+                Some((from, to))
+            }
+            VisualKind::Block => {
+                let (left, right) = self.block_columns(buf);
+                match self.to_end {
+                    true => Some((left, width.max(left))),
+                    false => Some((left, right)),
+                }
+            }
+        }
+    }
+    /// the keys that select as much text again from the cursor, for `.`.
+    pub fn keys(&self) -> String {
+        let (start, end) = (self.start(), self.end());
+        let rows = end.row - start.row;
+        let mut keys = String::from(self.kind.key());
+        if rows > 0 {
+            keys.push_str(&format!("{}j", rows));
+        }
+        let cols = match self.kind {
+            VisualKind::Line => 0,
+            VisualKind::Char if rows > 0 => {
+                keys.push('0');
+                end.col
+            }
+            VisualKind::Char => end.col - start.col,
+            VisualKind::Block if self.to_end => {
+                keys.push('$');
+                0
+            }
+            VisualKind::Block => self.anchor.col.abs_diff(self.cursor.col),
+        };
+        if cols > 0 {
+            keys.push_str(&format!("{}l", cols));
+        }
+        keys
+    }
+}
+impl BlockInsert {
+    /// put `text` on the lines of the block. lines that end before the column are
+    /// filled up with blanks.
+    pub fn insert(&self, buf: &mut FileBuffer, undo: &mut Undo, text: &str) {
+        if text.is_empty() || text.contains('\n') {
+            return;
+        }
+        for &row in &self.rows {
+            if row >= buf.get_row_length() {
+                break;
+            }
+            let col = column_at(buf, undo, row, self.col);
+            buf.insert_str(col, row, text);
+            undo.add_do_history(Operation::ADD, text.chars().collect(), Point { col, row });
+        }
+    }
+}
+/// the char column at display column `col` of `row`, the end of the line when None.
+/// a line that ends before the column is filled up with blanks.
+pub fn column_at(buf: &mut FileBuffer, undo: &mut Undo, row: usize, col: Option<usize>) -> usize {
+    let tabstop = buf.get_options().tabstop;
+    let line = buf.get_line(row);
+    let len = line.chars().count();
+    let width = char_to_display_col(&line, len, tabstop);
+    match col {
+        None => len,
+        Some(c) if width < c => {
+            let fill = " ".repeat(c - width);
+            buf.insert_str(len, row, &fill);
+            undo.add_do_history(
+                Operation::ADD,
+                fill.chars().collect(),
+                Point { col: len, row },
+            );
+            len + c - width
+        }
+        Some(c) => display_col_to_char(&line, c, tabstop),
+    }
+}
+#[cfg(test)]
+mod visual_test {
+    use super::*;
+
+    fn p(col: usize, row: usize) -> Point {
+        Point { col, row }
+    }
+    #[test]
+    fn test_selection() {
+        let buf = FileBuffer::from_text("selection.txt", "abcdef\nab\nabcdef\n");
+        let mut sel = Selection::new(VisualKind::Char, p(4, 2));
+        sel.cursor = p(1, 0);
+        let range = sel.range(&buf);
+        assert_eq!(buf.get_text(range.start, range.end), "bcdef\nab\nabcde");
+        assert_eq!(sel.columns(&buf, 1), Some((0, 3)));
+        assert_eq!(sel.keys(), "v2j04l");
+        sel.kind = VisualKind::Line;
+        assert_eq!(sel.range(&buf), Range::lines(0, 2));
+        sel.kind = VisualKind::Block;
+        assert_eq!(sel.block_columns(&buf), (1, 5));
+        let texts: Vec<String> = sel
+            .block_ranges(&buf)
+            .iter()
+            .map(|r| buf.get_text(r.start, r.end))
+            .collect();
+        assert_eq!(texts, ["bcde", "b", "bcde"]);
+        assert_eq!(sel.keys(), "\x162j3l");
+        sel.to_end = true;
+        assert_eq!(sel.columns(&buf, 1), Some((1, 2)));
+        // the line break is taken past the end of a line.
+        let mut sel = Selection::new(VisualKind::Char, p(1, 1));
+        sel.cursor = p(2, 1);
+        let range = sel.range(&buf);
+        assert_eq!(buf.get_text(range.start, range.end), "b\n");
+    }
+    #[test]
+    fn test_block_insert() {
+        let mut buf = FileBuffer::from_text("block_insert.txt", "abc\na\n\tb\n");
+        let mut undo = Undo::new();
+        undo.start_change();
+        let block = BlockInsert {
+            rows: vec![1, 2],
+            col: Some(2),
+        };
+        block.insert(&mut buf, &mut undo, "xy");
+        assert_eq!(buf.get_contents(), "abc\na xy\nxy\tb\n");
+        let block = BlockInsert {
+            rows: vec![0, 1],
+            col: None,
+        };
+        block.insert(&mut buf, &mut undo, ";");
+        assert_eq!(buf.get_contents(), "abc;\na xy;\nxy\tb\n");
+        undo.undo(&mut buf);
+        assert_eq!(buf.get_contents(), "abc\na\n\tb\n");
+    }
+}
@@ -0,0 +1,189 @@
+#[cfg(target_os = "linux")]
+use inotify::{Inotify, WatchMask};
+use std::collections::hash_map::DefaultHasher;
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+#[cfg(not(target_os = "linux"))]
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+/// the file on disk as the buffer last read or wrote it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskState {
+    mtime: Option<SystemTime>,
+    size: u64,
+    /// None for files too large to read at once, only mtime and size count then
+    hash: Option<u64>,
+}
+/// how the file on disk differs from the one the buffer knows.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum DiskChange {
+    Unchanged,
+    Modified,
+    Deleted,
+}
+
+impl DiskState {
+    /// the state of the file at `path` whose contents are `bytes`.
+    pub fn new(path: &Path, bytes: &[u8]) -> Option<DiskState> {
+        let meta = fs::metadata(path).ok()?;
+        Some(DiskState {
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+            hash: Some(hash(bytes)),
+        })
+    }
+    /// the state of a file from its metadata alone.
+    pub fn stat(path: &Path) -> Option<DiskState> {
+        let meta = fs::metadata(path).ok()?;
+        Some(DiskState {
+            mtime: meta.modified().ok(),
+            size: meta.len(),
+            hash: None,
+        })
+    }
+    pub fn read(path: &Path) -> Option<DiskState> {
+        let bytes = fs::read(path).ok()?;
+        DiskState::new(path, &bytes)
+    }
+    /// compare with the file now at `path`.
+    /// the contents are only read when the mtime or size moved, so a `touch` is no change.
+    /// returns the change and the current state.
+    pub fn compare(&self, path: &Path) -> (DiskChange, Option<DiskState>) {
+        let meta = match fs::metadata(path) {
+            Ok(m) => m,
+            Err(_) => return (DiskChange::Deleted, None),
+        };
+        if meta.modified().ok() == self.mtime && meta.len() == self.size {
+            return (DiskChange::Unchanged, Some(self.clone()));
+        }
+        if self.hash.is_none() {
+            return (DiskChange::Modified, DiskState::stat(path));
+        }
+        match DiskState::read(path) {
+            Some(now) if now.hash == self.hash && now.size == self.size => {
+                (DiskChange::Unchanged, Some(now))
+            }
+            Some(now) => (DiskChange::Modified, Some(now)),
+            None => (DiskChange::Deleted, None),
+        }
+    }
+}
+fn hash(bytes: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    bytes.hash(&mut hasher);
+    hasher.finish()
+}
+/// inotify watch on the directory of a file.
+/// the directory is watched since tools like git replace the file by a rename.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+pub struct Watcher {
+    inotify: Inotify,
+    name: OsString,
+}
+
+#[cfg(target_os = "linux")]
+impl Watcher {
+    pub fn new(path: &Path) -> Option<Watcher> {
+        let target = fs::canonicalize(path).ok()?;
+        let dir = target.parent()?;
+        let name = target.file_name()?.to_os_string();
+        let inotify = Inotify::init().ok()?;
+        inotify
+            .watches()
+            .add(
+                dir,
+                WatchMask::CLOSE_WRITE
+                    | WatchMask::MOVED_TO
+                    | WatchMask::MOVED_FROM
+                    | WatchMask::DELETE,
+            )
+            .ok()?;
+        Some(Watcher { inotify, name })
+    }
+    /// whether the file was touched since the last poll. never blocks.
+    pub fn poll(&mut self) -> bool {
+        let mut buffer = [0; 4096];
+        let mut touched = false;
+        // read until the queue is empty (WouldBlock), so one write is reported once.
+        while let Ok(events) = self.inotify.read_events(&mut buffer) {
+            for e in events {
+                touched |= e.name == Some(self.name.as_os_str());
+            }
+        }
+        touched
+    }
+}
+/// without inotify the file is polled. a new mtime or size counts as touched.
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct Watcher {
+    path: PathBuf,
+    last: Option<(Option<SystemTime>, u64)>,
+}
+
+#[cfg(not(target_os = "linux"))]
+impl Watcher {
+    pub fn new(path: &Path) -> Option<Watcher> {
+        Some(Watcher {
+            path: path.to_path_buf(),
+            last: stat(path),
+        })
+    }
+    /// whether the file was touched since the last poll. never blocks.
+    pub fn poll(&mut self) -> bool {
+        let now = stat(&self.path);
+        let touched = now != self.last;
+        self.last = now;
+        touched
+    }
+}
+#[cfg(not(target_os = "linux"))]
+fn stat(path: &Path) -> Option<(Option<SystemTime>, u64)> {
+    fs::metadata(path)
+        .ok()
+        .map(|m| (m.modified().ok(), m.len()))
+}
+#[cfg(test)]
+mod watch_test {
+    use super::*;
+
+    #[test]
+    fn test_compare() {
+        let p = std::env::temp_dir().join("ve_watch_test_compare.txt");
+        fs::write(&p, "abc\n").unwrap();
+        let state = DiskState::read(&p).unwrap();
+        assert_eq!(state.compare(&p).0, DiskChange::Unchanged);
+        // same contents with a new mtime.
+        fs::write(&p, "abc\n").unwrap();
+        assert_eq!(state.compare(&p).0, DiskChange::Unchanged);
+        fs::write(&p, "abcd\n").unwrap();
+        let (change, now) = state.compare(&p);
+        assert_eq!(change, DiskChange::Modified);
+        assert_eq!(now.unwrap().compare(&p).0, DiskChange::Unchanged);
+        fs::remove_file(&p).unwrap();
+        assert_eq!(state.compare(&p).0, DiskChange::Deleted);
+    }
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_watch() {
+        let dir = std::env::temp_dir().join("ve_watch_test_watch");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir(&dir).unwrap();
+        let p = dir.join("a.txt");
+        fs::write(&p, "abc\n").unwrap();
+        let mut watcher = Watcher::new(&p).unwrap();
+        assert!(!watcher.poll());
+        fs::write(dir.join("b.txt"), "other\n").unwrap();
+        assert!(!watcher.poll());
+        // replaced by a rename, as git does.
+        fs::write(dir.join("a.tmp"), "new\n").unwrap();
+        fs::rename(dir.join("a.tmp"), &p).unwrap();
+        assert!(watcher.poll());
+        assert!(!watcher.poll());
+    }
+}